@@ -1,13 +1,84 @@
-use crate::x86::{instruction_table, operand::Operand, utils};
+use std::collections::HashMap;
+
+use crate::x86::{instruction_table, operand::Operand, peephole, utils};
+use crate::x86::immediate::Immediate;
+use crate::x86::instruction_table::{Kind, OpType};
+use crate::x86::memory::{Memory, Scale};
+use crate::x86::register::{GPReg32, Register};
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub struct Label(u32);
+
+#[allow(unused)]
+#[repr(u8)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Condition {
+    Overflow = 0x0,
+    NoOverflow = 0x1,
+    Below = 0x2,
+    AboveOrEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    BelowOrEqual = 0x6,
+    Above = 0x7,
+    Sign = 0x8,
+    NoSign = 0x9,
+    Less = 0xC,
+    GreaterOrEqual = 0xD,
+    LessOrEqual = 0xE,
+    Greater = 0xF,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RelocationKind {
+    Relative32, // target - (offset + 4)
+    Absolute32, // image base + target address
+}
+
+// A 32-bit field at `offset` which refers to `symbol`
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Relocation {
+    pub offset: usize,
+    pub kind: RelocationKind,
+    pub symbol: String,
+}
+
+// Instructions are recorded first and encoded in `finish`, so that the
+// peephole pass can rewrite them in between
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Instruction {
+    Op(Kind, Option<Operand>, Option<Operand>),
+    MovSymbolAddress(Operand, String),
+    CallSymbol(String),
+    CallImport(String),
+    Jmp(Label),
+    Jcc(Condition, Label),
+    JumpTable(GPReg32, Vec<Label>), // `jmp [index * 4 + table]` followed by the table
+    Label(Label),
+    Symbol(String),
+    Bytes(Vec<u8>),
+}
 
 pub struct Codegen {
-    buffer: Vec<u8>
+    instructions: Vec<Instruction>,
+    buffer: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    label_fixups: Vec<(usize, Label)>,
+    label_addresses: Vec<(usize, Label)>, // absolute addresses of labels in jump tables
+    symbols: HashMap<String, usize>,
+    relocations: Vec<Relocation>,
 }
 
 impl Codegen {
     pub fn new() -> Self {
         Self {
+            instructions: Vec::new(),
             buffer: Vec::new(),
+            labels: Vec::new(),
+            label_fixups: Vec::new(),
+            label_addresses: Vec::new(),
+            symbols: HashMap::new(),
+            relocations: Vec::new(),
         }
     }
 
@@ -15,26 +86,345 @@ impl Codegen {
         &self.buffer
     }
 
+    pub fn symbols(&self) -> &HashMap<String, usize> {
+        &self.symbols
+    }
+
+    pub fn relocations(&self) -> &[Relocation] {
+        &self.relocations
+    }
+
+    #[allow(unused)]
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
 /*     pub fn emit_byte(&mut self, byte: u8) {
         self.buffer.push(byte);
     } */
 
+    #[allow(unused)]
     pub fn emit_bytes(&mut self, bytes: &[u8]) {
-        self.buffer.extend_from_slice(bytes);
+        self.instructions.push(Instruction::Bytes(bytes.to_vec()));
+    }
+
+    pub fn define_symbol(&mut self, name: &str) {
+        self.instructions.push(Instruction::Symbol(name.to_string()));
+    }
+
+    pub fn create_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() as u32 - 1)
+    }
+
+    pub fn bind_label(&mut self, label: Label) {
+        self.instructions.push(Instruction::Label(label));
+    }
+
+    pub fn peephole(&mut self) {
+        peephole::optimize(&mut self.instructions);
+    }
+
+    // Encodes the recorded instructions, then patches label references and
+    // calls between symbols defined here. Everything else is left in
+    // `relocations` for the writer, with the absolute addresses of labels
+    // which become local `.L` symbols
+    pub fn finish(&mut self) {
+        for instruction in std::mem::take(&mut self.instructions) {
+            self.encode(instruction);
+        }
+
+        for (offset, label) in std::mem::take(&mut self.label_fixups) {
+            let target = self.labels[label.0 as usize].expect("unbound label");
+            self.patch_relative(offset, target);
+        }
+
+        for (offset, label) in std::mem::take(&mut self.label_addresses) {
+            let target = self.labels[label.0 as usize].expect("unbound label");
+            let symbol = format!(".Llabel{}", label.0);
+
+            self.symbols.insert(symbol.clone(), target);
+            self.relocations.push(Relocation { offset, kind: RelocationKind::Absolute32, symbol });
+        }
+
+        let mut unresolved = Vec::new();
+
+        for relocation in std::mem::take(&mut self.relocations) {
+            let target = self.symbols.get(&relocation.symbol).copied();
+
+            match target {
+                Some(target) if relocation.kind == RelocationKind::Relative32 => {
+                    self.patch_relative(relocation.offset, target);
+                },
+
+                _ => unresolved.push(relocation),
+            }
+        }
+
+        self.relocations = unresolved;
+    }
+
+    fn patch_relative(&mut self, offset: usize, target: usize) {
+        let value = target as i64 - (offset as i64 + 4);
+        self.buffer[offset..offset + 4].copy_from_slice(&(value as i32).to_le_bytes());
+    }
+
+    pub fn emit(&mut self, kind: Kind, operand1: Option<Operand>, operand2: Option<Operand>) {
+        if instruction_table::find_instruction(kind, operand1, operand2).is_none() {
+            panic!("invalid instruction format");
+        }
+
+        self.instructions.push(Instruction::Op(kind, operand1, operand2));
     }
 
     pub fn mov(&mut self, operand1: Operand, operand2: Operand) {
-        let candidate = instruction_table::query_instruction(
-            instruction_table::Kind::Mov,
-            Some(instruction_table::OpType::ModRm16_32),
-            Some(instruction_table::OpType::Reg16_32)
-        );
+        self.emit(Kind::Mov, Some(operand1), Some(operand2));
+    }
+
+    // `mov operand, imm32` where imm32 is the absolute address of `symbol`
+    pub fn mov_symbol_address(&mut self, operand: Operand, symbol: &str) {
+        self.instructions.push(Instruction::MovSymbolAddress(operand, symbol.to_string()));
+    }
+
+    pub fn call_symbol(&mut self, symbol: &str) {
+        self.instructions.push(Instruction::CallSymbol(symbol.to_string()));
+    }
+
+    // `call [slot]` where slot is the IAT entry named `symbol`
+    pub fn call_import(&mut self, symbol: &str) {
+        self.instructions.push(Instruction::CallImport(symbol.to_string()));
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.instructions.push(Instruction::Jmp(label));
+    }
 
-        if let Some(instr_info) = candidate {
-            self.emit_bytes(instr_info.op);
-            utils::emit_modrm_byte(operand1, operand2, &mut self.buffer);
-        } else {
-            panic!("invalid instruction format")
+    pub fn jcc(&mut self, condition: Condition, label: Label) {
+        self.instructions.push(Instruction::Jcc(condition, label));
+    }
+
+    // Jumps to `targets[index]`, the index must be in range
+    pub fn jump_table(&mut self, index: GPReg32, targets: Vec<Label>) {
+        self.instructions.push(Instruction::JumpTable(index, targets));
+    }
+
+    fn encode(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Op(kind, operand1, operand2) => self.encode_op(kind, operand1, operand2),
+
+            Instruction::MovSymbolAddress(operand, symbol) => {
+                self.encode_op(Kind::Mov, Some(operand), Some(Operand::Immediate(Immediate::U32(0))));
+                self.add_relocation(RelocationKind::Absolute32, &symbol);
+            },
+
+            Instruction::CallSymbol(symbol) => {
+                self.buffer.extend_from_slice(&[0xE8, 0, 0, 0, 0]);
+                self.add_relocation(RelocationKind::Relative32, &symbol);
+            },
+
+            Instruction::CallImport(symbol) => {
+                self.buffer.extend_from_slice(&[0xFF, 0x15, 0, 0, 0, 0]);
+                self.add_relocation(RelocationKind::Absolute32, &symbol);
+            },
+
+            Instruction::Jmp(label) => {
+                self.buffer.extend_from_slice(&[0xE9, 0, 0, 0, 0]);
+                self.label_fixups.push((self.buffer.len() - 4, label));
+            },
+
+            Instruction::Jcc(condition, label) => {
+                self.buffer.extend_from_slice(&[0x0F, 0x80 + condition as u8, 0, 0, 0, 0]);
+                self.label_fixups.push((self.buffer.len() - 4, label));
+            },
+
+            // The table follows the jump, named after its offset
+            Instruction::JumpTable(index, targets) => {
+                let table = Memory::IndexScale(Register::GPR32(index), Scale::X4, Immediate::U32(0));
+                let symbol = format!(".Ltable{}", self.buffer.len());
+
+                self.encode_op(Kind::Jmp, Some(Operand::Memory(table)), None);
+                self.add_relocation(RelocationKind::Absolute32, &symbol);
+                self.symbols.insert(symbol, self.buffer.len());
+
+                for target in targets {
+                    self.buffer.extend_from_slice(&[0, 0, 0, 0]);
+                    self.label_addresses.push((self.buffer.len() - 4, target));
+                }
+            },
+
+            Instruction::Label(label) => {
+                self.labels[label.0 as usize] = Some(self.buffer.len());
+            },
+
+            Instruction::Symbol(name) => {
+                if self.symbols.insert(name.clone(), self.buffer.len()).is_some() {
+                    panic!("symbol `{}` is defined more than once", name);
+                }
+            },
+
+            Instruction::Bytes(bytes) => self.buffer.extend_from_slice(&bytes),
         }
     }
+
+    fn encode_op(&mut self, kind: Kind, operand1: Option<Operand>, operand2: Option<Operand>) {
+        let instr_info = match instruction_table::find_instruction(kind, operand1, operand2) {
+            Some(instr_info) => instr_info,
+            None => panic!("invalid instruction format"),
+        };
+
+        let (opcode, last) = instr_info.op.split_at(instr_info.op.len() - 1);
+        let mut last = last[0];
+
+        if instr_info.op_type1 == OpType::RegInOpcode16_32 {
+            if let Some(Operand::Register(register)) = operand1 {
+                last += crate::x86::modrm::register_to_reg(register) as u8;
+            }
+        }
+
+        self.buffer.extend_from_slice(opcode);
+        self.buffer.push(last);
+
+        match (instr_info.op_type1, instr_info.ext) {
+            (OpType::ModRm16_32, Some(digit)) => {
+                utils::emit_modrm_ext(operand1.unwrap(), digit, &mut self.buffer);
+            },
+
+            (OpType::ModRm16_32 | OpType::ModRm8, None) => {
+                utils::emit_modrm_byte(operand1.unwrap(), operand2.unwrap(), &mut self.buffer);
+            },
+
+            (OpType::Reg16_32, _) => {
+                utils::emit_modrm_byte(operand2.unwrap(), operand1.unwrap(), &mut self.buffer);
+            },
+
+            _ => {},
+        }
+
+        let immediate = match (instr_info.op_type1, instr_info.op_type2) {
+            (OpType::Imm8 | OpType::Imm16 | OpType::Imm16_32, _) => operand1.map(|operand| (instr_info.op_type1, operand)),
+            (_, OpType::Imm8 | OpType::Imm16 | OpType::Imm16_32) => operand2.map(|operand| (instr_info.op_type2, operand)),
+            _ => None,
+        };
+
+        if let Some((op_type, Operand::Immediate(immediate))) = immediate {
+            let value = match immediate {
+                Immediate::U8(imm) => imm as i8 as u32,
+                Immediate::U16(imm) => imm as i16 as u32,
+                Immediate::U32(imm) => imm,
+            };
+
+            if op_type == OpType::Imm8 {
+                self.buffer.push(value as u8);
+            } else if op_type == OpType::Imm16 {
+                self.buffer.extend_from_slice(&(value as u16).to_le_bytes());
+            } else {
+                utils::emit_immediate(Immediate::U32(value), &mut self.buffer);
+            }
+        }
+    }
+
+    fn add_relocation(&mut self, kind: RelocationKind, symbol: &str) {
+        self.relocations.push(Relocation {
+            offset: self.buffer.len() - 4,
+            kind,
+            symbol: symbol.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::*;
+    use crate::x86::memory::Memory;
+    use crate::x86::register::{GPReg32, GPReg8, Register};
+
+    fn reg(register: GPReg32) -> Operand {
+        Operand::Register(Register::GPR32(register))
+    }
+
+    #[test]
+    fn test_encodings() {
+        let mut codegen = Codegen::new();
+
+        codegen.mov(reg(GPReg32::ECX), Operand::Immediate(Immediate::U32(0x12345678)));
+        codegen.emit(Kind::Add, Some(reg(GPReg32::EAX)), Some(Operand::Immediate(Immediate::U32(1))));
+        codegen.emit(Kind::Sub, Some(reg(GPReg32::EAX)), Some(Operand::Immediate(Immediate::U32(0x1000))));
+        codegen.emit(Kind::Push, Some(reg(GPReg32::EBX)), None);
+        codegen.emit(Kind::Idiv, Some(Operand::Memory(Memory::RegisterDisplacement(
+            Register::GPR32(GPReg32::EBP),
+            Immediate::U32(-8i32 as u32)
+        ))), None);
+        codegen.emit(Kind::Shl, Some(reg(GPReg32::EDX)), Some(Operand::Immediate(Immediate::U32(3))));
+        codegen.emit(Kind::Sar, Some(reg(GPReg32::EAX)), Some(Operand::Register(Register::GPR8(GPReg8::CL))));
+
+        let field = Operand::Memory(Memory::RegisterDisplacement(Register::GPR32(GPReg32::EAX), Immediate::U32(4)));
+        codegen.emit(Kind::Lea, Some(reg(GPReg32::ECX)), Some(field));
+        codegen.emit(Kind::MovsxByte, Some(reg(GPReg32::EDX)), Some(field));
+        codegen.emit(Kind::MovByte, Some(field), Some(Operand::Register(Register::GPR8(GPReg8::BL))));
+        codegen.emit(Kind::MovWord, Some(field), Some(reg(GPReg32::ECX)));
+        codegen.finish();
+
+        assert_eq!(
+            &[
+                0xB9, 0x78, 0x56, 0x34, 0x12, // mov ecx, 0x12345678
+                0x83, 0xC0, 0x01, // add eax, 1
+                0x81, 0xE8, 0x00, 0x10, 0x00, 0x00, // sub eax, 0x1000
+                0x53, // push ebx
+                0xF7, 0xBD, 0xF8, 0xFF, 0xFF, 0xFF, // idiv dword [ebp - 8]
+                0xC1, 0xE2, 0x03, // shl edx, 3
+                0xD3, 0xF8, // sar eax, cl
+                0x8D, 0x88, 0x04, 0x00, 0x00, 0x00, // lea ecx, [eax + 4]
+                0x0F, 0xBE, 0x90, 0x04, 0x00, 0x00, 0x00, // movsx edx, byte [eax + 4]
+                0x88, 0x98, 0x04, 0x00, 0x00, 0x00, // mov byte [eax + 4], bl
+                0x66, 0x89, 0x88, 0x04, 0x00, 0x00, 0x00, // mov word [eax + 4], cx
+            ],
+            codegen.get_bytes()
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        let mut codegen = Codegen::new();
+        let label = codegen.create_label();
+
+        codegen.bind_label(label);
+        codegen.emit(Kind::Ret, None, None);
+        codegen.jcc(Condition::NotEqual, label);
+        codegen.finish();
+
+        assert_eq!(&[0xC3, 0x0F, 0x85, 0xF9, 0xFF, 0xFF, 0xFF], codegen.get_bytes());
+    }
+
+    #[test]
+    fn test_jump_table() {
+        let mut codegen = Codegen::new();
+        let first = codegen.create_label();
+        let second = codegen.create_label();
+
+        codegen.jump_table(GPReg32::EAX, vec![first, second]);
+        codegen.bind_label(first);
+        codegen.emit(Kind::Ret, None, None);
+        codegen.bind_label(second);
+        codegen.emit(Kind::Ud2, None, None);
+        codegen.finish();
+
+        assert_eq!(
+            &[
+                0xFF, 0x24, 0x85, 0x00, 0x00, 0x00, 0x00, // jmp [eax * 4 + table]
+                0x00, 0x00, 0x00, 0x00, // first
+                0x00, 0x00, 0x00, 0x00, // second
+                0xC3, // ret
+                0x0F, 0x0B, // ud2
+            ],
+            codegen.get_bytes()
+        );
+
+        let targets: Vec<(usize, usize)> = codegen.relocations()
+            .iter()
+            .map(|relocation| (relocation.offset, codegen.symbols()[&relocation.symbol]))
+            .collect();
+
+        assert_eq!(vec![(3, 7), (7, 15), (11, 16)], targets);
+        assert!(codegen.relocations().iter().all(|relocation| relocation.kind == RelocationKind::Absolute32));
+    }
 }
@@ -0,0 +1,21 @@
+use std::fmt;
+
+// An error in the source code, `line` and `column` start at 1
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(line: usize, column: usize, message: String) -> Self {
+        Self { line, column, message }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
@@ -1,39 +1,246 @@
-use crate::x86::register::Register;
-use crate::x86::operand::Operand;
-use crate::x86::memory::Scale;
-use crate::x86::register::GPReg32;
-use crate::x86::memory::Memory;
-use crate::x86::immediate::Immediate;
-use crate::codegen::Codegen;
+use std::collections::HashMap;
 
-pub struct ExeWriter {
+use crate::codegen::{Codegen, RelocationKind};
+use crate::export_builder::ExportBuilder;
+use crate::import_builder::ImportBuilder;
+
+const IMAGE_BASE: u32 = 0x00300000;
+const DLL_IMAGE_BASE: u32 = 0x10000000;
+const SECTION_ALIGNMENT: u32 = 0x1000;
+const FILE_ALIGNMENT: u32 = 0x200;
+
+const STACK_RESERVE: u64 = 0x100000;
+const STACK_COMMIT: u64 = 0x1000;
+const HEAP_RESERVE: u64 = 0x100000;
+const HEAP_COMMIT: u64 = 0x1000;
+
+// Contents of one section with the offsets of the symbols defined in it
+struct SectionContents {
+    bytes: Vec<u8>,
+    symbols: Vec<(String, usize)>,
+}
+
+impl SectionContents {
+    fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    fn append(&mut self, symbol: &str, bytes: &[u8]) {
+        self.bytes.resize(self.bytes.len().next_multiple_of(4), 0);
+        self.symbols.push((symbol.to_string(), self.bytes.len()));
+        self.bytes.extend_from_slice(bytes);
+    }
+}
 
+pub struct ExeWriter {
+    rdata: SectionContents,
+    data: SectionContents,
+    bss: SectionContents, // only the length of `bytes` is used
+    imports: ImportBuilder,
+    exports: ExportBuilder,
+    entry: String,
+    dynamic_base: bool,
+    dll: bool,
 }
 
 impl ExeWriter {
-    pub fn new() -> Self {
-        ExeWriter {}
+    pub fn new(entry: &str) -> Self {
+        ExeWriter {
+            rdata: SectionContents::new(),
+            data: SectionContents::new(),
+            bss: SectionContents::new(),
+            imports: ImportBuilder::new(),
+            exports: ExportBuilder::new(),
+            entry: entry.to_string(),
+            dynamic_base: false,
+            dll: false,
+        }
+    }
+
+    pub fn set_dll(&mut self, enabled: bool) {
+        self.dll = enabled;
+    }
+
+    // Lets the loader place the image at a random address (ASLR), and marks
+    // it compatible with non-executable data pages
+    pub fn set_dynamic_base(&mut self, enabled: bool) {
+        self.dynamic_base = enabled;
+    }
+
+    // Read-only data such as string literals
+    pub fn add_rdata(&mut self, symbol: &str, bytes: &[u8]) {
+        self.rdata.append(symbol, bytes);
+    }
+
+    // Writable data, zero-initialized globals take no space in the file
+    #[allow(unused)]
+    pub fn add_global(&mut self, symbol: &str, bytes: &[u8]) {
+        if bytes.iter().all(|&byte| byte == 0) {
+            self.bss.append(symbol, bytes);
+        } else {
+            self.data.append(symbol, bytes);
+        }
+    }
+
+    pub fn add_import(&mut self, dll: &str, function: &str) {
+        self.imports.add(dll, function);
+    }
+
+    // Exports the function defined by `symbol` under the same name
+    pub fn add_export(&mut self, symbol: &str) {
+        self.exports.add(symbol);
     }
 
-    pub fn write(&mut self) {
+    pub fn write(&mut self, codegen: &Codegen, path: &str) {
         let mut out_data = Vec::new();
 
         let mut writer = object::write::pe::Writer::new(
             false,
-            0x1000, 
-            0x1000,
+            SECTION_ALIGNMENT,
+            FILE_ALIGNMENT,
             &mut out_data
         );
 
+        let mut text = codegen.get_bytes().to_vec();
+
+        // Every absolute address needs a base relocation so the image can be
+        // loaded at a different address
+        let mut absolute_offsets: Vec<usize> = codegen.relocations()
+            .iter()
+            .filter(|relocation| relocation.kind == RelocationKind::Absolute32)
+            .map(|relocation| relocation.offset)
+            .collect();
+
+        absolute_offsets.sort();
+
+        let section_count = 1 +
+            !absolute_offsets.is_empty() as u16 +
+            !self.rdata.bytes.is_empty() as u16 +
+            !self.data.bytes.is_empty() as u16 +
+            !self.bss.bytes.is_empty() as u16 +
+            !self.exports.is_empty() as u16 +
+            !self.imports.is_empty() as u16;
+
+        let image_base = if self.dll { DLL_IMAGE_BASE } else { IMAGE_BASE };
+
+        let file_name = std::path::Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path);
+
         writer.reserve_dos_header_and_stub();
         writer.reserve_nt_headers(16);
-        writer.reserve_section_headers(1);
+        writer.reserve_section_headers(section_count);
+
+        // Sections are sized from their contents, the writer aligns them
+        let text_range = writer.reserve_text_section(text.len() as u32);
+
+        let rdata_range = (!self.rdata.bytes.is_empty())
+            .then(|| writer.reserve_rdata_section(self.rdata.bytes.len() as u32));
+
+        let data_range = (!self.data.bytes.is_empty()).then(|| {
+            let size = self.data.bytes.len() as u32;
+            writer.reserve_data_section(size, size)
+        });
+
+        let bss_range = (!self.bss.bytes.is_empty())
+            .then(|| writer.reserve_bss_section(self.bss.bytes.len() as u32));
+
+        let edata_range = (!self.exports.is_empty())
+            .then(|| writer.reserve_edata_section(self.exports.size(file_name)));
+
+        let idata = if self.imports.is_empty() {
+            None
+        } else {
+            let range = writer.reserve_idata_section(self.imports.size());
+            let table = self.imports.build(range.virtual_address);
 
-        writer.reserve_virtual_until(0x1000);
-        let text_range = writer.reserve_text_section(0x1000);
+            writer.set_data_directory(object::pe::IMAGE_DIRECTORY_ENTRY_IAT, table.iat_address, table.iat_size);
+            Some((range, table))
+        };
 
-        //writer.reserve_virtual_until(0x2000);
-        //let data_range = writer.reserve_data_section(0x1000, 0x1000);
+        for offset in &absolute_offsets {
+            writer.add_reloc(text_range.virtual_address + *offset as u32, object::pe::IMAGE_REL_BASED_HIGHLOW);
+        }
+
+        if writer.has_relocs() {
+            writer.reserve_reloc_section();
+        }
+
+        let dll_characteristics = if self.dynamic_base {
+            object::pe::IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE | object::pe::IMAGE_DLLCHARACTERISTICS_NX_COMPAT
+        } else {
+            0
+        };
+
+        // Symbols map to RVAs
+        let mut symbols = HashMap::new();
+
+        for (name, offset) in codegen.symbols() {
+            symbols.insert(name.clone(), text_range.virtual_address + *offset as u32);
+        }
+
+        let sections = [
+            (&self.rdata, rdata_range),
+            (&self.data, data_range),
+            (&self.bss, bss_range),
+        ];
+
+        for (contents, range) in sections {
+            if let Some(range) = range {
+                for (name, offset) in &contents.symbols {
+                    symbols.insert(name.clone(), range.virtual_address + *offset as u32);
+                }
+            }
+        }
+
+        if let Some((_, table)) = &idata {
+            for (name, address) in &table.slots {
+                symbols.insert(name.clone(), *address);
+            }
+        }
+
+        for relocation in codegen.relocations() {
+            let target = match symbols.get(&relocation.symbol) {
+                Some(target) => *target,
+                None => panic!("undefined symbol `{}`", relocation.symbol),
+            };
+
+            let address = text_range.virtual_address + relocation.offset as u32;
+
+            let value = match relocation.kind {
+                RelocationKind::Absolute32 => image_base + target,
+                RelocationKind::Relative32 => target.wrapping_sub(address + 4),
+            };
+
+            text[relocation.offset..relocation.offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let entry = match symbols.get(&self.entry) {
+            Some(entry) => *entry,
+            None => panic!("undefined entry point `{}`", self.entry),
+        };
+
+        let edata = edata_range.map(|range| {
+            let bytes = self.exports.build(file_name, range.virtual_address, |name| match symbols.get(name) {
+                Some(address) => *address,
+                None => panic!("undefined export `{}`", name),
+            });
+
+            (range, bytes)
+        });
+
+        let mut characteristics = object::pe::IMAGE_FILE_EXECUTABLE_IMAGE |
+            object::pe::IMAGE_FILE_LINE_NUMS_STRIPPED |
+            object::pe::IMAGE_FILE_LOCAL_SYMS_STRIPPED |
+            object::pe::IMAGE_FILE_32BIT_MACHINE;
+
+        if self.dll {
+            characteristics |= object::pe::IMAGE_FILE_DLL;
+        }
 
         writer.write_empty_dos_header()
             .unwrap();
@@ -41,14 +248,11 @@ impl ExeWriter {
         writer.write_nt_headers(object::write::pe::NtHeaders {
             machine: object::pe::IMAGE_FILE_MACHINE_I386,
             time_date_stamp: 0,
-            characteristics: object::pe::IMAGE_FILE_EXECUTABLE_IMAGE |
-                            object::pe::IMAGE_FILE_LINE_NUMS_STRIPPED |
-                            object::pe::IMAGE_FILE_LOCAL_SYMS_STRIPPED | 
-                            object::pe::IMAGE_FILE_32BIT_MACHINE,
+            characteristics,
             major_linker_version: 0,
             minor_linker_version: 0,
-            address_of_entry_point: text_range.virtual_address as u32,
-            image_base: 0x00300000,
+            address_of_entry_point: entry,
+            image_base: image_base as u64,
             major_operating_system_version: 0,
             minor_operating_system_version: 0,
             major_image_version: 0,
@@ -56,37 +260,99 @@ impl ExeWriter {
             major_subsystem_version: 5,
             minor_subsystem_version: 1,
             subsystem: object::pe::IMAGE_SUBSYSTEM_WINDOWS_CUI,
-            dll_characteristics: 0,
-            size_of_stack_reserve: 0,
-            size_of_stack_commit: 0,
-            size_of_heap_reserve: 0,
-            size_of_heap_commit: 0,
+            dll_characteristics,
+            size_of_stack_reserve: STACK_RESERVE,
+            size_of_stack_commit: STACK_COMMIT,
+            size_of_heap_reserve: HEAP_RESERVE,
+            size_of_heap_commit: HEAP_COMMIT,
         });
 
         writer.write_section_headers();
 
-        writer.pad_until(text_range.file_offset);
+        writer.write_section(text_range.file_offset, &text);
+
+        if let Some(range) = rdata_range {
+            writer.write_section(range.file_offset, &self.rdata.bytes);
+        }
 
+        if let Some(range) = data_range {
+            writer.write_section(range.file_offset, &self.data.bytes);
+        }
+
+        if let Some((range, bytes)) = &edata {
+            writer.write_section(range.file_offset, bytes);
+        }
+
+        if let Some((range, table)) = &idata {
+            writer.write_section(range.file_offset, &table.bytes);
+        }
+
+        writer.write_reloc_section();
+
+        std::fs::write(path, out_data).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use object::read::pe::PeFile32;
+    use object::{Object, ObjectSection};
+
+    use crate::exe_writer::*;
+    use crate::x86::instruction_table::Kind;
+    use crate::x86::operand::Operand;
+    use crate::x86::register::{GPReg32, Register};
+
+    #[test]
+    fn test_section_layout() {
         let mut codegen = Codegen::new();
 
-        codegen.mov(
-            Operand::Memory(Memory::BaseIndexScaleDisplacement(
-                Register::GPR32(GPReg32::EDI),
-                Register::GPR32(GPReg32::ESI),
-                Scale::X4,
-                Immediate::U32(0x12345678)
-            )),
-            Operand::Register(Register::GPR32(GPReg32::EBP))
-        );
+        codegen.define_symbol("_start");
+
+        // More than one page of code
+        for _ in 0..0x1000 {
+            codegen.emit(Kind::Cdq, None, None);
+        }
+
+        codegen.mov_symbol_address(Operand::Register(Register::GPR32(GPReg32::EAX)), "message");
+        codegen.emit(Kind::Ret, None, None);
+        codegen.finish();
+
+        let mut writer = ExeWriter::new("_start");
+        writer.set_dynamic_base(true);
+        writer.add_rdata("message", b"hello\0");
+        writer.add_global("counter", &[0; 8]);
+        writer.add_global("initialized", &[1, 2, 3, 4]);
+
+        let path = std::env::temp_dir().join("dylang_test_section_layout.exe");
+        writer.write(&codegen, path.to_str().unwrap());
+
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let file = PeFile32::parse(&*bytes).unwrap();
+
+        let names: Vec<String> = file.sections().map(|section| section.name().unwrap().to_string()).collect();
+        assert_eq!(vec![".text", ".rdata", ".data", ".bss", ".reloc"], names);
+
+        let text = file.section_by_name(".text").unwrap();
+        let rdata = file.section_by_name(".rdata").unwrap();
+        assert_eq!(0x1006, text.size());
+        assert_eq!(b"hello\0", &rdata.data().unwrap()[..6]);
 
-        writer.write_section(text_range.file_offset, codegen.get_bytes() /*&[0xEB, 0xFE, 0xC3]*/);
+        // mov eax, imm32 refers to the string
+        let code = text.data().unwrap();
+        let address = u32::from_le_bytes(code[0x1001..0x1005].try_into().unwrap());
+        assert_eq!(rdata.address(), address as u64);
 
-        //writer.pad_until(data_range.file_offset);
-        //writer.write_section(data_range.file_offset, &[0xAB, 0xCD, 0xEF]);
-        
+        let optional_header = &file.nt_headers().optional_header;
+        assert_eq!(STACK_RESERVE as u32, optional_header.size_of_stack_reserve.get(object::LittleEndian));
+        assert_ne!(0, optional_header.dll_characteristics.get(object::LittleEndian) & object::pe::IMAGE_DLLCHARACTERISTICS_DYNAMIC_BASE);
 
-        
-        
-        std::fs::write("compiled.exe", out_data).unwrap();
+        // One block for the page with the `mov`, padded to an even count
+        let reloc = file.section_by_name(".reloc").unwrap().data().unwrap();
+        assert_eq!(text.address() as u32 - IMAGE_BASE + 0x1000, u32::from_le_bytes(reloc[0..4].try_into().unwrap()));
+        assert_eq!(12, u32::from_le_bytes(reloc[4..8].try_into().unwrap()));
+        assert_eq!(0x3001, u16::from_le_bytes(reloc[8..10].try_into().unwrap()));
     }
 }
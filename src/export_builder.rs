@@ -0,0 +1,132 @@
+// Lays out the export directory of a PE DLL:
+//
+//   export directory table
+//   export address table (function RVAs, indexed by ordinal - base)
+//   name pointer table (sorted, the loader binary searches it)
+//   ordinal table (parallel to the name pointer table)
+//   DLL name and function names
+//
+// Every export is by name and its ordinal is its index in the sorted list
+
+const DIRECTORY_SIZE: usize = 40;
+const ORDINAL_BASE: u32 = 1;
+
+pub struct ExportBuilder {
+    names: Vec<String>,
+}
+
+impl ExportBuilder {
+    pub fn new() -> Self {
+        Self {
+            names: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn add(&mut self, name: &str) {
+        if !self.names.iter().any(|export| export == name) {
+            self.names.push(name.to_string());
+        }
+    }
+
+    pub fn size(&self, dll_name: &str) -> u32 {
+        self.build(dll_name, 0, |_| 0).len() as u32
+    }
+
+    // `address` is the RVA the table will be loaded at, `resolve` gives the
+    // RVA of an exported function
+    pub fn build(&self, dll_name: &str, address: u32, resolve: impl Fn(&str) -> u32) -> Vec<u8> {
+        let mut names: Vec<&String> = self.names.iter().collect();
+        names.sort();
+
+        let count = names.len();
+        let eat_offset = DIRECTORY_SIZE;
+        let name_pointers_offset = eat_offset + count * 4;
+        let ordinals_offset = name_pointers_offset + count * 4;
+        let dll_name_offset = ordinals_offset + count * 2;
+
+        let mut bytes = vec![0u8; dll_name_offset];
+        bytes.extend_from_slice(dll_name.as_bytes());
+        bytes.push(0);
+
+        let rva = |offset: usize| address + offset as u32;
+
+        write_u32(&mut bytes, 12, rva(dll_name_offset)); // Name
+        write_u32(&mut bytes, 16, ORDINAL_BASE); // Base
+        write_u32(&mut bytes, 20, count as u32); // NumberOfFunctions
+        write_u32(&mut bytes, 24, count as u32); // NumberOfNames
+        write_u32(&mut bytes, 28, rva(eat_offset)); // AddressOfFunctions
+        write_u32(&mut bytes, 32, rva(name_pointers_offset)); // AddressOfNames
+        write_u32(&mut bytes, 36, rva(ordinals_offset)); // AddressOfNameOrdinals
+
+        for (index, name) in names.iter().enumerate() {
+            let name_offset = bytes.len();
+
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.push(0);
+
+            write_u32(&mut bytes, eat_offset + index * 4, resolve(name));
+            write_u32(&mut bytes, name_pointers_offset + index * 4, rva(name_offset));
+
+            let ordinal = (index as u16).to_le_bytes();
+            bytes[ordinals_offset + index * 2..ordinals_offset + index * 2 + 2].copy_from_slice(&ordinal);
+        }
+
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+        bytes
+    }
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export_builder::*;
+
+    fn read_u32(bytes: &[u8], offset: u32) -> u32 {
+        let offset = offset as usize;
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_name(bytes: &[u8], offset: u32) -> &str {
+        let name = &bytes[offset as usize..];
+        let end = name.iter().position(|&byte| byte == 0).unwrap();
+        std::str::from_utf8(&name[..end]).unwrap()
+    }
+
+    #[test]
+    fn test_export_layout() {
+        let mut builder = ExportBuilder::new();
+
+        builder.add("sub");
+        builder.add("add");
+        builder.add("sub");
+
+        let base = 0x3000;
+        let bytes = builder.build("lib.dll", base, |name| if name == "add" { 0x1000 } else { 0x1010 });
+
+        assert_eq!(builder.size("lib.dll") as usize, bytes.len());
+        assert_eq!("lib.dll", read_name(&bytes, read_u32(&bytes, 12) - base));
+        assert_eq!(2, read_u32(&bytes, 24));
+
+        let functions = read_u32(&bytes, 28) - base;
+        let names = read_u32(&bytes, 32) - base;
+        let ordinals = read_u32(&bytes, 36) - base;
+
+        // Names are sorted, each ordinal indexes the address table
+        for (index, (name, address)) in [("add", 0x1000), ("sub", 0x1010)].iter().enumerate() {
+            let index = index as u32;
+            let name_address = read_u32(&bytes, names + index * 4) - base;
+            assert_eq!(*name, read_name(&bytes, name_address));
+
+            let offset = (ordinals + index * 2) as usize;
+            let ordinal = u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap()) as u32;
+            assert_eq!(*address, read_u32(&bytes, functions + ordinal * 4));
+        }
+    }
+}
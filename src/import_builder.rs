@@ -0,0 +1,191 @@
+// Lays out the import directory of a PE image:
+//
+//   import descriptors (one per DLL, zero terminated)
+//   import lookup tables (one per DLL, zero terminated)
+//   import address tables (same layout as the lookup tables)
+//   hint/name entries
+//   DLL names
+//
+// The loader overwrites each IAT slot with the address of the function, so
+// code calls it with `call [slot]`
+
+const DESCRIPTOR_SIZE: usize = 20;
+
+pub fn import_symbol(function: &str) -> String {
+    format!("__imp_{}", function)
+}
+
+pub struct ImportTable {
+    pub bytes: Vec<u8>,
+    pub slots: Vec<(String, u32)>, // IAT slot symbol and its RVA
+    pub iat_address: u32,
+    pub iat_size: u32,
+}
+
+pub struct ImportBuilder {
+    dlls: Vec<(String, Vec<String>)>,
+}
+
+impl ImportBuilder {
+    pub fn new() -> Self {
+        Self {
+            dlls: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dlls.is_empty()
+    }
+
+    pub fn add(&mut self, dll: &str, function: &str) {
+        let index = match self.dlls.iter().position(|(name, _)| name == dll) {
+            Some(index) => index,
+            None => {
+                self.dlls.push((dll.to_string(), Vec::new()));
+                self.dlls.len() - 1
+            },
+        };
+
+        let functions = &mut self.dlls[index].1;
+
+        if !functions.iter().any(|name| name == function) {
+            functions.push(function.to_string());
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.build(0).bytes.len() as u32
+    }
+
+    // `address` is the RVA the table will be loaded at
+    pub fn build(&self, address: u32) -> ImportTable {
+        let thunk_count: usize = self.dlls
+            .iter()
+            .map(|(_, functions)| functions.len() + 1)
+            .sum();
+
+        let descriptors_offset = 0;
+        let ilt_offset = descriptors_offset + (self.dlls.len() + 1) * DESCRIPTOR_SIZE;
+        let iat_offset = ilt_offset + thunk_count * 4;
+        let names_offset = iat_offset + thunk_count * 4;
+
+        // Hint/name entries and DLL names go after the tables
+        let mut names = Vec::new();
+        let mut hint_names = Vec::new();
+        let mut dll_names = Vec::new();
+
+        for (_, functions) in &self.dlls {
+            let mut entries = Vec::new();
+
+            for function in functions {
+                entries.push(names_offset + names.len());
+
+                names.extend_from_slice(&0u16.to_le_bytes());
+                names.extend_from_slice(function.as_bytes());
+                names.push(0);
+                names.resize(names.len().next_multiple_of(2), 0);
+            }
+
+            hint_names.push(entries);
+        }
+
+        for (dll, _) in &self.dlls {
+            dll_names.push(names_offset + names.len());
+
+            names.extend_from_slice(dll.as_bytes());
+            names.push(0);
+        }
+
+        let mut bytes = vec![0u8; names_offset];
+        bytes.extend_from_slice(&names);
+        bytes.resize(bytes.len().next_multiple_of(4), 0);
+
+        let rva = |offset: usize| address + offset as u32;
+        let mut slots = Vec::new();
+        let mut thunk = 0;
+
+        for (index, (_, functions)) in self.dlls.iter().enumerate() {
+            let descriptor = descriptors_offset + index * DESCRIPTOR_SIZE;
+            let ilt = ilt_offset + thunk * 4;
+            let iat = iat_offset + thunk * 4;
+
+            write_u32(&mut bytes, descriptor, rva(ilt)); // OriginalFirstThunk
+            write_u32(&mut bytes, descriptor + 12, rva(dll_names[index])); // Name
+            write_u32(&mut bytes, descriptor + 16, rva(iat)); // FirstThunk
+
+            for (function_index, function) in functions.iter().enumerate() {
+                let entry = rva(hint_names[index][function_index]);
+
+                write_u32(&mut bytes, ilt + function_index * 4, entry);
+                write_u32(&mut bytes, iat + function_index * 4, entry);
+
+                slots.push((import_symbol(function), rva(iat + function_index * 4)));
+            }
+
+            thunk += functions.len() + 1;
+        }
+
+        ImportTable {
+            bytes,
+            slots,
+            iat_address: rva(iat_offset),
+            iat_size: (thunk_count * 4) as u32,
+        }
+    }
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::import_builder::*;
+
+    fn read_u32(bytes: &[u8], offset: u32) -> u32 {
+        let offset = offset as usize;
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_name(bytes: &[u8], offset: u32) -> &str {
+        let name = &bytes[offset as usize..];
+        let end = name.iter().position(|&byte| byte == 0).unwrap();
+        std::str::from_utf8(&name[..end]).unwrap()
+    }
+
+    #[test]
+    fn test_import_layout() {
+        let mut builder = ImportBuilder::new();
+
+        builder.add("kernel32.dll", "ExitProcess");
+        builder.add("kernel32.dll", "WriteFile");
+        builder.add("kernel32.dll", "ExitProcess");
+
+        let base = 0x2000;
+        let table = builder.build(base);
+        let bytes = &table.bytes;
+
+        assert_eq!(builder.size() as usize, bytes.len());
+        assert_eq!(2, table.slots.len());
+
+        // The second descriptor terminates the list
+        assert_eq!(&[0; DESCRIPTOR_SIZE], &bytes[DESCRIPTOR_SIZE..DESCRIPTOR_SIZE * 2]);
+
+        let name = read_u32(bytes, 12) - base;
+        let iat = read_u32(bytes, 16) - base;
+
+        assert_eq!("kernel32.dll", read_name(bytes, name));
+        assert_eq!(table.iat_address, base + iat);
+        assert_eq!(12, table.iat_size);
+
+        for (index, (symbol, address)) in table.slots.iter().enumerate() {
+            let slot = iat + index as u32 * 4;
+            assert_eq!(base + slot, *address);
+
+            let hint_name = read_u32(bytes, slot) - base;
+            assert_eq!(*symbol, import_symbol(read_name(bytes, hint_name + 2)));
+        }
+
+        assert_eq!(0, read_u32(bytes, iat + 8));
+    }
+}
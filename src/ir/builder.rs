@@ -0,0 +1,158 @@
+use super::{BinaryOp, Block, BlockId, DataId, Function, Inst, InstKind, SlotId, StackSlot, Terminator, Type, UnaryOp, Value};
+
+pub struct FunctionBuilder<'a> {
+    function: &'a mut Function,
+    current_block: Option<BlockId>,
+}
+
+#[allow(unused)]
+impl<'a> FunctionBuilder<'a> {
+    pub fn new(function: &'a mut Function) -> Self {
+        Self {
+            function,
+            current_block: None,
+        }
+    }
+
+    pub fn add_param(&mut self, ty: Type) -> Value {
+        let value = self.function.new_value(ty);
+        self.function.params.push(value);
+        value
+    }
+
+    pub fn create_block(&mut self) -> BlockId {
+        self.function.blocks.push(Block::default());
+        BlockId(self.function.blocks.len() as u32 - 1)
+    }
+
+    pub fn switch_to_block(&mut self, block: BlockId) {
+        self.current_block = Some(block);
+    }
+
+    pub fn current_block(&self) -> Option<BlockId> {
+        self.current_block
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        match self.current_block {
+            Some(block) => self.function.block(block).terminator.is_some(),
+            None => true,
+        }
+    }
+
+    pub fn value_type(&self, value: Value) -> Type {
+        self.function.value_type(value)
+    }
+
+    pub fn iconst(&mut self, ty: Type, value: i64) -> Value {
+        self.push_value(ty, InstKind::Const(value))
+    }
+
+    pub fn binary(&mut self, op: BinaryOp, lhs: Value, rhs: Value) -> Value {
+        let ty = self.function.value_type(lhs);
+        self.push_value(ty, InstKind::Binary(op, lhs, rhs))
+    }
+
+    pub fn unary(&mut self, op: UnaryOp, value: Value) -> Value {
+        let ty = self.function.value_type(value);
+        self.push_value(ty, InstKind::Unary(op, value))
+    }
+
+    pub fn copy(&mut self, value: Value) -> Value {
+        let ty = self.function.value_type(value);
+        self.push_value(ty, InstKind::Copy(value))
+    }
+
+    pub fn bitcast(&mut self, value: Value, ty: Type) -> Value {
+        self.push_value(ty, InstKind::Bitcast(value))
+    }
+
+    pub fn data_address(&mut self, data: DataId) -> Value {
+        self.push_value(Type::Ptr, InstKind::DataAddress(data))
+    }
+
+    pub fn create_stack_slot(&mut self, size: u32, align: u32) -> SlotId {
+        self.function.stack_slots.push(StackSlot { size, align });
+        SlotId(self.function.stack_slots.len() as u32 - 1)
+    }
+
+    pub fn stack_address(&mut self, slot: SlotId) -> Value {
+        self.push_value(Type::Ptr, InstKind::StackAddress(slot))
+    }
+
+    pub fn load(&mut self, ty: Type, address: Value, offset: i32) -> Value {
+        self.push_value(ty, InstKind::Load(address, offset))
+    }
+
+    pub fn store(&mut self, address: Value, offset: i32, value: Value) {
+        let ty = self.function.value_type(value);
+        self.push(Inst { result: None, ty, kind: InstKind::Store(address, offset, value) });
+    }
+
+    pub fn element_address(&mut self, base: Value, index: Value, scale: u32) -> Value {
+        self.push_value(Type::Ptr, InstKind::ElementAddress(base, index, scale))
+    }
+
+    pub fn call(&mut self, name: &str, args: Vec<Value>, return_type: Type) -> Option<Value> {
+        let kind = InstKind::Call(name.to_string(), args);
+
+        if return_type == Type::Void {
+            self.push(Inst { result: None, ty: Type::Void, kind });
+            None
+        } else {
+            Some(self.push_value(return_type, kind))
+        }
+    }
+
+    pub fn phi(&mut self, ty: Type, incoming: Vec<(BlockId, Value)>) -> Value {
+        self.push_value(ty, InstKind::Phi(incoming))
+    }
+
+    pub fn ret(&mut self, value: Option<Value>) {
+        self.terminate(Terminator::Return(value));
+    }
+
+    pub fn jump(&mut self, target: BlockId) {
+        self.terminate(Terminator::Jump(target));
+    }
+
+    pub fn branch(&mut self, condition: Value, then_block: BlockId, else_block: BlockId) {
+        self.terminate(Terminator::Branch(condition, then_block, else_block));
+    }
+
+    pub fn switch(&mut self, value: Value, cases: Vec<BlockId>, default: BlockId) {
+        self.terminate(Terminator::Switch(value, cases, default));
+    }
+
+    pub fn unreachable(&mut self) {
+        self.terminate(Terminator::Unreachable);
+    }
+
+    pub fn trap(&mut self, message: DataId) {
+        self.terminate(Terminator::Trap(message));
+    }
+
+    fn push_value(&mut self, ty: Type, kind: InstKind) -> Value {
+        let result = self.function.new_value(ty);
+        self.push(Inst { result: Some(result), ty, kind });
+        result
+    }
+
+    fn push(&mut self, inst: Inst) {
+        let block = self.current_block.expect("no current block");
+        let block = &mut self.function.blocks[block.0 as usize];
+
+        assert!(block.terminator.is_none(), "block is already terminated");
+
+        block.insts.push(inst);
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        let block = self.current_block.expect("no current block");
+        let block = &mut self.function.blocks[block.0 as usize];
+
+        assert!(block.terminator.is_none(), "block is already terminated");
+
+        block.terminator = Some(terminator);
+    }
+}
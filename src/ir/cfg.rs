@@ -0,0 +1,149 @@
+use super::{BlockId, Function};
+
+pub struct ControlFlowGraph {
+    successors: Vec<Vec<BlockId>>,
+    predecessors: Vec<Vec<BlockId>>,
+    reverse_postorder: Vec<BlockId>,
+    idom: Vec<Option<BlockId>>,
+}
+
+impl ControlFlowGraph {
+    pub fn new(function: &Function) -> Self {
+        let block_count = function.blocks.len();
+
+        let mut successors = vec![Vec::new(); block_count];
+        let mut predecessors = vec![Vec::new(); block_count];
+
+        for block_id in function.block_ids() {
+            if let Some(terminator) = &function.block(block_id).terminator {
+                for successor in terminator.successors() {
+                    if (successor.0 as usize) < block_count {
+                        successors[block_id.0 as usize].push(successor);
+                        predecessors[successor.0 as usize].push(block_id);
+                    }
+                }
+            }
+        }
+
+        let mut cfg = Self {
+            successors,
+            predecessors,
+            reverse_postorder: Vec::new(),
+            idom: vec![None; block_count],
+        };
+
+        if block_count > 0 {
+            cfg.compute_reverse_postorder(function.entry());
+            cfg.compute_dominators(function.entry());
+        }
+
+        cfg
+    }
+
+    pub fn predecessors(&self, block: BlockId) -> &[BlockId] {
+        &self.predecessors[block.0 as usize]
+    }
+
+    // Only reachable blocks, entry first
+    pub fn reverse_postorder(&self) -> &[BlockId] {
+        &self.reverse_postorder
+    }
+
+    pub fn is_reachable(&self, block: BlockId) -> bool {
+        self.reverse_postorder.contains(&block)
+    }
+
+    pub fn dominates(&self, dominator: BlockId, block: BlockId) -> bool {
+        let mut current = block;
+
+        loop {
+            if current == dominator {
+                return true;
+            }
+
+            match self.idom[current.0 as usize] {
+                Some(parent) if parent != current => current = parent,
+                _ => return false,
+            }
+        }
+    }
+
+    fn compute_reverse_postorder(&mut self, entry: BlockId) {
+        let mut visited = vec![false; self.successors.len()];
+        let mut postorder = Vec::new();
+        let mut stack = vec![(entry, 0)];
+
+        visited[entry.0 as usize] = true;
+
+        while let Some((block, next_successor)) = stack.pop() {
+            let successors = &self.successors[block.0 as usize];
+
+            if next_successor < successors.len() {
+                stack.push((block, next_successor + 1));
+
+                let successor = successors[next_successor];
+
+                if !visited[successor.0 as usize] {
+                    visited[successor.0 as usize] = true;
+                    stack.push((successor, 0));
+                }
+            } else {
+                postorder.push(block);
+            }
+        }
+
+        postorder.reverse();
+        self.reverse_postorder = postorder;
+    }
+
+    // Cooper, Harvey, Kennedy: "A Simple, Fast Dominance Algorithm"
+    fn compute_dominators(&mut self, entry: BlockId) {
+        let mut order = vec![usize::MAX; self.successors.len()];
+
+        for (index, block) in self.reverse_postorder.iter().enumerate() {
+            order[block.0 as usize] = index;
+        }
+
+        self.idom[entry.0 as usize] = Some(entry);
+
+        let mut changed = true;
+
+        while changed {
+            changed = false;
+
+            for &block in self.reverse_postorder.iter().skip(1) {
+                let mut new_idom: Option<BlockId> = None;
+
+                for &predecessor in &self.predecessors[block.0 as usize] {
+                    if self.idom[predecessor.0 as usize].is_none() {
+                        continue;
+                    }
+
+                    new_idom = Some(match new_idom {
+                        None => predecessor,
+                        Some(current) => self.intersect(&order, predecessor, current),
+                    });
+                }
+
+                if new_idom.is_some() && self.idom[block.0 as usize] != new_idom {
+                    self.idom[block.0 as usize] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    fn intersect(&self, order: &[usize], mut a: BlockId, mut b: BlockId) -> BlockId {
+        while a != b {
+            while order[a.0 as usize] > order[b.0 as usize] {
+                a = self.idom[a.0 as usize].unwrap();
+            }
+
+            while order[b.0 as usize] > order[a.0 as usize] {
+                b = self.idom[b.0 as usize].unwrap();
+            }
+        }
+
+        a
+    }
+}
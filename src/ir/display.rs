@@ -0,0 +1,246 @@
+use std::fmt;
+
+use super::{BinaryOp, BlockId, CallingConvention, DataId, Function, Inst, InstKind, Module, SlotId, Terminator, Type, UnaryOp, Value};
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Type::Void => "void",
+            Type::I8 => "i8",
+            Type::I16 => "i16",
+            Type::I32 => "i32",
+            Type::I64 => "i64",
+            Type::Ptr => "ptr",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bb{}", self.0)
+    }
+}
+
+impl fmt::Display for DataId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "@d{}", self.0)
+    }
+}
+
+impl fmt::Display for SlotId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "$s{}", self.0)
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::Div => "div",
+            BinaryOp::Rem => "rem",
+            BinaryOp::Udiv => "udiv",
+            BinaryOp::Urem => "urem",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Xor => "xor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::Sar => "sar",
+            BinaryOp::Shr => "shr",
+            BinaryOp::Eq => "eq",
+            BinaryOp::Ne => "ne",
+            BinaryOp::Lt => "lt",
+            BinaryOp::Le => "le",
+            BinaryOp::Gt => "gt",
+            BinaryOp::Ge => "ge",
+            BinaryOp::Ult => "ult",
+            BinaryOp::Ule => "ule",
+            BinaryOp::Ugt => "ugt",
+            BinaryOp::Uge => "uge",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            UnaryOp::Neg => "neg",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(result) = self.result {
+            write!(f, "{} = ", result)?;
+        }
+
+        match &self.kind {
+            InstKind::Const(value) => write!(f, "const {} {}", self.ty, value),
+            InstKind::Binary(op, lhs, rhs) => write!(f, "{} {} {}, {}", op, self.ty, lhs, rhs),
+            InstKind::Unary(op, value) => write!(f, "{} {} {}", op, self.ty, value),
+            InstKind::Copy(value) => write!(f, "copy {} {}", self.ty, value),
+            InstKind::Bitcast(value) => write!(f, "bitcast {} {}", self.ty, value),
+            InstKind::DataAddress(data) => write!(f, "addr {} {}", self.ty, data),
+            InstKind::StackAddress(slot) => write!(f, "addr {} {}", self.ty, slot),
+            InstKind::Load(address, offset) => write!(f, "load {} [{} + {}]", self.ty, address, offset),
+            InstKind::Store(address, offset, value) => write!(f, "store {} {}, [{} + {}]", self.ty, value, address, offset),
+            InstKind::ElementAddress(base, index, scale) => write!(f, "element {} [{} + {} * {}]", self.ty, base, index, scale),
+
+            InstKind::Call(name, args) => {
+                write!(f, "call {} @{}(", self.ty, name)?;
+                write_list(f, args)?;
+                write!(f, ")")
+            },
+
+            InstKind::Phi(incoming) => {
+                write!(f, "phi {}", self.ty)?;
+
+                for (index, (block, value)) in incoming.iter().enumerate() {
+                    let separator = if index == 0 { " " } else { ", " };
+                    write!(f, "{}[{}, {}]", separator, block, value)?;
+                }
+
+                Ok(())
+            },
+        }
+    }
+}
+
+impl fmt::Display for Terminator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminator::Return(Some(value)) => write!(f, "ret {}", value),
+            Terminator::Return(None) => write!(f, "ret"),
+            Terminator::Jump(target) => write!(f, "jmp {}", target),
+            Terminator::Branch(condition, then_block, else_block) => {
+                write!(f, "br {}, {}, {}", condition, then_block, else_block)
+            },
+            Terminator::Switch(value, cases, default) => {
+                write!(f, "switch {}, [", value)?;
+
+                for (index, case) in cases.iter().enumerate() {
+                    let separator = if index == 0 { "" } else { ", " };
+                    write!(f, "{}{}", separator, case)?;
+                }
+
+                write!(f, "], {}", default)
+            },
+            Terminator::Unreachable => write!(f, "unreachable"),
+            Terminator::Trap(message) => write!(f, "trap {}", message),
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.exported {
+            write!(f, "pub ")?;
+        }
+
+        write!(f, "fn {}(", self.name)?;
+
+        for (index, param) in self.params.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+
+            write!(f, "{}: {}", param, self.value_type(*param))?;
+        }
+
+        writeln!(f, ") -> {} {{", self.return_type)?;
+
+        for (index, slot) in self.stack_slots.iter().enumerate() {
+            writeln!(f, "    {} = stack {}, align {}", SlotId(index as u32), slot.size, slot.align)?;
+        }
+
+        for block_id in self.block_ids() {
+            let block = self.block(block_id);
+
+            writeln!(f, "{}:", block_id)?;
+
+            for inst in &block.insts {
+                writeln!(f, "    {}", inst)?;
+            }
+
+            match &block.terminator {
+                Some(terminator) => writeln!(f, "    {}", terminator)?,
+                None => writeln!(f, "    <missing terminator>")?,
+            }
+        }
+
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, data) in self.data.iter().enumerate() {
+            writeln!(
+                f,
+                "{} = {:?}",
+                DataId(index as u32),
+                String::from_utf8_lossy(&data.bytes)
+            )?;
+        }
+
+        for external in &self.externals {
+            write!(f, "declare ")?;
+
+            if external.convention == CallingConvention::Stdcall {
+                write!(f, "stdcall ")?;
+            }
+
+            write!(f, "{} @{}(", external.return_type, external.name)?;
+
+            for (index, param) in external.params.iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+
+                write!(f, "{}", param)?;
+            }
+
+            write!(f, ")")?;
+
+            if let Some(library) = &external.library {
+                write!(f, " from \"{}\"", library)?;
+            }
+
+            writeln!(f)?;
+        }
+
+        for function in &self.functions {
+            writeln!(f)?;
+            write!(f, "{}", function)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, values: &[Value]) -> fmt::Result {
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+
+        write!(f, "{}", value)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use super::Type;
+
+// Size and alignment in bytes
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Layout {
+    pub size: u32,
+    pub align: u32,
+}
+
+impl Layout {
+    pub fn scalar(ty: Type) -> Self {
+        let size = ty.bits() / 8;
+
+        Self { size, align: size.max(1) }
+    }
+
+    // The offsets of `fields` and the layout of the whole struct, like C:
+    // every field goes to the next offset aligned for it, in order, and the
+    // size is rounded up to the largest alignment so arrays stay aligned
+    pub fn of_struct(fields: &[Layout]) -> (Vec<u32>, Layout) {
+        let mut offsets = Vec::new();
+        let mut size = 0;
+        let mut align = 1;
+
+        for field in fields {
+            let offset = align_to(size, field.align);
+
+            offsets.push(offset);
+            size = offset + field.size;
+            align = align.max(field.align);
+        }
+
+        (offsets, Layout { size: align_to(size, align), align })
+    }
+}
+
+pub fn align_to(offset: u32, align: u32) -> u32 {
+    offset.div_ceil(align) * align
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::layout::*;
+
+    #[test]
+    fn test_struct_layout() {
+        let (offsets, layout) = Layout::of_struct(&[
+            Layout::scalar(Type::I8),
+            Layout::scalar(Type::I32),
+            Layout::scalar(Type::I16),
+        ]);
+
+        assert_eq!(vec![0, 4, 8], offsets);
+        assert_eq!(Layout { size: 12, align: 4 }, layout);
+
+        let (offsets, layout) = Layout::of_struct(&[Layout::scalar(Type::I64), layout, Layout::scalar(Type::I8)]);
+
+        assert_eq!(vec![0, 8, 20], offsets);
+        assert_eq!(Layout { size: 24, align: 8 }, layout);
+
+        assert_eq!((Vec::new(), Layout { size: 0, align: 1 }), Layout::of_struct(&[]));
+    }
+}
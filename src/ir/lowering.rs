@@ -0,0 +1,2966 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::parser;
+use crate::parser::{BinaryOperator, Expression, Statement, UnaryOperator};
+
+use super::builder::FunctionBuilder;
+use super::layout::{align_to, Layout};
+use super::modules::{ModuleId, ModuleTree, Namespace, ResolveError, ROOT};
+use super::patterns::{self, PatternError, PatternType};
+use super::{BinaryOp, BlockId, CallingConvention, ExternalFunction, Function, Module, Type, UnaryOp, Value};
+
+// The message of the trap taken by an out of bounds index
+const BOUNDS_CHECK_MESSAGE: &[u8] = b"index out of bounds\n";
+
+// How deeply instances of generics can be nested, instances which keep
+// instantiating larger types would never end
+const INSTANTIATION_LIMIT: usize = 64;
+
+// Functions built into the compiler, user functions with the same names
+// hide them
+const INTRINSICS: &[&str] = &["size_of", "cast"];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LoweringError {
+    pub message: String,
+}
+
+impl LoweringError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl fmt::Display for LoweringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+pub struct LoweringOptions {
+    pub bounds_checks: bool, // trap on out of bounds indices, turned off for release builds
+}
+
+impl Default for LoweringOptions {
+    fn default() -> Self {
+        Self { bounds_checks: true }
+    }
+}
+
+// The type of a value in the source language. Structs, enums, arrays and
+// slices live in memory, their values are the addresses of their storage
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Ty {
+    Scalar(Type), // signed integers, and void
+    Unsigned(Type), // converts to and from the signed integer of the same size like in C
+    Struct(String),
+    Enum(String),
+    Array(Box<Ty>, u32),
+    Slice(Box<Ty>), // the address of the first element followed by the length as i32
+    Pointer(Box<Ty>), // raw pointers don't track mutability, like in C
+    Reference(bool, Box<Ty>), // mutable when true
+    Null, // the type of `null`, which converts to any pointer
+}
+
+impl Ty {
+    fn ir_type(&self) -> Type {
+        match self {
+            Ty::Scalar(ty) | Ty::Unsigned(ty) => *ty,
+            Ty::Struct(_) | Ty::Enum(_) | Ty::Array(..) | Ty::Slice(_) => Type::Ptr,
+            Ty::Pointer(_) | Ty::Reference(..) | Ty::Null => Type::Ptr,
+        }
+    }
+
+    fn is_integer(&self) -> bool {
+        match self {
+            Ty::Scalar(ty) => ty.is_integer(),
+            Ty::Unsigned(_) => true,
+            _ => false,
+        }
+    }
+
+    fn in_memory(&self) -> bool {
+        matches!(self, Ty::Struct(_) | Ty::Enum(_) | Ty::Array(..) | Ty::Slice(_))
+    }
+
+    // What a pointer or a reference points to
+    fn pointee(&self) -> Option<&Ty> {
+        match self {
+            Ty::Pointer(pointee) | Ty::Reference(_, pointee) => Some(pointee),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Ty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ty::Scalar(ty) => write!(f, "{}", ty),
+            Ty::Unsigned(ty) => write!(f, "u{}", ty.bits()),
+            Ty::Struct(name) | Ty::Enum(name) => write!(f, "{}", name),
+            Ty::Array(element, length) => write!(f, "[{}; {}]", element, length),
+            Ty::Slice(element) => write!(f, "&[{}]", element),
+            Ty::Pointer(pointee) => write!(f, "*{}", pointee),
+            Ty::Reference(false, referent) => write!(f, "&{}", referent),
+            Ty::Reference(true, referent) => write!(f, "&mut {}", referent),
+            Ty::Null => write!(f, "null"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct StructField {
+    name: String,
+    ty: Ty,
+    offset: u32,
+}
+
+struct StructInfo {
+    fields: Vec<StructField>,
+    layout: Layout,
+}
+
+impl StructInfo {
+    fn field(&self, name: &str) -> Option<&StructField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+// The tag is an i32 at offset 0, the payload of each variant is laid out
+// after it like the fields of a struct
+struct EnumVariant {
+    name: String,
+    fields: Vec<(Ty, u32)>, // types and offsets
+}
+
+struct EnumInfo {
+    variants: Vec<EnumVariant>,
+    layout: Layout,
+}
+
+impl EnumInfo {
+    fn variant(&self, name: &str) -> Option<(usize, &EnumVariant)> {
+        self.variants.iter().enumerate().find(|(_, variant)| variant.name == name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum TypeKind {
+    Struct,
+    Enum,
+}
+
+impl fmt::Display for TypeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TypeKind::Struct => write!(f, "struct"),
+            TypeKind::Enum => write!(f, "enum"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Definition<'ast> {
+    Struct(&'ast parser::Struct),
+    Enum(&'ast parser::Enum),
+}
+
+impl Definition<'_> {
+    fn kind(self) -> TypeKind {
+        match self {
+            Definition::Struct(_) => TypeKind::Struct,
+            Definition::Enum(_) => TypeKind::Enum,
+        }
+    }
+
+    fn generics(&self) -> &[String] {
+        match self {
+            Definition::Struct(definition) => &definition.generics,
+            Definition::Enum(definition) => &definition.generics,
+        }
+    }
+}
+
+// The structs and enums of the program by their qualified names, with the
+// modules they are in. A generic one is instantiated for each list of type
+// arguments it is used with, and `Pair<i32>` is laid out like a struct of
+// its own with that name
+#[derive(Default)]
+struct Definitions<'ast> {
+    modules: ModuleTree<'ast>,
+    items: HashMap<String, (Definition<'ast>, ModuleId)>,
+    instances: HashMap<String, (String, Vec<Ty>)>, // instances of generic definitions, with their type arguments
+    pending: Vec<(String, Pending)>, // instantiated but not laid out yet
+    structs: HashMap<String, StructInfo>,
+    enums: HashMap<String, EnumInfo>,
+    depth: usize, // of instances which are instantiating the types of their fields
+}
+
+// A definition waiting for the types it contains to be laid out
+#[derive(Clone)]
+enum Pending {
+    Struct(Vec<(String, Ty)>),
+    Enum(Vec<(String, Vec<Ty>)>),
+}
+
+impl<'ast> Definitions<'ast> {
+    // Instantiates and lays out the definitions which are not generic, so
+    // errors in them are found even if they are not used
+    fn new(ast: &'ast parser::Module) -> Result<Self, LoweringError> {
+        let modules = ModuleTree::new(ast).map_err(LoweringError::new)?;
+        let mut definitions = Definitions { modules, ..Definitions::default() };
+        let mut concrete = Vec::new();
+
+        for (module, info) in definitions.modules.modules().iter().enumerate() {
+            for item in info.items {
+                let (name, definition) = match item {
+                    parser::Item::Struct(definition) => (&definition.name, Definition::Struct(definition)),
+                    parser::Item::Enum(definition) => (&definition.name, Definition::Enum(definition)),
+                    _ => continue,
+                };
+
+                let name = definitions.modules.qualify(module, name);
+
+                if definition.generics().is_empty() {
+                    concrete.push(name.clone());
+                }
+
+                definitions.items.insert(name, (definition, module));
+            }
+        }
+
+        for name in concrete {
+            definitions.instantiate(&name, Vec::new())?;
+        }
+
+        definitions.lay_out()?;
+        Ok(definitions)
+    }
+
+    // `params` are the type arguments of the generic function or definition
+    // the type is in, and `module` is the module it is written in
+    fn lower_type(&mut self, type_: &parser::Type, params: &HashMap<String, Ty>, module: ModuleId) -> Result<Ty, LoweringError> {
+        let ty = self.resolve(type_, params, module)?;
+        self.lay_out()?;
+        Ok(ty)
+    }
+
+    // Like `lower_type`, but the structs and enums in the type can be left
+    // to be laid out
+    fn resolve(&mut self, type_: &parser::Type, params: &HashMap<String, Ty>, module: ModuleId) -> Result<Ty, LoweringError> {
+        let (name, arguments) = match type_ {
+            parser::Type::Named(name) => (name, &[][..]),
+            parser::Type::Generic(name, arguments) => (name, arguments.as_slice()),
+
+            parser::Type::Pointer(_, pointee) => return Ok(Ty::Pointer(Box::new(self.resolve(pointee, params, module)?))),
+
+            parser::Type::Reference(mutable, referent) => {
+                return Ok(Ty::Reference(*mutable, Box::new(self.resolve(referent, params, module)?)));
+            },
+
+            parser::Type::Array(element, length) => {
+                let element = self.resolve(element, params, module)?;
+
+                return match u32::try_from(*length) {
+                    Ok(length) => Ok(Ty::Array(Box::new(element), length)),
+                    Err(_) => Err(LoweringError::new(format!("array length {} is too large", length))),
+                };
+            },
+
+            parser::Type::Slice(element) => return Ok(Ty::Slice(Box::new(self.resolve(element, params, module)?))),
+        };
+
+        if arguments.is_empty() {
+            if let Some(ty) = params.get(name) {
+                return Ok(ty.clone());
+            }
+
+            if let Some(ty) = integer_type(name) {
+                if ty.ir_type() == Type::I64 {
+                    return Err(LoweringError::new(format!("type `{}` is not supported, the i386 backend has no 64-bit integers", name)));
+                }
+
+                return Ok(ty);
+            }
+        }
+
+        let name = match self.modules.resolve_name(module, name, Namespace::Type) {
+            Ok(name) => name,
+            Err(ResolveError::NotFound) => return Err(LoweringError::new(format!("unknown type `{}`", name))),
+            Err(ResolveError::Other(message)) => return Err(LoweringError::new(message)),
+        };
+
+        let arguments = arguments
+            .iter()
+            .map(|argument| self.resolve(argument, params, module))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.instantiate(&name, arguments)
+    }
+
+    // The instance is registered before the types of its fields are
+    // resolved, so that they can refer to it through pointers and slices
+    fn instantiate(&mut self, name: &str, arguments: Vec<Ty>) -> Result<Ty, LoweringError> {
+        let (definition, module) = self.items[name];
+        let generics = definition.generics();
+
+        if generics.len() != arguments.len() {
+            return Err(LoweringError::new(format!(
+                "{} `{}` takes {} type arguments but {} were given",
+                definition.kind(),
+                name,
+                generics.len(),
+                arguments.len()
+            )));
+        }
+
+        let instance = if arguments.is_empty() {
+            name.to_string()
+        } else {
+            let arguments: Vec<String> = arguments.iter().map(Ty::to_string).collect();
+            format!("{}<{}>", name, arguments.join(", "))
+        };
+
+        let ty = match definition {
+            Definition::Struct(_) => Ty::Struct(instance.clone()),
+            Definition::Enum(_) => Ty::Enum(instance.clone()),
+        };
+
+        let known = self.structs.contains_key(&instance)
+            || self.enums.contains_key(&instance)
+            || self.pending.iter().any(|(pending, _)| *pending == instance);
+
+        if known {
+            return Ok(ty);
+        }
+
+        // `struct S<T> { next: *const S<Pair<T>> }` would never end
+        if self.depth == INSTANTIATION_LIMIT {
+            return Err(LoweringError::new(format!("reached the instantiation limit while instantiating `{}`", instance)));
+        }
+
+        let params: HashMap<String, Ty> = generics.iter().cloned().zip(arguments.iter().cloned()).collect();
+
+        if !arguments.is_empty() {
+            self.instances.insert(instance.clone(), (name.to_string(), arguments));
+        }
+
+        let index = self.pending.len();
+        self.pending.push((instance.clone(), Pending::Struct(Vec::new())));
+        self.depth += 1;
+
+        let pending = match definition {
+            Definition::Struct(definition) => {
+                let mut fields: Vec<(String, Ty)> = Vec::new();
+
+                for field in &definition.fields {
+                    if fields.iter().any(|(name, _)| *name == field.name) {
+                        return Err(LoweringError::new(format!("field `{}` is declared more than once in `{}`", field.name, definition.name)));
+                    }
+
+                    fields.push((field.name.clone(), self.resolve(&field.type_, &params, module)?));
+                }
+
+                Pending::Struct(fields)
+            },
+
+            Definition::Enum(definition) => {
+                let mut variants: Vec<(String, Vec<Ty>)> = Vec::new();
+
+                for variant in &definition.variants {
+                    if variants.iter().any(|(name, _)| *name == variant.name) {
+                        return Err(LoweringError::new(format!("variant `{}` is declared more than once in `{}`", variant.name, definition.name)));
+                    }
+
+                    let fields = variant.fields
+                        .iter()
+                        .map(|type_| self.resolve(type_, &params, module))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    variants.push((variant.name.clone(), fields));
+                }
+
+                Pending::Enum(variants)
+            },
+        };
+
+        self.depth -= 1;
+        self.pending[index].1 = pending;
+
+        Ok(ty)
+    }
+
+    // Lays out what is instantiated so far, each definition after the types
+    // it contains. One which is reached again while it is being laid out
+    // contains itself
+    fn lay_out(&mut self) -> Result<(), LoweringError> {
+        while let Some((name, _)) = self.pending.first() {
+            let name = name.clone();
+            self.lay_out_definition(&name, &mut Vec::new())?;
+        }
+
+        Ok(())
+    }
+
+    fn lay_out_definition(&mut self, name: &str, visiting: &mut Vec<String>) -> Result<Layout, LoweringError> {
+        if let Some(info) = self.structs.get(name) {
+            return Ok(info.layout);
+        }
+
+        if let Some(info) = self.enums.get(name) {
+            return Ok(info.layout);
+        }
+
+        let (_, pending) = self.pending.iter().find(|(pending, _)| pending == name).unwrap().clone();
+
+        if visiting.iter().any(|visited| visited == name) {
+            let kind = match pending {
+                Pending::Struct(_) => TypeKind::Struct,
+                Pending::Enum(_) => TypeKind::Enum,
+            };
+
+            return Err(LoweringError::new(format!("{} `{}` contains itself and has infinite size", kind, name)));
+        }
+
+        visiting.push(name.to_string());
+
+        let layout = match pending {
+            Pending::Struct(fields) => {
+                let layouts = fields
+                    .iter()
+                    .map(|(_, ty)| self.lay_out_type(ty, visiting))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let (offsets, layout) = Layout::of_struct(&layouts);
+
+                let fields = fields
+                    .into_iter()
+                    .zip(offsets)
+                    .map(|((name, ty), offset)| StructField { name, ty, offset })
+                    .collect();
+
+                self.structs.insert(name.to_string(), StructInfo { fields, layout });
+                layout
+            },
+
+            // As large as the largest variant
+            Pending::Enum(variants) => {
+                let mut laid_out = Vec::new();
+                let mut layout = Layout::scalar(Type::I32);
+
+                for (variant_name, fields) in variants {
+                    let mut layouts = vec![Layout::scalar(Type::I32)];
+
+                    for ty in &fields {
+                        layouts.push(self.lay_out_type(ty, visiting)?);
+                    }
+
+                    let (offsets, variant_layout) = Layout::of_struct(&layouts);
+
+                    layout.size = layout.size.max(variant_layout.size);
+                    layout.align = layout.align.max(variant_layout.align);
+
+                    laid_out.push(EnumVariant {
+                        name: variant_name,
+                        fields: fields.into_iter().zip(offsets[1..].iter().copied()).collect(),
+                    });
+                }
+
+                layout.size = align_to(layout.size, layout.align);
+
+                self.enums.insert(name.to_string(), EnumInfo { variants: laid_out, layout });
+                layout
+            },
+        };
+
+        visiting.pop();
+        self.pending.retain(|(pending, _)| pending != name);
+
+        Ok(layout)
+    }
+
+    fn lay_out_type(&mut self, ty: &Ty, visiting: &mut Vec<String>) -> Result<Layout, LoweringError> {
+        match ty {
+            Ty::Struct(name) | Ty::Enum(name) => self.lay_out_definition(name, visiting),
+
+            Ty::Array(element, length) => {
+                let element = self.lay_out_type(element, visiting)?;
+                Ok(Layout { size: element.size * length, align: element.align })
+            },
+
+            // Pointees don't have to be laid out yet, so a struct can point to itself
+            _ => Ok(self.layout_of(ty)),
+        }
+    }
+
+    // Of a type which is laid out already
+    fn layout_of(&self, ty: &Ty) -> Layout {
+        match ty {
+            Ty::Scalar(ty) | Ty::Unsigned(ty) => Layout::scalar(*ty),
+            Ty::Struct(name) => self.structs[name].layout,
+            Ty::Enum(name) => self.enums[name].layout,
+
+            // The size of the element is a multiple of its alignment already
+            Ty::Array(element, length) => {
+                let element = self.layout_of(element);
+                Layout { size: element.size * length, align: element.align }
+            },
+
+            Ty::Slice(_) => Layout { size: 8, align: 4 },
+            Ty::Pointer(_) | Ty::Reference(..) | Ty::Null => Layout::scalar(Type::Ptr),
+        }
+    }
+
+    // `Option` for `Option<i32>`, the definition the instance is of
+    fn generic_name<'n>(&'n self, name: &'n str) -> &'n str {
+        match self.instances.get(name) {
+            Some((generic, _)) => generic,
+            None => name,
+        }
+    }
+
+    // Binds the type parameters in `type_`, written in `module`, by matching
+    // it with `actual`. The first binding of a parameter wins, the values are
+    // type checked against the instance afterwards
+    fn infer(&self, type_: &parser::Type, actual: &Ty, generics: &[String], module: ModuleId, inferred: &mut HashMap<String, Ty>) {
+        match (type_, actual) {
+            // `null` says nothing about the pointee
+            (parser::Type::Named(name), _) if generics.contains(name) && *actual != Ty::Null => {
+                inferred.entry(name.clone()).or_insert_with(|| actual.clone());
+            },
+
+            (parser::Type::Generic(name, arguments), Ty::Struct(instance) | Ty::Enum(instance)) => {
+                if let Some((generic, actual_arguments)) = self.instances.get(instance) {
+                    if self.modules.resolve_name(module, name, Namespace::Type).is_ok_and(|name| name == *generic) {
+                        for (type_, actual) in arguments.iter().zip(actual_arguments) {
+                            self.infer(type_, actual, generics, module, inferred);
+                        }
+                    }
+                }
+            },
+
+            (parser::Type::Array(element, _), Ty::Array(actual, _)) | (parser::Type::Slice(element), Ty::Slice(actual)) => {
+                self.infer(element, actual, generics, module, inferred);
+            },
+
+            // A reference converts to a raw pointer
+            (parser::Type::Pointer(_, pointee), Ty::Pointer(actual) | Ty::Reference(_, actual))
+            | (parser::Type::Reference(_, pointee), Ty::Reference(_, actual)) => {
+                self.infer(pointee, actual, generics, module, inferred);
+            },
+
+            _ => {},
+        }
+    }
+
+    // The symbol of an instance of a generic function, `max$i` for
+    // `max::<i32>`. `$` is not allowed in names, so the symbols cannot clash
+    // with the functions of the module
+    fn mangle(&self, name: &str, arguments: &[Ty]) -> String {
+        let mut symbol = format!("{}$", name);
+
+        for argument in arguments {
+            self.mangle_type(argument, &mut symbol);
+        }
+
+        symbol
+    }
+
+    // Codes like the Itanium C++ ABI, which stay unambiguous when they are
+    // concatenated: `i` is i32, `4PairIiE` is `Pair<i32>`, `A4_i` is
+    // `[i32; 4]`, `Si` is `&[i32]`, `Pi` is `*i32` and `RKi` is `&i32`
+    fn mangle_type(&self, ty: &Ty, symbol: &mut String) {
+        match ty {
+            Ty::Scalar(ty) => symbol.push(match ty {
+                Type::Void => 'v',
+                Type::I8 => 'a',
+                Type::I16 => 's',
+                Type::I32 => 'i',
+                Type::I64 => 'x',
+                Type::Ptr => 'p',
+            }),
+
+            Ty::Unsigned(ty) => symbol.push(match ty {
+                Type::I8 => 'h',
+                Type::I16 => 't',
+                Type::I32 => 'j',
+                _ => 'y',
+            }),
+
+            Ty::Struct(name) | Ty::Enum(name) => match self.instances.get(name) {
+                Some((generic, arguments)) => {
+                    symbol.push_str(&format!("{}{}I", generic.len(), generic));
+
+                    for argument in arguments {
+                        self.mangle_type(argument, symbol);
+                    }
+
+                    symbol.push('E');
+                },
+
+                None => symbol.push_str(&format!("{}{}", name.len(), name)),
+            },
+
+            Ty::Array(element, length) => {
+                symbol.push_str(&format!("A{}_", length));
+                self.mangle_type(element, symbol);
+            },
+
+            Ty::Slice(element) => {
+                symbol.push('S');
+                self.mangle_type(element, symbol);
+            },
+
+            Ty::Pointer(pointee) => {
+                symbol.push('P');
+                self.mangle_type(pointee, symbol);
+            },
+
+            Ty::Reference(mutable, referent) => {
+                symbol.push_str(if *mutable { "R" } else { "RK" });
+                self.mangle_type(referent, symbol);
+            },
+
+            Ty::Null => symbol.push_str("Dn"),
+        }
+    }
+}
+
+// Where a part of a `match` scrutinee is: integers may be values, enums
+// and everything in them are in memory
+#[derive(Clone, Copy)]
+enum Place {
+    Value(Value),
+    Memory(Value, i32), // address and offset
+}
+
+struct Signature {
+    params: Vec<Ty>,
+    return_type: Ty,
+}
+
+// What a call can refer to. A generic function is lowered for each list of
+// type arguments it is called with
+enum Callee<'ast> {
+    Function(Signature),
+    Generic(&'ast parser::Function, ModuleId), // with the module it is defined in
+}
+
+// A generic function with the type arguments of a call to it. `depth`
+// counts the instances it was requested through
+#[derive(Clone)]
+struct Instance {
+    name: String,
+    arguments: Vec<Ty>,
+    depth: usize,
+}
+
+pub fn lower_module(ast: &parser::Module, options: &LoweringOptions) -> Result<Module, LoweringError> {
+    let mut module = Module::new();
+    let mut definitions = Definitions::new(ast)?;
+    let functions = resolve_items(&mut module, &mut definitions)?;
+    let mut instances = Vec::new();
+
+    for id in 0..definitions.modules.modules().len() {
+        for item in definitions.modules.modules()[id].items {
+            if let parser::Item::Function(function) = item {
+                if function.generics.is_empty() {
+                    let lowered = FunctionLowering::lower(&mut module, &mut definitions, &functions, &mut instances, options, (function, id), None)?;
+                    module.functions.push(lowered);
+                }
+            }
+        }
+    }
+
+    // Instances call other instances, which are added to the end. The body
+    // of a generic function is only checked with the type arguments it is
+    // called with
+    let mut next = 0;
+
+    while let Some(instance) = instances.get(next).cloned() {
+        let Callee::Generic(function, id) = functions[&instance.name] else { unreachable!() };
+
+        let lowered = FunctionLowering::lower(&mut module, &mut definitions, &functions, &mut instances, options, (function, id), Some(&instance))?;
+        module.functions.push(lowered);
+        next += 1;
+    }
+
+    Ok(module)
+}
+
+// Collects the signature of every function defined or declared in the
+// program by its qualified name, so calls can refer to functions which come
+// later. Functions from `extern` blocks are added to the module as externals
+fn resolve_items<'ast>(
+    module: &mut Module,
+    definitions: &mut Definitions<'ast>
+) -> Result<HashMap<String, Callee<'ast>>, LoweringError> {
+    let mut functions = HashMap::new();
+    let modules: Vec<_> = definitions.modules.modules().iter().map(|module| module.items).collect();
+
+    for (id, items) in modules.into_iter().enumerate() {
+        for item in items {
+            let declared = match item {
+                parser::Item::Function(function) if !function.generics.is_empty() => {
+                    vec![(definitions.modules.qualify(id, &function.name), Callee::Generic(function, id))]
+                },
+
+                parser::Item::Function(function) => {
+                    let signature = lower_signature(definitions, &HashMap::new(), &function.parameters, &function.return_type, id)?;
+                    vec![(definitions.modules.qualify(id, &function.name), Callee::Function(signature))]
+                },
+
+                parser::Item::Struct(_) | parser::Item::Enum(_) | parser::Item::Module(_) | parser::Item::Use(_) => continue,
+
+                parser::Item::Extern(block) => {
+                    let convention = match block.abi.as_str() {
+                        "C" | "cdecl" => CallingConvention::Cdecl,
+                        "stdcall" => CallingConvention::Stdcall,
+
+                        //
+                        abi => return Err(LoweringError::new(format!("unknown calling convention `{}`", abi))),
+                    };
+
+                    let mut declared = Vec::new();
+
+                    for function in &block.functions {
+                        let signature = lower_signature(definitions, &HashMap::new(), &function.parameters, &function.return_type, id)?;
+
+                        // C passes structs by value, which is not supported
+                        if let Some(ty) = signature.params.iter().find(|ty| ty.in_memory()) {
+                            return Err(LoweringError::new(format!(
+                                "extern function `{}` cannot take `{}` by value, take a pointer instead",
+                                function.name,
+                                ty
+                            )));
+                        }
+
+                        let external = ExternalFunction {
+                            name: function.name.clone(),
+                            params: signature.params.iter().map(Ty::ir_type).collect(),
+                            return_type: signature.return_type.ir_type(),
+                            convention,
+                            library: block.library.clone(),
+                        };
+
+                        // Several modules can declare the same external function
+                        if let Some(existing) = module.externals.iter().find(|existing| existing.name == external.name) {
+                            if *existing != external {
+                                return Err(LoweringError::new(format!(
+                                    "extern function `{}` is declared differently in another module",
+                                    function.name
+                                )));
+                            }
+
+                            continue;
+                        }
+
+                        module.externals.push(external);
+                        declared.push((function.name.clone(), Callee::Function(signature)));
+                    }
+
+                    declared
+                },
+            };
+
+            for (name, callee) in declared {
+                if functions.insert(name.clone(), callee).is_some() {
+                    return Err(LoweringError::new(format!("function `{}` is defined more than once", name)));
+                }
+            }
+        }
+    }
+
+    Ok(functions)
+}
+
+// Structs, enums, arrays and slices are passed by pointer to the caller's
+// value, the callee cannot modify it because parameters are immutable
+fn lower_signature(
+    definitions: &mut Definitions,
+    params: &HashMap<String, Ty>,
+    parameters: &[parser::Parameter],
+    return_type: &Option<parser::Type>,
+    module: ModuleId
+) -> Result<Signature, LoweringError> {
+    let param_types = parameters
+        .iter()
+        .map(|parameter| definitions.lower_type(&parameter.type_, params, module))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let return_type = match return_type {
+        Some(type_) => match definitions.lower_type(type_, params, module)? {
+            ty if ty.in_memory() => return Err(LoweringError::new(format!("returning `{}` is not supported yet", ty))),
+            ty => ty,
+        },
+
+        None => Ty::Scalar(Type::Void),
+    };
+
+    Ok(Signature { params: param_types, return_type })
+}
+
+fn integer_type(name: &str) -> Option<Ty> {
+    match name {
+        "i8" => Some(Ty::Scalar(Type::I8)),
+        "i16" => Some(Ty::Scalar(Type::I16)),
+        "i32" => Some(Ty::Scalar(Type::I32)),
+        "i64" => Some(Ty::Scalar(Type::I64)),
+        "u8" => Some(Ty::Unsigned(Type::I8)),
+        "u16" => Some(Ty::Unsigned(Type::I16)),
+        "u32" => Some(Ty::Unsigned(Type::I32)),
+        "u64" => Some(Ty::Unsigned(Type::I64)),
+        _ => None,
+    }
+}
+
+struct FunctionLowering<'a, 'b, 'ast> {
+    module: &'a mut Module,
+    definitions: &'a mut Definitions<'ast>,
+    functions: &'a HashMap<String, Callee<'ast>>,
+    instances: &'a mut Vec<Instance>, // of generic functions, to be lowered after this one
+    options: &'a LoweringOptions,
+    module_id: ModuleId, // where the function is defined, names in it are resolved from there
+    type_params: HashMap<String, Ty>, // the type arguments of an instance
+    depth: usize,
+    builder: FunctionBuilder<'b>,
+    scope: HashMap<String, Value>,
+    mutable: HashSet<String>, // variables declared with `let mut`
+    addressed: HashSet<String>, // variables whose address is taken somewhere in the function
+    spilled: HashMap<Value, Ty>, // stack slots of variables whose address is taken, with their types
+    types: HashMap<Value, Ty>, // values which are not plain scalars with their types
+}
+
+impl<'a, 'b, 'ast> FunctionLowering<'a, 'b, 'ast> {
+    // `ast` is lowered as a function of the module `module_id`. An instance
+    // of a generic function gets the mangled name, and is not exported even
+    // if the function is public, neither are functions in modules
+    fn lower(
+        module: &'a mut Module,
+        definitions: &'a mut Definitions<'ast>,
+        functions: &'a HashMap<String, Callee<'ast>>,
+        instances: &'a mut Vec<Instance>,
+        options: &'a LoweringOptions,
+        (ast, module_id): (&parser::Function, ModuleId),
+        instance: Option<&Instance>
+    ) -> Result<Function, LoweringError> {
+        let (name, type_params, depth) = match instance {
+            Some(instance) => (
+                definitions.mangle(&instance.name, &instance.arguments),
+                ast.generics.iter().cloned().zip(instance.arguments.iter().cloned()).collect(),
+                instance.depth,
+            ),
+
+            None => (definitions.modules.qualify(module_id, &ast.name), HashMap::new(), 0),
+        };
+
+        let signature = lower_signature(definitions, &type_params, &ast.parameters, &ast.return_type, module_id)?;
+        let mut function = Function::new(&name, signature.return_type.ir_type());
+        function.exported = ast.public && instance.is_none() && module_id == ROOT;
+
+        let mut lowering = FunctionLowering {
+            module,
+            definitions,
+            functions,
+            instances,
+            options,
+            module_id,
+            type_params,
+            depth,
+            builder: FunctionBuilder::new(&mut function),
+            scope: HashMap::new(),
+            mutable: HashSet::new(),
+            addressed: HashSet::new(),
+            spilled: HashMap::new(),
+            types: HashMap::new(),
+        };
+
+        for statement in &ast.body {
+            collect_addressed(statement, &mut lowering.addressed);
+        }
+
+        let entry = lowering.builder.create_block();
+        lowering.builder.switch_to_block(entry);
+
+        for (parameter, ty) in ast.parameters.iter().zip(&signature.params) {
+            let value = lowering.builder.add_param(ty.ir_type());
+            let value = lowering.typed(value, ty.clone());
+
+            lowering.declare(&parameter.name, value);
+        }
+
+        let mut reachable = true;
+
+        for statement in &ast.body {
+            if lowering.builder.is_terminated() {
+                // Code after `return` goes into a block without predecessors
+                let dead = lowering.builder.create_block();
+                lowering.builder.switch_to_block(dead);
+                reachable = false;
+            }
+
+            lowering.lower_statement(statement, &signature.return_type)?;
+        }
+
+        if !lowering.builder.is_terminated() {
+            if signature.return_type == Ty::Scalar(Type::Void) {
+                lowering.builder.ret(None);
+            } else if reachable {
+                return Err(LoweringError::new(format!("missing `return` at the end of `{}`", ast.name)));
+            } else {
+                lowering.builder.unreachable();
+            }
+        }
+
+        Ok(function)
+    }
+
+    fn lower_statement(&mut self, statement: &Statement, return_type: &Ty) -> Result<(), LoweringError> {
+        match statement {
+            Statement::Let(name, mutable, type_, expression) => {
+                let value = match type_ {
+                    Some(type_) => {
+                        let expected = self.lower_type(type_)?;
+                        let value = self.lower_expected(expression, &expected)?;
+                        self.owned(expression, value)
+                    },
+
+                    None => self.lower_owned(expression)?,
+                };
+
+                if type_.is_none() && self.ty_of(value) == Ty::Null {
+                    return Err(LoweringError::new(format!("cannot infer the type of `{}` from `null`, give it a pointer type", name)));
+                }
+
+                // A new `let` shadows the previous variable and its mutability
+                if *mutable {
+                    self.mutable.insert(name.clone());
+                } else {
+                    self.mutable.remove(name);
+                }
+
+                self.declare(name, value);
+            },
+
+            Statement::Return(Some(expression)) => {
+                let value = self.lower_expected(expression, return_type)?;
+                self.builder.ret(Some(value));
+            },
+
+            Statement::Return(None) => {
+                if *return_type != Ty::Scalar(Type::Void) {
+                    return Err(LoweringError::new(format!("`return` without a value in a function returning {}", return_type)));
+                }
+
+                self.builder.ret(None);
+            },
+
+            Statement::Expression(expression) => {
+                self.lower_expression(expression)?;
+            },
+        }
+
+        Ok(())
+    }
+
+    fn lower_value(&mut self, expression: &Expression) -> Result<Value, LoweringError> {
+        self.lower_expression(expression)?
+            .ok_or_else(|| LoweringError::new("expression does not produce a value".to_string()))
+    }
+
+    // `let` and assignment copy a struct, enum, array or slice held by a
+    // variable, other expressions of these types give a new one already
+    fn lower_owned(&mut self, expression: &Expression) -> Result<Value, LoweringError> {
+        let value = self.lower_value(expression)?;
+        Ok(self.owned(expression, value))
+    }
+
+    fn owned(&mut self, expression: &Expression, value: Value) -> Value {
+        let mut inner = expression;
+
+        while let Expression::Group(expression) = inner {
+            inner = expression;
+        }
+
+        match (self.ty_of(value), inner) {
+            (ty, Expression::Identifier(_)) if ty.in_memory() => self.copy_value(&ty, value, 0),
+            _ => value,
+        }
+    }
+
+    fn lower_expression(&mut self, expression: &Expression) -> Result<Option<Value>, LoweringError> {
+        let value = match expression {
+            Expression::Integer(value, suffix) => self.lower_integer(*value, suffix, false)?,
+
+            Expression::Float(value, suffix) => {
+                let suffix = suffix.as_deref().unwrap_or("");
+                return Err(LoweringError::new(format!("floating-point literal `{}{}` is not supported yet", value, suffix)));
+            },
+
+            Expression::String(s) => {
+                let mut bytes = s.as_bytes().to_vec();
+                bytes.push(0);
+
+                let data = self.module.add_data(bytes);
+                let address = self.builder.data_address(data);
+                self.typed(address, Ty::Pointer(Box::new(Ty::Unsigned(Type::I8))))
+            },
+
+            // Byte strings are not terminated, `b"..\0"` makes a C string
+            Expression::ByteString(bytes) => {
+                let data = self.module.add_data(bytes.clone());
+                let address = self.builder.data_address(data);
+                self.typed(address, Ty::Pointer(Box::new(Ty::Unsigned(Type::I8))))
+            },
+
+            Expression::Null => {
+                let null = self.builder.iconst(Type::Ptr, 0);
+                self.typed(null, Ty::Null)
+            },
+
+            // There is no boolean type yet, booleans are i32 0 or 1 like in C
+            Expression::Boolean(value) => self.builder.iconst(Type::I32, *value as i64),
+
+            // A Unicode scalar value
+            Expression::Char(ch) => self.builder.iconst(Type::I32, *ch as i64),
+
+            Expression::Identifier(name) => match self.scope.get(name).copied() {
+                Some(value) => match self.spilled.get(&value).cloned() {
+                    Some(ty) => self.load_value(&ty, value, 0),
+                    None => value,
+                },
+
+                None => return Err(LoweringError::new(format!("unknown variable `{}`", name))),
+            },
+
+            // `-128i8` is in range although `128i8` is not
+            Expression::Unary(UnaryOperator::Minus, operand) if matches!(**operand, Expression::Integer(..)) => {
+                let Expression::Integer(value, suffix) = &**operand else { unreachable!() };
+                self.lower_integer(*value, suffix, true)?
+            },
+
+            Expression::Unary(UnaryOperator::Reference, operand) => self.lower_reference(operand, false)?,
+            Expression::Unary(UnaryOperator::ReferenceMut, operand) => self.lower_reference(operand, true)?,
+
+            // Like a field, a struct behind the pointer is copied out
+            Expression::Unary(UnaryOperator::Deref, _) => {
+                let (address, offset, ty) = self.lower_place(expression, false)?;
+                self.load_value(&ty, address, offset)
+            },
+
+            Expression::Unary(operator, operand) => {
+                let operand = self.lower_value(operand)?;
+                self.expect_integer(operand)?;
+
+                let ty = self.ty_of(operand);
+
+                match operator {
+                    UnaryOperator::Plus => operand,
+
+                    UnaryOperator::Minus => {
+                        let negated = self.builder.unary(UnaryOp::Neg, operand);
+                        self.typed(negated, ty)
+                    },
+
+                    // Logical not like in C, `!x` is `x == 0`
+                    UnaryOperator::Not => {
+                        let zero = self.builder.iconst(self.builder.value_type(operand), 0);
+                        let result = self.builder.binary(BinaryOp::Eq, operand, zero);
+                        self.typed(result, ty)
+                    },
+
+                    UnaryOperator::Reference | UnaryOperator::ReferenceMut | UnaryOperator::Deref => unreachable!(),
+                }
+            },
+
+            Expression::Binary(operator @ (BinaryOperator::LogicalAnd | BinaryOperator::LogicalOr), lhs, rhs) => {
+                self.lower_logical(*operator, lhs, rhs)?
+            },
+
+            Expression::Binary(operator, lhs, rhs) => {
+                let lhs = self.lower_value(lhs)?;
+                let rhs = self.lower_value(rhs)?;
+
+                self.lower_binary(*operator, lhs, rhs)?
+            },
+
+            Expression::Assign(operator, target, value) => {
+                self.lower_assign(*operator, target, value)?;
+                return Ok(None);
+            },
+
+            // `len()` of arrays and slices is the only method
+            Expression::Call(callee, arguments) if matches!(&**callee, Expression::Field(_, name) if name == "len") => {
+                let Expression::Field(base, _) = &**callee else { unreachable!() };
+
+                if !arguments.is_empty() {
+                    return Err(LoweringError::new(format!("`len` takes 0 arguments but {} were given", arguments.len())));
+                }
+
+                let (address, offset, ty) = match self.lower_base(base, false)? {
+                    (pointer, _, ty) if ty.pointee().is_some() => self.deref(pointer, false)?,
+                    place => place,
+                };
+
+                match ty {
+                    Ty::Array(_, length) => self.builder.iconst(Type::I32, length as i64),
+                    Ty::Slice(_) => self.builder.load(Type::I32, address, offset + 4),
+                    ty => return Err(LoweringError::new(format!("no method `len` on type {}", ty))),
+                }
+            },
+
+            Expression::Call(callee, arguments) => match &**callee {
+                Expression::Identifier(name) => return self.lower_call(name, &[], arguments),
+                Expression::Path(path, types) if self.is_variant(path) => self.lower_variant(path, types, arguments)?,
+                Expression::Path(path, types) => return self.lower_call(&path.join("::"), types, arguments),
+                _ => return Err(LoweringError::new("only functions can be called, by their name".to_string())),
+            },
+
+            Expression::Path(path, types) => self.lower_variant(path, types, &[])?,
+            Expression::Match(scrutinee, arms) => return self.lower_match(scrutinee, arms),
+
+            // Fields and elements which are structs, arrays or slices are
+            // copied out, so that the result has an address of its own
+            Expression::Field(..) | Expression::Index(..) => {
+                let (address, offset, ty) = self.lower_place(expression, false)?;
+                self.load_value(&ty, address, offset)
+            },
+
+            Expression::Group(expression) => return self.lower_expression(expression),
+
+            Expression::Struct(name, types, fields) => self.lower_struct_literal(name, types, fields)?,
+            Expression::Array(elements) => self.lower_array_literal(elements)?,
+        };
+
+        Ok(Some(value))
+    }
+
+    fn lower_binary(&mut self, operator: BinaryOperator, lhs: Value, rhs: Value) -> Result<Value, LoweringError> {
+        if matches!(self.ty_of(lhs), Ty::Pointer(_) | Ty::Reference(..) | Ty::Null) {
+            return self.lower_pointer_binary(operator, lhs, rhs);
+        }
+
+        self.expect_integer(lhs)?;
+
+        let ty = self.ty_of(lhs);
+        let other = self.ty_of(rhs);
+
+        if !other.is_integer() || other.ir_type() != ty.ir_type() {
+            return Err(LoweringError::new(format!("expected {}, found {}", ty, other)));
+        }
+
+        // An operand of the other signedness converts to unsigned like in C,
+        // except for the count of a shift
+        let unsigned = match operator {
+            BinaryOperator::ShiftLeft | BinaryOperator::ShiftRight => matches!(ty, Ty::Unsigned(_)),
+            _ => matches!(ty, Ty::Unsigned(_)) || matches!(other, Ty::Unsigned(_)),
+        };
+
+        let op = match (operator, unsigned) {
+            (BinaryOperator::Plus, _) => BinaryOp::Add,
+            (BinaryOperator::Minus, _) => BinaryOp::Sub,
+            (BinaryOperator::Multiply, _) => BinaryOp::Mul,
+            (BinaryOperator::Divide, false) => BinaryOp::Div,
+            (BinaryOperator::Divide, true) => BinaryOp::Udiv,
+            (BinaryOperator::Remainder, false) => BinaryOp::Rem,
+            (BinaryOperator::Remainder, true) => BinaryOp::Urem,
+            (BinaryOperator::BitwiseAnd, _) => BinaryOp::And,
+            (BinaryOperator::BitwiseOr, _) => BinaryOp::Or,
+            (BinaryOperator::BitwiseXor, _) => BinaryOp::Xor,
+            (BinaryOperator::ShiftLeft, _) => BinaryOp::Shl,
+            (BinaryOperator::ShiftRight, false) => BinaryOp::Sar,
+            (BinaryOperator::ShiftRight, true) => BinaryOp::Shr,
+
+            // Comparisons give 0 or 1 of the operand type like in C
+            (BinaryOperator::Equal, _) => BinaryOp::Eq,
+            (BinaryOperator::NotEqual, _) => BinaryOp::Ne,
+            (BinaryOperator::Less, false) => BinaryOp::Lt,
+            (BinaryOperator::Less, true) => BinaryOp::Ult,
+            (BinaryOperator::LessEqual, false) => BinaryOp::Le,
+            (BinaryOperator::LessEqual, true) => BinaryOp::Ule,
+            (BinaryOperator::Greater, false) => BinaryOp::Gt,
+            (BinaryOperator::Greater, true) => BinaryOp::Ugt,
+            (BinaryOperator::GreaterEqual, false) => BinaryOp::Ge,
+            (BinaryOperator::GreaterEqual, true) => BinaryOp::Uge,
+
+            (BinaryOperator::Power, _) => return Err(LoweringError::new("operator `**` is not supported yet".to_string())),
+
+            (BinaryOperator::LogicalAnd | BinaryOperator::LogicalOr, _) => unreachable!(),
+        };
+
+        let result = self.builder.binary(op, lhs, rhs);
+
+        match unsigned {
+            true => Ok(self.typed(result, Ty::Unsigned(ty.ir_type()))),
+            false => Ok(result),
+        }
+    }
+
+    // `p + n` and `p - n` move a raw pointer by `n` elements, and `p - q` is
+    // the number of elements from `q` to `p`. Pointers and references are
+    // compared by address, to `null` or to pointers of the same type
+    fn lower_pointer_binary(&mut self, operator: BinaryOperator, lhs: Value, rhs: Value) -> Result<Value, LoweringError> {
+        let ty = self.ty_of(lhs);
+        let other = self.ty_of(rhs);
+
+        match (operator, &ty) {
+            (BinaryOperator::Equal | BinaryOperator::NotEqual, _) => {
+                if ty != other && other != Ty::Null && !(ty == Ty::Null && other.pointee().is_some()) {
+                    return Err(LoweringError::new(format!("cannot compare {} with {}", ty, other)));
+                }
+
+                let op = if matches!(operator, BinaryOperator::Equal) { BinaryOp::Eq } else { BinaryOp::Ne };
+                let lhs = self.builder.bitcast(lhs, Type::I32);
+                let rhs = self.builder.bitcast(rhs, Type::I32);
+
+                Ok(self.builder.binary(op, lhs, rhs))
+            },
+
+            (BinaryOperator::Minus, Ty::Pointer(pointee)) if other == ty => {
+                let size = self.layout_of(pointee).size;
+                let lhs = self.builder.bitcast(lhs, Type::I32);
+                let rhs = self.builder.bitcast(rhs, Type::I32);
+                let difference = self.builder.binary(BinaryOp::Sub, lhs, rhs);
+                let size = self.builder.iconst(Type::I32, size as i64);
+
+                Ok(self.builder.binary(BinaryOp::Div, difference, size))
+            },
+
+            (BinaryOperator::Plus | BinaryOperator::Minus, Ty::Pointer(pointee)) => {
+                let rhs = self.coerce(rhs, &Ty::Scalar(Type::I32))?;
+
+                let size = self.layout_of(pointee).size;
+                let offset = match operator {
+                    BinaryOperator::Minus => self.builder.unary(UnaryOp::Neg, rhs),
+                    _ => rhs,
+                };
+
+                let address = self.element_address(lhs, offset, size);
+                Ok(self.typed(address, ty))
+            },
+
+            _ => Err(LoweringError::new(format!("unsupported operation on {}, only raw pointers can be offset and subtracted", ty))),
+        }
+    }
+
+    // `a && b` evaluates `b` only when `a` is not 0:
+    //
+    //     lhs:   branch a, rhs, short     (`||` swaps the targets)
+    //     short: jump end                 (keeps the edge to the phi uncritical)
+    //     rhs:   b != 0, jump end
+    //     end:   phi [short: 0 or 1], [rhs: b != 0]
+    fn lower_logical(&mut self, operator: BinaryOperator, lhs: &Expression, rhs: &Expression) -> Result<Value, LoweringError> {
+        let lhs = self.lower_value(lhs)?;
+        self.expect_integer(lhs)?;
+
+        let ty = self.builder.value_type(lhs);
+        let is_and = matches!(operator, BinaryOperator::LogicalAnd);
+
+        let rhs_block = self.builder.create_block();
+        let short_block = self.builder.create_block();
+        let end_block = self.builder.create_block();
+
+        if is_and {
+            self.builder.branch(lhs, rhs_block, short_block);
+        } else {
+            self.builder.branch(lhs, short_block, rhs_block);
+        }
+
+        self.builder.switch_to_block(short_block);
+        let short_value = self.builder.iconst(ty, !is_and as i64);
+        self.builder.jump(end_block);
+
+        self.builder.switch_to_block(rhs_block);
+        let rhs = self.lower_value(rhs)?;
+        let rhs = self.coerce(rhs, &Ty::Scalar(ty))?;
+        let zero = self.builder.iconst(ty, 0);
+        let rhs = self.builder.binary(BinaryOp::Ne, rhs, zero);
+        let rhs_end = self.builder.current_block().unwrap();
+        self.builder.jump(end_block);
+
+        self.builder.switch_to_block(end_block);
+        Ok(self.builder.phi(ty, vec![(short_block, short_value), (rhs_end, rhs)]))
+    }
+
+    // Assignment rebinds the name to the new SSA value, where branches join
+    // the values are merged with phis. Fields and elements are stored to
+    // memory
+    fn lower_assign(&mut self, operator: Option<BinaryOperator>, target: &Expression, value: &Expression) -> Result<(), LoweringError> {
+        let name = match target {
+            Expression::Identifier(name) => name,
+
+            Expression::Field(..) | Expression::Index(..) | Expression::Unary(UnaryOperator::Deref, _) => {
+                return self.lower_place_assign(operator, target, value);
+            },
+
+            _ => return Err(LoweringError::new("invalid left-hand side of assignment".to_string())),
+        };
+
+        let old = match self.scope.get(name) {
+            Some(value) => *value,
+            None => return Err(LoweringError::new(format!("unknown variable `{}`", name))),
+        };
+
+        if !self.mutable.contains(name) {
+            return Err(LoweringError::new(format!("cannot assign twice to immutable variable `{}`", name)));
+        }
+
+        // A variable in a stack slot is stored to
+        if let Some(ty) = self.spilled.get(&old).cloned() {
+            let value = self.lower_value(value)?;
+
+            let value = match operator {
+                Some(operator) => {
+                    let current = self.load_value(&ty, old, 0);
+                    self.lower_binary(operator, current, value)?
+                },
+
+                None => value,
+            };
+
+            let value = self.coerce(value, &ty)?;
+            self.builder.store(old, 0, value);
+            return Ok(());
+        }
+
+        let value = self.lower_owned(value)?;
+        let ty = self.ty_of(old);
+
+        let value = match operator {
+            Some(operator) => self.lower_binary(operator, old, value)?,
+            None => value,
+        };
+
+        let value = self.coerce(value, &ty)?;
+
+        // References to the storage of the variable must see the new value
+        if self.addressed.contains(name) && ty.in_memory() {
+            self.copy(&ty, old, 0, value, 0);
+            return Ok(());
+        }
+
+        self.scope.insert(name.clone(), value);
+        Ok(())
+    }
+
+    fn lower_place_assign(&mut self, operator: Option<BinaryOperator>, target: &Expression, value: &Expression) -> Result<(), LoweringError> {
+        if let Some(name) = self.root_variable(target) {
+            if self.scope.contains_key(name) && !self.mutable.contains(name) {
+                let part = if let Expression::Index(..) = target { "an element" } else { "a field" };
+                return Err(LoweringError::new(format!("cannot assign to {} of immutable variable `{}`", part, name)));
+            }
+        }
+
+        let (address, offset, ty) = self.lower_place(target, true)?;
+        let value = self.lower_value(value)?;
+
+        match operator {
+            Some(_) if ty.in_memory() => return Err(LoweringError::new(format!("expected an integer, found {}", ty))),
+
+            Some(operator) => {
+                let old = self.load_value(&ty, address, offset);
+                let value = self.lower_binary(operator, old, value)?;
+                let value = self.coerce(value, &ty)?;
+                self.builder.store(address, offset, value);
+            },
+
+            None => {
+                let value = self.coerce(value, &ty)?;
+                self.store_value(&ty, address, offset, value);
+            },
+        }
+
+        Ok(())
+    }
+
+    // The address, offset and type of a field, an element, a pointee or a
+    // variable in a stack slot. Offsets of nested fields add up, so `a.b.c`
+    // is a single memory access. Slices borrow their elements, which cannot
+    // be written through them. Other expressions give their value
+    fn lower_place(&mut self, expression: &Expression, write: bool) -> Result<(Value, i32, Ty), LoweringError> {
+        let (base, field_name) = match expression {
+            Expression::Field(base, field_name) => (base, field_name),
+            Expression::Index(base, index) => return self.lower_element(base, index, write),
+            Expression::Group(expression) => return self.lower_place(expression, write),
+
+            Expression::Unary(UnaryOperator::Deref, pointer) => {
+                let pointer = self.lower_value(pointer)?;
+                return self.deref(pointer, write);
+            },
+
+            Expression::Identifier(name) if self.scope.get(name).is_some_and(|value| self.spilled.contains_key(value)) => {
+                let address = self.scope[name];
+                return Ok((address, 0, self.spilled[&address].clone()));
+            },
+
+            _ => {
+                let value = self.lower_value(expression)?;
+                return Ok((value, 0, self.ty_of(value)));
+            },
+        };
+
+        // `p.x` is the field of the struct `p` points to
+        let (address, offset, ty) = match self.lower_base(base, write)? {
+            (pointer, _, ty) if ty.pointee().is_some() => self.deref(pointer, write)?,
+            place => place,
+        };
+
+        let field = match &ty {
+            Ty::Struct(name) => self.definitions.structs[name].field(field_name),
+            _ => None,
+        };
+
+        match field {
+            Some(field) => Ok((address, offset + field.offset as i32, field.ty.clone())),
+            None => Err(LoweringError::new(format!("no field `{}` on type {}", field_name, ty))),
+        }
+    }
+
+    // base + index * size, after checking the index against the length.
+    // A raw pointer is indexed like in C, without a length to check against
+    fn lower_element(&mut self, base: &Expression, index: &Expression, write: bool) -> Result<(Value, i32, Ty), LoweringError> {
+        let (address, offset, ty) = match self.lower_base(base, write)? {
+            (pointer, _, Ty::Pointer(pointee)) if !matches!(*pointee, Ty::Array(..)) => {
+                let index = self.lower_value(index)?;
+                let index = self.coerce(index, &Ty::Scalar(Type::I32))?;
+
+                let size = self.layout_of(&pointee).size;
+                return Ok((self.element_address(pointer, index, size), 0, *pointee));
+            },
+
+            (pointer, _, ty) if ty.pointee().is_some() => self.deref(pointer, write)?,
+            place => place,
+        };
+
+        let index = self.lower_value(index)?;
+
+        let (data, data_offset, element) = match &ty {
+            Ty::Array(element, _) => (address, offset, (**element).clone()),
+
+            Ty::Slice(_) if write => {
+                return Err(LoweringError::new("cannot assign through a slice, slices are read-only".to_string()));
+            },
+
+            Ty::Slice(element) => (self.builder.load(Type::Ptr, address, offset), 0, (**element).clone()),
+            ty => return Err(LoweringError::new(format!("cannot index into a value of type {}", ty))),
+        };
+
+        let index = self.coerce(index, &Ty::Scalar(Type::I32))?;
+
+        if self.options.bounds_checks {
+            let length = match ty {
+                Ty::Array(_, length) => self.builder.iconst(Type::I32, length as i64),
+                _ => self.builder.load(Type::I32, address, offset + 4),
+            };
+
+            self.check_bounds(index, length);
+        }
+
+        let size = self.layout_of(&element).size;
+        Ok((self.element_address(data, index, size), data_offset, element))
+    }
+
+    // base + index * size. Sizes which x86 addressing cannot scale by are
+    // multiplied first
+    fn element_address(&mut self, base: Value, index: Value, size: u32) -> Value {
+        if [1, 2, 4, 8].contains(&size) {
+            self.builder.element_address(base, index, size)
+        } else {
+            let size = self.builder.iconst(Type::I32, size as i64);
+            let index = self.builder.binary(BinaryOp::Mul, index, size);
+            self.builder.element_address(base, index, 1)
+        }
+    }
+
+    // The place of the struct or array a field or an element is in. A
+    // pointer or a reference there is only read, it is given as its value
+    // and written through by the caller
+    fn lower_base(&mut self, base: &Expression, write: bool) -> Result<(Value, i32, Ty), LoweringError> {
+        let through_pointer = self.type_of_place(base).is_some_and(|ty| ty.pointee().is_some());
+        let in_memory = self.is_memory_place(base);
+        let (address, offset, ty) = self.lower_place(base, write && !through_pointer)?;
+
+        if in_memory && ty.pointee().is_some() {
+            let pointer = self.load_value(&ty, address, offset);
+            return Ok((pointer, 0, ty));
+        }
+
+        Ok((address, offset, ty))
+    }
+
+    // The place a pointer or a reference points to
+    fn deref(&self, pointer: Value, write: bool) -> Result<(Value, i32, Ty), LoweringError> {
+        match self.ty_of(pointer) {
+            Ty::Reference(false, _) if write => Err(LoweringError::new("cannot assign through a `&` reference".to_string())),
+            Ty::Pointer(pointee) | Ty::Reference(_, pointee) => Ok((pointer, 0, *pointee)),
+            Ty::Null => Err(LoweringError::new("cannot dereference `null`".to_string())),
+            ty => Err(LoweringError::new(format!("cannot dereference a value of type {}", ty))),
+        }
+    }
+
+    // Whether `lower_place` gives the address of `expression` rather than
+    // its value
+    fn is_memory_place(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Field(..) | Expression::Index(..) | Expression::Unary(UnaryOperator::Deref, _) => true,
+            Expression::Identifier(name) => self.scope.get(name).is_some_and(|value| self.spilled.contains_key(value)),
+            Expression::Group(expression) => self.is_memory_place(expression),
+            _ => false,
+        }
+    }
+
+    // The type of a place, without lowering it. None for other expressions
+    fn type_of_place(&self, expression: &Expression) -> Option<Ty> {
+        match expression {
+            Expression::Identifier(name) => {
+                let value = self.scope.get(name)?;
+                Some(self.spilled.get(value).cloned().unwrap_or_else(|| self.ty_of(*value)))
+            },
+
+            Expression::Group(expression) => self.type_of_place(expression),
+            Expression::Unary(UnaryOperator::Deref, pointer) => self.type_of_place(pointer)?.pointee().cloned(),
+
+            Expression::Field(base, field_name) => {
+                let ty = self.type_of_place(base)?;
+
+                match ty.pointee().unwrap_or(&ty) {
+                    Ty::Struct(name) => Some(self.definitions.structs[name].field(field_name)?.ty.clone()),
+                    _ => None,
+                }
+            },
+
+            Expression::Index(base, _) => match self.type_of_place(base)? {
+                Ty::Pointer(pointee) if !matches!(*pointee, Ty::Array(..)) => Some(*pointee),
+
+                ty => match ty.pointee().unwrap_or(&ty) {
+                    Ty::Array(element, _) | Ty::Slice(element) => Some((**element).clone()),
+                    _ => None,
+                },
+            },
+
+            _ => None,
+        }
+    }
+
+    // The variable a place is part of, unless it is reached through a
+    // pointer or a reference
+    fn root_variable<'e>(&self, expression: &'e Expression) -> Option<&'e String> {
+        match expression {
+            Expression::Identifier(name) => Some(name),
+            Expression::Group(expression) => self.root_variable(expression),
+
+            Expression::Field(base, _) | Expression::Index(base, _) => {
+                if self.type_of_place(base).is_some_and(|ty| ty.pointee().is_some()) {
+                    None
+                } else {
+                    self.root_variable(base)
+                }
+            },
+
+            _ => None,
+        }
+    }
+
+    // Unsigned, so negative indices are out of bounds too:
+    //
+    //     in_bounds = ult index, length
+    //     br in_bounds, ok, fail
+    //   fail:
+    //     trap "index out of bounds"
+    //   ok:
+    fn check_bounds(&mut self, index: Value, length: Value) {
+        let in_bounds = self.builder.binary(BinaryOp::Ult, index, length);
+        let ok_block = self.builder.create_block();
+        let fail_block = self.builder.create_block();
+
+        self.builder.branch(in_bounds, ok_block, fail_block);
+
+        self.builder.switch_to_block(fail_block);
+        let message = self.module.add_data(BOUNDS_CHECK_MESSAGE.to_vec());
+        self.builder.trap(message);
+
+        self.builder.switch_to_block(ok_block);
+    }
+
+    // `&a` borrows an array as a slice, and anything else as a reference to
+    // where it is. A value which is not in memory is put in a stack slot of
+    // its own first
+    fn lower_reference(&mut self, operand: &Expression, mutable: bool) -> Result<Value, LoweringError> {
+        if let Some(name) = self.root_variable(operand) {
+            if mutable && self.scope.contains_key(name) && !self.mutable.contains(name) {
+                return Err(LoweringError::new(format!("cannot borrow immutable variable `{}` as mutable", name)));
+            }
+        }
+
+        let in_memory = self.is_memory_place(operand);
+        let (address, offset, ty) = self.lower_place(operand, mutable)?;
+
+        // A new value, the type of the address of the place stays as it is
+        let address = if in_memory || ty.in_memory() {
+            if offset == 0 {
+                self.builder.copy(address)
+            } else {
+                let offset = self.builder.iconst(Type::I32, offset as i64);
+                self.builder.element_address(address, offset, 1)
+            }
+        } else {
+            let layout = self.layout_of(&ty);
+            let slot = self.builder.create_stack_slot(layout.size, layout.align);
+            let temporary = self.builder.stack_address(slot);
+
+            self.store_value(&ty, temporary, 0, address);
+            temporary
+        };
+
+        let (element, length) = match ty {
+            Ty::Array(element, length) if !mutable => (element, length),
+            ty => return Ok(self.typed(address, Ty::Reference(mutable, Box::new(ty)))),
+        };
+
+        let data = address;
+        let slot = self.builder.create_stack_slot(8, 4);
+        let slice = self.builder.stack_address(slot);
+        let length = self.builder.iconst(Type::I32, length as i64);
+
+        self.builder.store(slice, 0, data);
+        self.builder.store(slice, 4, length);
+
+        self.types.insert(slice, Ty::Slice(element));
+        Ok(slice)
+    }
+
+    // Array literals are built in a stack slot of their own, the type of
+    // the first element is the type of all of them
+    fn lower_array_literal(&mut self, elements: &[Expression]) -> Result<Value, LoweringError> {
+        let Some((first, rest)) = elements.split_first() else {
+            return Err(LoweringError::new("cannot infer the type of an empty array".to_string()));
+        };
+
+        let first = self.lower_value(first)?;
+        let element = self.ty_of(first);
+        let ty = Ty::Array(Box::new(element.clone()), elements.len() as u32);
+
+        let layout = self.layout_of(&ty);
+        let slot = self.builder.create_stack_slot(layout.size, layout.align);
+        let address = self.builder.stack_address(slot);
+        let size = self.layout_of(&element).size as i32;
+
+        self.store_value(&element, address, 0, first);
+
+        for (index, expression) in rest.iter().enumerate() {
+            let value = self.lower_expected(expression, &element)?;
+            self.store_value(&element, address, (index as i32 + 1) * size, value);
+        }
+
+        self.types.insert(address, ty);
+        Ok(address)
+    }
+
+    // Struct literals are built in a stack slot of their own. The type
+    // arguments of a generic struct are inferred from the fields unless they
+    // are given
+    fn lower_struct_literal(&mut self, name: &str, types: &[parser::Type], fields: &[(String, Expression)]) -> Result<Value, LoweringError> {
+        let key = self.resolve_path(name, Namespace::Type, "struct")?;
+
+        let Some((Definition::Struct(definition), module)) = self.definitions.items.get(&key).copied() else {
+            return Err(LoweringError::new(format!("`{}` is not a struct", name)));
+        };
+
+        let mut values = Vec::new();
+
+        for (index, (field_name, expression)) in fields.iter().enumerate() {
+            if fields[..index].iter().any(|(other, _)| other == field_name) {
+                return Err(LoweringError::new(format!("field `{}` specified more than once", field_name)));
+            }
+
+            let Some(field) = definition.fields.iter().find(|field| field.name == *field_name) else {
+                return Err(LoweringError::new(format!("struct `{}` has no field named `{}`", name, field_name)));
+            };
+
+            values.push((&field.type_, self.lower_value(expression)?));
+        }
+
+        for field in &definition.fields {
+            if !fields.iter().any(|(name, _)| *name == field.name) {
+                return Err(LoweringError::new(format!("missing field `{}` in initializer of `{}`", field.name, name)));
+            }
+        }
+
+        let arguments = self.type_arguments(name, &definition.generics, types, &values, module)?;
+        let ty = self.instantiate(&key, arguments)?;
+        let Ty::Struct(instance) = &ty else { unreachable!() };
+
+        let layout = self.definitions.structs[instance].layout;
+        let slot = self.builder.create_stack_slot(layout.size, layout.align);
+        let address = self.builder.stack_address(slot);
+
+        for ((field_name, _), (_, value)) in fields.iter().zip(values) {
+            let field = self.definitions.structs[instance].field(field_name).unwrap().clone();
+
+            let value = self.coerce(value, &field.ty)?;
+            self.store_value(&field.ty, address, field.offset as i32, value);
+        }
+
+        self.types.insert(address, ty);
+        Ok(address)
+    }
+
+    // `Enum::Variant` and `Enum::Variant(values)` build the enum in a stack
+    // slot of its own, the tag followed by the payload
+    fn lower_variant(&mut self, path: &[String], types: &[parser::Type], arguments: &[Expression]) -> Result<Value, LoweringError> {
+        let Some((variant_name, prefix)) = path.split_last().filter(|(_, prefix)| !prefix.is_empty()) else {
+            return Err(LoweringError::new(format!("unknown path `{}`", path.join("::"))));
+        };
+
+        let name = &prefix.join("::");
+        let key = self.resolve_path(name, Namespace::Type, "enum")?;
+
+        let Some((Definition::Enum(definition), module)) = self.definitions.items.get(&key).copied() else {
+            return Err(LoweringError::new(format!("`{}` is not an enum", name)));
+        };
+
+        let Some(variant) = definition.variants.iter().find(|variant| variant.name == *variant_name) else {
+            return Err(LoweringError::new(format!("enum `{}` has no variant `{}`", name, variant_name)));
+        };
+
+        if variant.fields.len() != arguments.len() {
+            return Err(LoweringError::new(format!(
+                "`{}::{}` takes {} arguments but {} were given",
+                name,
+                variant_name,
+                variant.fields.len(),
+                arguments.len()
+            )));
+        }
+
+        let mut values = Vec::new();
+
+        for (argument, type_) in arguments.iter().zip(&variant.fields) {
+            values.push((type_, self.lower_value(argument)?));
+        }
+
+        let type_arguments = self.type_arguments(name, &definition.generics, types, &values, module)?;
+        let ty = self.instantiate(&key, type_arguments)?;
+        let Ty::Enum(instance) = &ty else { unreachable!() };
+
+        let info = &self.definitions.enums[instance];
+        let layout = info.layout;
+        let (tag, variant) = info.variant(variant_name).unwrap();
+        let fields = variant.fields.clone();
+
+        let slot = self.builder.create_stack_slot(layout.size, layout.align);
+        let address = self.builder.stack_address(slot);
+        let tag = self.builder.iconst(Type::I32, tag as i64);
+
+        self.builder.store(address, 0, tag);
+
+        for ((_, value), (ty, offset)) in values.into_iter().zip(&fields) {
+            let value = self.coerce(value, ty)?;
+            self.store_value(ty, address, *offset as i32, value);
+        }
+
+        self.types.insert(address, ty);
+        Ok(address)
+    }
+
+    // The type arguments of a generic function, struct or enum, given like
+    // in `max::<i32>` or inferred from the values of its parameters or
+    // fields with their declared types, written in `module`
+    fn type_arguments(
+        &mut self,
+        name: &str,
+        generics: &[String],
+        types: &[parser::Type],
+        values: &[(&parser::Type, Value)],
+        module: ModuleId
+    ) -> Result<Vec<Ty>, LoweringError> {
+        if !types.is_empty() {
+            if types.len() != generics.len() {
+                return Err(LoweringError::new(format!(
+                    "`{}` takes {} type arguments but {} were given",
+                    name,
+                    generics.len(),
+                    types.len()
+                )));
+            }
+
+            return types.iter().map(|type_| self.lower_type(type_)).collect();
+        }
+
+        let mut inferred = HashMap::new();
+
+        for (type_, value) in values {
+            self.definitions.infer(type_, &self.ty_of(*value), generics, module, &mut inferred);
+        }
+
+        generics
+            .iter()
+            .map(|generic| match inferred.remove(generic) {
+                Some(ty) => Ok(ty),
+                None => Err(LoweringError::new(format!("cannot infer type parameter `{}` of `{}`", generic, name))),
+            })
+            .collect()
+    }
+
+    // Arms are tried in order. An enum is first switched on its tag to the
+    // arms which can match each variant, the arms are compared in order
+    // from there:
+    //
+    //     switch tag, [case0, case1], invalid
+    //   case0:
+    //     <tests of arm 0, to next on failure>
+    //     jmp arm0
+    //   next:
+    //     jmp arm2                (the last arm left has to match)
+    //   arm0:
+    //     <bindings and the arm>
+    //     jmp end
+    //   end:
+    //     phi [arm0, %a], [arm1, %b], [arm2, %c]
+    fn lower_match(&mut self, scrutinee: &Expression, arms: &[(parser::Pattern, Expression)]) -> Result<Option<Value>, LoweringError> {
+        let in_memory = self.is_memory_place(scrutinee);
+        let (address, offset, ty) = self.lower_place(scrutinee, false)?;
+
+        let place = if in_memory || ty.in_memory() {
+            Place::Memory(address, offset)
+        } else {
+            Place::Value(address)
+        };
+
+        let mut checked = Vec::new();
+        let mut bound = Vec::new();
+
+        for (pattern, _) in arms {
+            let mut names = Vec::new();
+            checked.push(self.check_pattern(pattern, &ty, &mut names)?);
+            bound.push(names);
+        }
+
+        let pattern_type = self.pattern_type(&ty);
+
+        match patterns::check_match(&pattern_type, &checked) {
+            Ok(()) => {},
+
+            Err(PatternError::UnreachableArm(index)) => {
+                let pattern = patterns::display(&checked[index], &pattern_type);
+                return Err(LoweringError::new(format!("unreachable pattern `{}`", pattern)));
+            },
+
+            Err(PatternError::NotCovered(witness)) => {
+                return Err(LoweringError::new(format!("non-exhaustive patterns: `{}` not covered", witness)));
+            },
+        }
+
+        let arm_blocks: Vec<BlockId> = arms.iter().map(|_| self.builder.create_block()).collect();
+
+        match (&ty, place) {
+            (Ty::Enum(name), Place::Memory(address, offset)) => {
+                let tag = self.builder.load(Type::I32, address, offset);
+                let variants = self.definitions.enums[name].variants.len();
+                let cases: Vec<BlockId> = (0..variants).map(|_| self.builder.create_block()).collect();
+                let invalid = self.builder.create_block();
+
+                self.builder.switch(tag, cases.clone(), invalid);
+                self.builder.switch_to_block(invalid);
+                self.builder.unreachable();
+
+                for (variant, case) in cases.into_iter().enumerate() {
+                    let candidates: Vec<usize> = checked
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, pattern)| match pattern {
+                            patterns::Pattern::Variant(index, _) => *index == variant,
+                            _ => true,
+                        })
+                        .map(|(arm, _)| arm)
+                        .collect();
+
+                    self.builder.switch_to_block(case);
+                    self.lower_arm_tests(&checked, &candidates, place, &ty, &arm_blocks, true);
+                }
+            },
+
+            _ => {
+                let candidates: Vec<usize> = (0..arms.len()).collect();
+                self.lower_arm_tests(&checked, &candidates, place, &ty, &arm_blocks, false);
+            },
+        }
+
+        let scope = self.scope.clone();
+        let mutable = self.mutable.clone();
+        let end_block = self.builder.create_block();
+        let mut incoming = Vec::new();
+
+        for (index, (pattern, expression)) in arms.iter().enumerate() {
+            self.builder.switch_to_block(arm_blocks[index]);
+            self.scope = scope.clone();
+            self.mutable = mutable.clone();
+
+            self.bind_pattern(pattern, &checked[index], place, &ty);
+
+            let value = self.lower_expression(expression)?.map(|value| self.owned(expression, value));
+
+            // Assignments to variables the pattern shadows don't reach them
+            let mut arm_scope = scope.clone();
+
+            for (name, value) in &self.scope {
+                if arm_scope.contains_key(name) && !bound[index].contains(name) {
+                    arm_scope.insert(name.clone(), *value);
+                }
+            }
+
+            incoming.push((self.builder.current_block().unwrap(), value, arm_scope));
+            self.builder.jump(end_block);
+        }
+
+        self.builder.switch_to_block(end_block);
+        self.mutable = mutable;
+
+        let expected = incoming.first().and_then(|(_, value, _)| value.map(|value| self.ty_of(value)));
+
+        for (_, value, _) in &incoming {
+            let actual = value.map(|value| self.ty_of(value));
+
+            if actual != expected {
+                let describe = |ty: Option<Ty>| ty.map_or("no value".to_string(), |ty| ty.to_string());
+                return Err(LoweringError::new(format!(
+                    "`match` arms have incompatible types: expected {}, found {}",
+                    describe(expected),
+                    describe(actual)
+                )));
+            }
+        }
+
+        let result = expected.map(|ty| {
+            let values = incoming.iter().map(|(block, value, _)| (*block, value.unwrap())).collect();
+            let result = self.builder.phi(ty.ir_type(), values);
+
+            if !matches!(ty, Ty::Scalar(_)) {
+                self.types.insert(result, ty);
+            }
+
+            result
+        });
+
+        // Variables assigned in some of the arms
+        let mut names: Vec<&String> = scope.keys().collect();
+        names.sort();
+
+        self.scope = scope.clone();
+
+        for name in names {
+            let values: Vec<(BlockId, Value)> = incoming
+                .iter()
+                .map(|(block, _, arm_scope)| (*block, arm_scope[name]))
+                .collect();
+
+            if values.iter().all(|(_, value)| *value == scope[name]) {
+                continue;
+            }
+
+            let ty = self.ty_of(values[0].1);
+            let merged = self.builder.phi(ty.ir_type(), values);
+
+            if !matches!(ty, Ty::Scalar(_)) {
+                self.types.insert(merged, ty);
+            }
+
+            self.scope.insert(name.clone(), merged);
+        }
+
+        Ok(result)
+    }
+
+    // Tries the candidate arms in order. The match is exhaustive, so the
+    // last one left matches without a test, and nothing after an arm which
+    // cannot fail is tried
+    fn lower_arm_tests(
+        &mut self,
+        checked: &[patterns::Pattern],
+        candidates: &[usize],
+        place: Place,
+        ty: &Ty,
+        arm_blocks: &[BlockId],
+        tag_known: bool
+    ) {
+        let end = candidates
+            .iter()
+            .position(|arm| irrefutable(&checked[*arm], tag_known))
+            .map_or(candidates.len(), |position| position + 1);
+
+        let (last, rest) = candidates[..end].split_last().unwrap();
+
+        for &arm in rest {
+            let next = self.builder.create_block();
+
+            self.lower_pattern_test(&checked[arm], place, ty, next, tag_known);
+            self.builder.jump(arm_blocks[arm]);
+            self.builder.switch_to_block(next);
+        }
+
+        self.builder.jump(arm_blocks[*last]);
+    }
+
+    // Continues in the current block when the value at `place` matches and
+    // branches to `fail` otherwise. In a switch case the tag of the
+    // outermost enum is known already
+    fn lower_pattern_test(&mut self, pattern: &patterns::Pattern, place: Place, ty: &Ty, fail: BlockId, tag_known: bool) {
+        let (actual, expected) = match (pattern, ty, place) {
+            (patterns::Pattern::Wildcard, _, _) => return,
+
+            (patterns::Pattern::Integer(value), Ty::Scalar(ty) | Ty::Unsigned(ty), _) => {
+                let actual = self.read(place, *ty);
+                (actual, self.builder.iconst(*ty, *value))
+            },
+
+            (patterns::Pattern::Variant(index, payload), Ty::Enum(name), Place::Memory(address, offset)) => {
+                if !tag_known {
+                    let tag = self.builder.load(Type::I32, address, offset);
+                    let expected = self.builder.iconst(Type::I32, *index as i64);
+                    self.test_equal(tag, expected, fail);
+                }
+
+                let fields = self.definitions.enums[name].variants[*index].fields.clone();
+
+                for (pattern, (ty, field_offset)) in payload.iter().zip(&fields) {
+                    self.lower_pattern_test(pattern, Place::Memory(address, offset + *field_offset as i32), ty, fail, false);
+                }
+
+                return;
+            },
+
+            _ => unreachable!(),
+        };
+
+        self.test_equal(actual, expected, fail);
+    }
+
+    fn test_equal(&mut self, actual: Value, expected: Value, fail: BlockId) {
+        let equal = self.builder.binary(BinaryOp::Eq, actual, expected);
+        let next = self.builder.create_block();
+
+        self.builder.branch(equal, next, fail);
+        self.builder.switch_to_block(next);
+    }
+
+    // Binds the names in `pattern` to the parts of the matched value, which
+    // are copied out of it like fields
+    fn bind_pattern(&mut self, pattern: &parser::Pattern, checked: &patterns::Pattern, place: Place, ty: &Ty) {
+        match (pattern, checked, ty, place) {
+            (parser::Pattern::Binding(name), _, _, _) => {
+                let value = match place {
+                    Place::Memory(address, offset) => self.load_value(ty, address, offset),
+                    Place::Value(value) => value,
+                };
+
+                self.declare(name, value);
+                self.mutable.remove(name);
+            },
+
+            (
+                parser::Pattern::Variant(_, payload),
+                patterns::Pattern::Variant(index, checked_payload),
+                Ty::Enum(name),
+                Place::Memory(address, offset),
+            ) => {
+                let fields = self.definitions.enums[name].variants[*index].fields.clone();
+
+                for ((pattern, checked), (ty, field_offset)) in payload.iter().zip(checked_payload).zip(&fields) {
+                    self.bind_pattern(pattern, checked, Place::Memory(address, offset + *field_offset as i32), ty);
+                }
+            },
+
+            _ => {},
+        }
+    }
+
+    fn read(&mut self, place: Place, ty: Type) -> Value {
+        match place {
+            Place::Value(value) => value,
+            Place::Memory(address, offset) => self.builder.load(ty, address, offset),
+        }
+    }
+
+    // Type checks `pattern` against `ty`, collecting the names it binds
+    fn check_pattern(&self, pattern: &parser::Pattern, ty: &Ty, names: &mut Vec<String>) -> Result<patterns::Pattern, LoweringError> {
+        match pattern {
+            parser::Pattern::Wildcard => Ok(patterns::Pattern::Wildcard),
+
+            parser::Pattern::Binding(name) => {
+                if names.contains(name) {
+                    return Err(LoweringError::new(format!("identifier `{}` is bound more than once in the same pattern", name)));
+                }
+
+                names.push(name.clone());
+                Ok(patterns::Pattern::Wildcard)
+            },
+
+            parser::Pattern::Boolean(value) => self.check_integer_pattern(*value as i128, &None, ty),
+            parser::Pattern::Integer(value, suffix) => self.check_integer_pattern(*value, suffix, ty),
+
+            parser::Pattern::Variant(path, payload) => {
+                let (variant_name, prefix) = path.split_last().unwrap();
+
+                let enum_name = match prefix {
+                    [] => None,
+                    _ => Some(self.resolve_path(&prefix.join("::"), Namespace::Type, "enum")?),
+                };
+
+                let (info, name) = match ty {
+                    Ty::Enum(name) if enum_name.as_deref() == Some(self.definitions.generic_name(name)) => {
+                        (&self.definitions.enums[name], name)
+                    },
+
+                    _ => return Err(LoweringError::new(format!("expected {}, found `{}`", ty, path.join("::")))),
+                };
+
+                let Some((index, variant)) = info.variant(variant_name) else {
+                    return Err(LoweringError::new(format!("enum `{}` has no variant `{}`", name, variant_name)));
+                };
+
+                if variant.fields.len() != payload.len() {
+                    return Err(LoweringError::new(format!(
+                        "`{}::{}` has {} fields, but the pattern has {}",
+                        name,
+                        variant_name,
+                        variant.fields.len(),
+                        payload.len()
+                    )));
+                }
+
+                let payload = payload
+                    .iter()
+                    .zip(&variant.fields)
+                    .map(|(pattern, (ty, _))| self.check_pattern(pattern, ty, names))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(patterns::Pattern::Variant(index, payload))
+            },
+        }
+    }
+
+    fn check_integer_pattern(&self, value: i128, suffix: &Option<String>, ty: &Ty) -> Result<patterns::Pattern, LoweringError> {
+        if !ty.is_integer() {
+            return Err(LoweringError::new(format!("expected {}, found an integer", ty)));
+        }
+
+        if let Some(suffix) = suffix {
+            if integer_type(suffix).as_ref() != Some(ty) {
+                return Err(LoweringError::new(format!("expected {}, found {}", ty, suffix)));
+            }
+        }
+
+        let ir_type = ty.ir_type();
+        let bits = ir_type.bits();
+
+        let (min, max) = match ty {
+            Ty::Unsigned(_) => (0, (1i128 << bits) - 1),
+            _ => (-(1i128 << (bits - 1)), (1i128 << (bits - 1)) - 1),
+        };
+
+        if value < min || value > max {
+            return Err(LoweringError::new(format!("literal `{}` is out of range for {}", value, ty)));
+        }
+
+        Ok(patterns::Pattern::Integer(ir_type.truncate(value as i64)))
+    }
+
+    fn pattern_type(&self, ty: &Ty) -> PatternType {
+        match ty {
+            Ty::Enum(name) => {
+                let variants = self.definitions.enums[name].variants
+                    .iter()
+                    .map(|variant| {
+                        let fields = variant.fields.iter().map(|(ty, _)| self.pattern_type(ty)).collect();
+                        (variant.name.clone(), fields)
+                    })
+                    .collect();
+
+                PatternType::Enum(self.definitions.generic_name(name).to_string(), variants)
+            },
+
+            ty if ty.is_integer() => PatternType::Integer,
+            _ => PatternType::Other,
+        }
+    }
+
+    fn layout_of(&self, ty: &Ty) -> Layout {
+        self.definitions.layout_of(ty)
+    }
+
+    fn lower_type(&mut self, type_: &parser::Type) -> Result<Ty, LoweringError> {
+        self.definitions.lower_type(type_, &self.type_params, self.module_id)
+    }
+
+    // `Shape::Circle` is a variant, `shapes::area` a function
+    fn is_variant(&self, path: &[String]) -> bool {
+        path.len() > 1 && self.definitions.modules.resolve(self.module_id, &path[..path.len() - 1], Namespace::Type).is_ok()
+    }
+
+    // The qualified name of the `kind` a path like `shapes::Point` refers to
+    fn resolve_path(&self, path: &str, namespace: Namespace, kind: &str) -> Result<String, LoweringError> {
+        match self.definitions.modules.resolve_name(self.module_id, path, namespace) {
+            Ok(name) => Ok(name),
+            Err(ResolveError::NotFound) => Err(LoweringError::new(format!("unknown {} `{}`", kind, path))),
+            Err(ResolveError::Other(message)) => Err(LoweringError::new(message)),
+        }
+    }
+
+    fn instantiate(&mut self, name: &str, arguments: Vec<Ty>) -> Result<Ty, LoweringError> {
+        let ty = self.definitions.instantiate(name, arguments)?;
+        self.definitions.lay_out()?;
+        Ok(ty)
+    }
+
+    // Variables whose address is taken live in a stack slot, other scalars
+    // are SSA values
+    fn declare(&mut self, name: &str, value: Value) {
+        let ty = self.ty_of(value);
+
+        let value = if self.addressed.contains(name) && !ty.in_memory() {
+            let layout = self.layout_of(&ty);
+            let slot = self.builder.create_stack_slot(layout.size, layout.align);
+            let address = self.builder.stack_address(slot);
+
+            self.builder.store(address, 0, value);
+            self.spilled.insert(address, ty);
+            address
+        } else {
+            value
+        };
+
+        self.scope.insert(name.to_string(), value);
+    }
+
+    // Scalars and pointers are stored, other values are copied from their
+    // address
+    fn store_value(&mut self, ty: &Ty, address: Value, offset: i32, value: Value) {
+        if ty.in_memory() {
+            self.copy(ty, address, offset, value, 0);
+        } else {
+            self.builder.store(address, offset, value);
+        }
+    }
+
+    // Scalars and pointers are loaded, other values are copied to a new
+    // stack slot
+    fn load_value(&mut self, ty: &Ty, address: Value, offset: i32) -> Value {
+        if ty.in_memory() {
+            return self.copy_value(ty, address, offset);
+        }
+
+        let value = self.builder.load(ty.ir_type(), address, offset);
+        self.typed(value, ty.clone())
+    }
+
+    // Copies the value of type `ty` at [source + offset] to a new stack slot
+    fn copy_value(&mut self, ty: &Ty, source: Value, offset: i32) -> Value {
+        let layout = self.layout_of(ty);
+        let slot = self.builder.create_stack_slot(layout.size, layout.align);
+        let address = self.builder.stack_address(slot);
+
+        self.copy(ty, address, 0, source, offset);
+        self.types.insert(address, ty.clone());
+        address
+    }
+
+    // Scalar by scalar, so padding is never read. Enums are copied by words
+    // because which payload is there depends on the tag
+    fn copy(&mut self, ty: &Ty, destination: Value, destination_offset: i32, source: Value, source_offset: i32) {
+        match ty {
+            Ty::Scalar(ty) | Ty::Unsigned(ty) => {
+                let value = self.builder.load(*ty, source, source_offset);
+                self.builder.store(destination, destination_offset, value);
+            },
+
+            Ty::Struct(name) => {
+                for field in self.definitions.structs[name].fields.clone() {
+                    let offset = field.offset as i32;
+                    self.copy(&field.ty, destination, destination_offset + offset, source, source_offset + offset);
+                }
+            },
+
+            Ty::Enum(name) => {
+                for offset in (0..self.definitions.enums[name].layout.size as i32).step_by(4) {
+                    self.copy(&Ty::Scalar(Type::I32), destination, destination_offset + offset, source, source_offset + offset);
+                }
+            },
+
+            Ty::Array(element, length) => {
+                let size = self.layout_of(element).size as i32;
+
+                for index in 0..*length as i32 {
+                    self.copy(element, destination, destination_offset + index * size, source, source_offset + index * size);
+                }
+            },
+
+            Ty::Slice(_) => {
+                self.copy(&Ty::Scalar(Type::Ptr), destination, destination_offset, source, source_offset);
+                self.copy(&Ty::Scalar(Type::I32), destination, destination_offset + 4, source, source_offset + 4);
+            },
+
+            Ty::Pointer(_) | Ty::Reference(..) | Ty::Null => {
+                self.copy(&Ty::Scalar(Type::Ptr), destination, destination_offset, source, source_offset);
+            },
+        }
+    }
+
+    // Literals without a suffix are i32, unless `lower_expected` knows the
+    // type they should have
+    fn lower_integer(&mut self, value: u64, suffix: &Option<String>, negative: bool) -> Result<Value, LoweringError> {
+        let suffix = suffix.as_deref().unwrap_or("i32");
+        let Some(ty) = integer_type(suffix) else {
+            return Err(LoweringError::new(format!("unknown type `{}`", suffix)));
+        };
+
+        if ty.ir_type() == Type::I64 {
+            let sign = if negative { "-" } else { "" };
+            return Err(LoweringError::new(format!(
+                "literal `{}{}{}` is not supported, the i386 backend has no 64-bit integers", sign, value, suffix
+            )));
+        }
+
+        self.integer_constant(value, ty, negative)
+    }
+
+    fn integer_constant(&mut self, value: u64, ty: Ty, negative: bool) -> Result<Value, LoweringError> {
+        let ir_type = ty.ir_type();
+        let bits = ir_type.bits();
+
+        let max = if let Ty::Unsigned(_) = ty {
+            if negative { 0 } else { u64::MAX >> (64 - bits) }
+        } else {
+            (1u64 << (bits - 1)) - 1 + negative as u64
+        };
+
+        if value > max {
+            let sign = if negative { "-" } else { "" };
+            return Err(LoweringError::new(format!("literal `{}{}` is out of range for {}", sign, value, ty)));
+        }
+
+        // `200u8` is the same constant as `-56i8`, sign-extended like every
+        // narrow value
+        let value = if negative { (value as i64).wrapping_neg() } else { value as i64 };
+        let constant = self.builder.iconst(ir_type, ir_type.truncate(value));
+        Ok(self.typed(constant, ty))
+    }
+
+    // `lower_value` followed by `coerce`, except that an integer literal
+    // without a suffix gets the expected integer type instead of i32
+    fn lower_expected(&mut self, expression: &Expression, expected: &Ty) -> Result<Value, LoweringError> {
+        let literal = match expression {
+            Expression::Integer(value, None) => Some((*value, false)),
+
+            Expression::Unary(UnaryOperator::Minus, operand) => match **operand {
+                Expression::Integer(value, None) => Some((value, true)),
+                _ => None,
+            },
+
+            _ => None,
+        };
+
+        match literal {
+            Some((value, negative)) if expected.is_integer() => self.integer_constant(value, expected.clone(), negative),
+
+            _ => {
+                let value = self.lower_value(expression)?;
+                self.coerce(value, expected)
+            },
+        }
+    }
+
+    // A call to a generic function calls its instance for the type
+    // arguments, which is lowered later
+    fn lower_call(&mut self, name: &str, types: &[parser::Type], arguments: &[Expression]) -> Result<Option<Value>, LoweringError> {
+        let key = match self.definitions.modules.resolve_name(self.module_id, name, Namespace::Value) {
+            Ok(key) => key,
+            Err(ResolveError::NotFound) if INTRINSICS.contains(&name) => return self.lower_intrinsic(name, types, arguments).map(Some),
+            Err(ResolveError::NotFound) => return Err(LoweringError::new(format!("unknown function `{}`", name))),
+            Err(ResolveError::Other(message)) => return Err(LoweringError::new(message)),
+        };
+
+        let (function, module) = match &self.functions[&key] {
+            Callee::Generic(function, module) => (*function, *module),
+            Callee::Function(signature) if types.is_empty() => return self.lower_direct_call(&key, name, signature, arguments),
+            Callee::Function(_) => return Err(LoweringError::new(format!("`{}` takes 0 type arguments but {} were given", name, types.len()))),
+        };
+
+        if function.parameters.len() != arguments.len() {
+            return Err(LoweringError::new(format!(
+                "`{}` takes {} arguments but {} were given",
+                name,
+                function.parameters.len(),
+                arguments.len()
+            )));
+        }
+
+        let mut values = Vec::new();
+
+        for (argument, parameter) in arguments.iter().zip(&function.parameters) {
+            values.push((&parameter.type_, self.lower_value(argument)?));
+        }
+
+        let type_arguments = self.type_arguments(name, &function.generics, types, &values, module)?;
+        let params = function.generics.iter().cloned().zip(type_arguments.iter().cloned()).collect();
+        let signature = lower_signature(self.definitions, &params, &function.parameters, &function.return_type, module)?;
+
+        let mut args = Vec::new();
+
+        for ((_, value), expected) in values.iter().zip(&signature.params) {
+            args.push(self.coerce(*value, expected)?);
+        }
+
+        let symbol = self.definitions.mangle(&key, &type_arguments);
+        let requested = self.instances.iter().any(|instance| instance.name == key && instance.arguments == type_arguments);
+
+        if !requested {
+            if self.depth == INSTANTIATION_LIMIT {
+                let arguments: Vec<String> = type_arguments.iter().map(Ty::to_string).collect();
+                return Err(LoweringError::new(format!(
+                    "reached the instantiation limit while instantiating `{}<{}>`",
+                    name,
+                    arguments.join(", ")
+                )));
+            }
+
+            self.instances.push(Instance { name: key, arguments: type_arguments, depth: self.depth + 1 });
+        }
+
+        let result = self.builder.call(&symbol, args, signature.return_type.ir_type());
+        Ok(result.map(|result| self.typed(result, signature.return_type)))
+    }
+
+    // `size_of::<T>()` is the size of `T` in bytes, `cast::<T>(pointer)` is
+    // the pointer as `*T`. The runtime library allocates with them
+    fn lower_intrinsic(&mut self, name: &str, types: &[parser::Type], arguments: &[Expression]) -> Result<Value, LoweringError> {
+        let [type_] = types else {
+            return Err(LoweringError::new(format!("`{}` takes 1 type argument but {} were given", name, types.len())));
+        };
+
+        let parameters = if name == "size_of" { 0 } else { 1 };
+
+        if arguments.len() != parameters {
+            return Err(LoweringError::new(format!(
+                "`{}` takes {} arguments but {} were given",
+                name,
+                parameters,
+                arguments.len()
+            )));
+        }
+
+        let ty = self.lower_type(type_)?;
+
+        if name == "size_of" {
+            let size = self.layout_of(&ty).size;
+            return Ok(self.builder.iconst(Type::I32, size as i64));
+        }
+
+        let pointer = self.lower_value(&arguments[0])?;
+
+        match self.ty_of(pointer) {
+            Ty::Pointer(_) | Ty::Reference(..) => {
+                let cast = self.builder.bitcast(pointer, Type::Ptr);
+                Ok(self.typed(cast, Ty::Pointer(Box::new(ty))))
+            },
+
+            actual => Err(LoweringError::new(format!("`cast` takes a pointer, found {}", actual))),
+        }
+    }
+
+    // `symbol` is the qualified name of the function and `name` the path it
+    // is called by
+    fn lower_direct_call(&mut self, symbol: &str, name: &str, signature: &Signature, arguments: &[Expression]) -> Result<Option<Value>, LoweringError> {
+        if signature.params.len() != arguments.len() {
+            return Err(LoweringError::new(format!(
+                "`{}` takes {} arguments but {} were given",
+                name,
+                signature.params.len(),
+                arguments.len()
+            )));
+        }
+
+        let mut args = Vec::new();
+
+        for (argument, expected) in arguments.iter().zip(&signature.params) {
+            args.push(self.lower_expected(argument, expected)?);
+        }
+
+        let result = self.builder.call(symbol, args, signature.return_type.ir_type());
+        Ok(result.map(|result| self.typed(result, signature.return_type.clone())))
+    }
+
+    fn ty_of(&self, value: Value) -> Ty {
+        match self.types.get(&value) {
+            Some(ty) => ty.clone(),
+            None => Ty::Scalar(self.builder.value_type(value)),
+        }
+    }
+
+    fn expect_integer(&self, value: Value) -> Result<(), LoweringError> {
+        match self.ty_of(value) {
+            ty if ty.is_integer() => Ok(()),
+            actual => Err(LoweringError::new(format!("expected an integer, found {}", actual))),
+        }
+    }
+
+    fn expect_type(&self, value: Value, expected: &Ty) -> Result<(), LoweringError> {
+        let actual = self.ty_of(value);
+
+        if actual == *expected {
+            Ok(())
+        } else {
+            Err(LoweringError::new(format!("expected {}, found {}", expected, actual)))
+        }
+    }
+
+    // Like `expect_type`, but `null` converts to any pointer or reference,
+    // references to raw pointers, `&mut T` to `&T`, and integers to the
+    // integer of the other signedness with the same size
+    fn coerce(&mut self, value: Value, expected: &Ty) -> Result<Value, LoweringError> {
+        let converts = match (self.ty_of(value), expected) {
+            (Ty::Scalar(ty), Ty::Unsigned(expected)) | (Ty::Unsigned(ty), Ty::Scalar(expected)) => {
+                ty == *expected && ty.is_integer()
+            },
+
+            (Ty::Null, Ty::Pointer(_) | Ty::Reference(..)) => true,
+            (Ty::Reference(_, referent), Ty::Pointer(pointee)) => referent == *pointee,
+            (Ty::Reference(true, referent), Ty::Reference(false, expected)) => referent == *expected,
+            _ => false,
+        };
+
+        if !converts {
+            self.expect_type(value, expected)?;
+            return Ok(value);
+        }
+
+        let converted = self.builder.copy(value);
+        Ok(self.typed(converted, expected.clone()))
+    }
+
+    // Records the type of a value which is not a plain scalar
+    fn typed(&mut self, value: Value, ty: Ty) -> Value {
+        if !matches!(ty, Ty::Scalar(_)) {
+            self.types.insert(value, ty);
+        }
+
+        value
+    }
+}
+
+// Collects the variables whose address is taken with `&x` or `&x.field`,
+// which live in stack slots. A name is collected for every variable it
+// names in the function
+fn collect_addressed(statement: &Statement, addressed: &mut HashSet<String>) {
+    match statement {
+        Statement::Let(_, _, _, expression) | Statement::Return(Some(expression)) | Statement::Expression(expression) => {
+            collect_addressed_in(expression, addressed);
+        },
+
+        Statement::Return(None) => {},
+    }
+}
+
+fn collect_addressed_in(expression: &Expression, addressed: &mut HashSet<String>) {
+    match expression {
+        Expression::Unary(UnaryOperator::Reference | UnaryOperator::ReferenceMut, operand) => {
+            let mut root = &**operand;
+
+            while let Expression::Field(base, _) | Expression::Index(base, _) | Expression::Group(base) = root {
+                root = base;
+            }
+
+            if let Expression::Identifier(name) = root {
+                addressed.insert(name.clone());
+            }
+
+            collect_addressed_in(operand, addressed);
+        },
+
+        Expression::Unary(_, operand) | Expression::Field(operand, _) | Expression::Group(operand) => {
+            collect_addressed_in(operand, addressed);
+        },
+
+        Expression::Binary(_, lhs, rhs) | Expression::Assign(_, lhs, rhs) | Expression::Index(lhs, rhs) => {
+            collect_addressed_in(lhs, addressed);
+            collect_addressed_in(rhs, addressed);
+        },
+
+        Expression::Call(callee, arguments) => {
+            collect_addressed_in(callee, addressed);
+
+            for argument in arguments {
+                collect_addressed_in(argument, addressed);
+            }
+        },
+
+        Expression::Struct(_, _, fields) => {
+            for (_, value) in fields {
+                collect_addressed_in(value, addressed);
+            }
+        },
+
+        Expression::Array(elements) => {
+            for element in elements {
+                collect_addressed_in(element, addressed);
+            }
+        },
+
+        Expression::Match(scrutinee, arms) => {
+            collect_addressed_in(scrutinee, addressed);
+
+            for (_, arm) in arms {
+                collect_addressed_in(arm, addressed);
+            }
+        },
+
+        _ => {},
+    }
+}
+
+// Whether `pattern` matches every value, with the tag known for the
+// outermost enum
+fn irrefutable(pattern: &patterns::Pattern, tag_known: bool) -> bool {
+    match pattern {
+        patterns::Pattern::Wildcard => true,
+        patterns::Pattern::Variant(_, payload) => tag_known && payload.iter().all(|pattern| irrefutable(pattern, false)),
+        patterns::Pattern::Integer(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::lowering::*;
+    use crate::ir::verifier::verify_module;
+    use crate::ir::{DataId, Terminator};
+    use crate::opt::{OptLevel, PassManager};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn lower(source: &str) -> Result<Module, LoweringError> {
+        let ast = Parser::new(Lexer::new(source)).parse_module().unwrap();
+        lower_module(&ast, &LoweringOptions::default())
+    }
+
+    #[test]
+    fn test_lower_app() {
+        let module = lower(include_str!("../../app.dl")).unwrap();
+
+        assert_eq!(Ok(()), verify_module(&module));
+        assert_eq!(1, module.functions.len());
+        assert_eq!(1, module.data.len());
+    }
+
+    #[test]
+    fn test_integer_ranges() {
+        assert!(lower("fn main() { let a = -128i8; let b = 255u8; let c = 4294967295u32; }").is_ok());
+
+        let result = lower("fn main() { let a = 128i8; }");
+        assert_eq!("literal `128` is out of range for i8", result.unwrap_err().message);
+
+        let result = lower("fn main() { let a = 3000000000; }");
+        assert_eq!("literal `3000000000` is out of range for i32", result.unwrap_err().message);
+
+        let result = lower("fn main() { let a = -1u32; }");
+        assert_eq!("literal `-1` is out of range for u32", result.unwrap_err().message);
+
+        // Literals without a suffix take the declared type
+        assert!(lower("fn f(a: u8) -> u32 { let b: i8 = -128; let c = [1u16, 65535]; f(255); return 4000000000; }").is_ok());
+
+        let result = lower("fn main() { let a: u8 = 256; }");
+        assert_eq!("literal `256` is out of range for u8", result.unwrap_err().message);
+
+        // Narrow constants are sign-extended
+        let module = lower("fn f() -> u8 { return 200u8; }").unwrap();
+        assert!(module.function("f").unwrap().to_string().contains("const i8 -56\n"));
+
+        let result = lower("fn main() { let a = 7i64; }");
+        assert_eq!("literal `7i64` is not supported, the i386 backend has no 64-bit integers", result.unwrap_err().message);
+
+        let result = lower("fn f(a: *u64) {}");
+        assert_eq!("type `u64` is not supported, the i386 backend has no 64-bit integers", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_unsigned() {
+        let mut module = lower(concat!(
+            "fn compare() -> u8 { return match 200u8 > 100u8 { 0 => 0u8, _ => 1u8 }; }",
+            "fn divide() -> u32 { return 4000000000u32 / 2u32; }",
+            "fn shift() -> u32 { return 4000000000u32 >> 1; }",
+            "fn remainder() -> u8 { return 255u8 % 10u8; }",
+            "fn mixed(a: i32, b: u32) -> i32 { return a / b; }",
+        )).unwrap();
+
+        assert_eq!(Ok(()), verify_module(&module));
+
+        let ir = |module: &Module, name: &str| module.function(name).unwrap().to_string();
+        assert!(ir(&module, "compare").contains("ugt i8"));
+        assert!(ir(&module, "divide").contains("udiv i32"));
+        assert!(ir(&module, "shift").contains("shr i32"));
+        assert!(ir(&module, "mixed").contains("udiv i32"));
+
+        // The values are above the signed maximum of their types
+        PassManager::for_level(OptLevel::O2).run(&mut module);
+
+        assert!(ir(&module, "compare").contains("const i8 1\n"));
+        assert!(ir(&module, "divide").contains("const i32 2000000000\n"));
+        assert!(ir(&module, "shift").contains("const i32 2000000000\n"));
+        assert!(ir(&module, "remainder").contains("const i8 5\n"));
+
+        let result = lower("fn f(a: i32, b: u8) -> i32 { return a + b; }");
+        assert_eq!("expected i32, found u8", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let result = lower("fn main() -> i32 { return y; }");
+
+        assert_eq!("unknown variable `y`", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_extern_block() {
+        let module = lower(concat!(
+            "extern \"C\" { fn puts(s: *const u8) -> i32; }",
+            "extern \"stdcall\" \"kernel32.dll\" { fn Sleep(milliseconds: u32); }",
+            "fn main() -> i32 { Sleep(10); return puts(\"hi\"); }",
+        )).unwrap();
+
+        assert_eq!(Ok(()), verify_module(&module));
+        assert_eq!(2, module.externals.len());
+        assert_eq!(vec![Type::Ptr], module.externals[0].params);
+        assert_eq!(CallingConvention::Stdcall, module.externals[1].convention);
+        assert_eq!(Some("kernel32.dll".to_string()), module.externals[1].library);
+
+        let result = lower("fn main() -> i32 { return puts(\"hi\"); }");
+        assert_eq!("unknown function `puts`", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_assignment() {
+        let module = lower("fn main() -> i32 { let mut x = 1; x <<= 2; x = x % 3; return x == 1 && x != 2 || !x; }").unwrap();
+        assert_eq!(Ok(()), verify_module(&module));
+
+        let result = lower("fn main() { let x = 1; x = 2; }");
+        assert_eq!("cannot assign twice to immutable variable `x`", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_postfix_expressions() {
+        let module = lower("fn f(a: i32, b: i32) -> i32 { return (a + b) * 3; } fn main() -> i32 { return f(1, 2,); }").unwrap();
+        assert_eq!(Ok(()), verify_module(&module));
+
+        let result = lower("fn main() -> i32 { let a = 1; return a[0]; }");
+        assert_eq!("cannot index into a value of type i32", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_structs() {
+        let module = lower(concat!(
+            "struct Line { start: Point, end: Point } struct Point { x: i32, y: i8 }",
+            "fn length(line: Line) -> i32 { return line.end.x - line.start.x; }",
+            "fn main() -> i32 {",
+            "    let mut p = Point { y: 2i8, x: 1 };",
+            "    p.x += 3;",
+            "    let mut line = Line { start: p, end: Point { x: 10, y: p.y } };",
+            "    line.end.x = 7;",
+            "    return length(line);",
+            "}",
+        )).unwrap();
+
+        assert_eq!(Ok(()), verify_module(&module));
+        let length = &module.functions[0];
+        assert_eq!(Type::Ptr, length.value_type(length.params[0]));
+
+        let result = lower("struct A { b: B } struct B { a: A }");
+        assert_eq!("struct `A` contains itself and has infinite size", result.unwrap_err().message);
+
+        let result = lower("struct P { x: i32 } fn main() { let p = P { x: 1 }; p.x = 2; }");
+        assert_eq!("cannot assign to a field of immutable variable `p`", result.unwrap_err().message);
+
+        let result = lower("struct P { x: i32, y: i32 } fn main() { let p = P { x: 1 }; }");
+        assert_eq!("missing field `y` in initializer of `P`", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_arrays() {
+        let source = concat!(
+            "fn sum(values: &[i32]) -> i32 { return values[0] + values[values.len() - 1]; }",
+            "fn main() -> i32 {",
+            "    let mut grid = [[1i8, 2i8, 3i8], [4i8, 5i8, 6i8]];",
+            "    grid[1][2] += grid[0][1];",
+            "    let a = [7, 8];",
+            "    return sum(&a);",
+            "}",
+        );
+
+        let ast = Parser::new(Lexer::new(source)).parse_module().unwrap();
+        let module = lower_module(&ast, &LoweringOptions::default()).unwrap();
+
+        assert_eq!(Ok(()), verify_module(&module));
+        let traps = module.functions[0].blocks
+            .iter()
+            .filter(|block| block.terminator == Some(Terminator::Trap(DataId(0))))
+            .count();
+
+        assert_eq!(2, traps);
+
+        let module = lower_module(&ast, &LoweringOptions { bounds_checks: false }).unwrap();
+        assert_eq!(1, module.functions[0].blocks.len());
+
+        let result = lower("fn f(a: [i32; 2]) { let mut values = &a; values[0] = 1; }");
+        assert_eq!("cannot assign through a slice, slices are read-only", result.unwrap_err().message);
+
+        let result = lower("fn main() { let a = [1, 2]; a[0] = 3; }");
+        assert_eq!("cannot assign to an element of immutable variable `a`", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_match() {
+        let module = lower(concat!(
+            "enum Shape { Circle(i32), Rect(i32, i32), Empty, Tagged(Option) }",
+            "enum Option { None, Some(i8) }",
+            "fn area(shape: Shape) -> i32 {",
+            "    return match shape {",
+            "        Shape::Circle(r) => 3 * r * r,",
+            "        Shape::Rect(w, h) => w * h,",
+            "        Shape::Tagged(Option::Some(5i8)) => 5,",
+            "        _ => 0,",
+            "    };",
+            "}",
+            "fn main() -> i32 {",
+            "    let mut total = area(Shape::Rect(2, 3));",
+            "    match total { 6 => total = 1, n => total += n };",
+            "    return total + area(Shape::Tagged(Option::None));",
+            "}",
+        )).unwrap();
+
+        assert_eq!(Ok(()), verify_module(&module));
+
+        let switches = module.functions[0].blocks
+            .iter()
+            .filter(|block| matches!(block.terminator, Some(Terminator::Switch(..))))
+            .count();
+
+        assert_eq!(1, switches);
+
+        let result = lower("enum E { A, B(i32) } fn f(e: E) -> i32 { return match e { E::A => 1, E::B(0) => 2 }; }");
+        assert_eq!("non-exhaustive patterns: `E::B(_)` not covered", result.unwrap_err().message);
+
+        let result = lower("enum E { A, B } fn f(e: E) -> i32 { return match e { E::A => 1, _ => 2, E::B => 3 }; }");
+        assert_eq!("unreachable pattern `E::B`", result.unwrap_err().message);
+
+        let result = lower("fn f(x: i32) -> i32 { return match x { 0 => 1, _ => 2i8 }; }");
+        assert_eq!("`match` arms have incompatible types: expected i32, found i8", result.unwrap_err().message);
+
+        let result = lower("enum E { A(i32) } fn f() { let e = E::A; }");
+        assert_eq!("`E::A` takes 1 arguments but 0 were given", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_pointers() {
+        let source = [
+            "struct Node { value: i32, next: *Node }",
+            "fn bump(counter: &mut i32) { *counter += 1; }",
+            "fn sum(node: *Node) -> i32 { return match node == null { 0 => node.value + sum(node.next), _ => 0 }; }",
+            "fn third(values: *i32) -> i32 { return *(values + 2) + values[2]; }",
+            "fn main() -> i32 {",
+            "    let mut count = 0;",
+            "    bump(&mut count);",
+            "    let tail = Node { value: 2, next: null };",
+            "    let head = Node { value: 1, next: &tail };",
+            "    let values = [1, 2, 3];",
+            "    let first: *i32 = &values[0];",
+            "    return count + sum(&head) + third(first) + ((first + 2) - first);",
+            "}",
+        ];
+
+        let module = lower(&source.join("\n")).unwrap();
+        assert_eq!(Ok(()), verify_module(&module));
+
+        // `count` lives in a stack slot next to the nodes and the array,
+        // because its address is taken
+        let main = module.function("main").unwrap();
+        assert_eq!(4, main.stack_slots.len());
+
+        let result = lower("fn main() { let x = 1; let p = &mut x; }");
+        assert_eq!("cannot borrow immutable variable `x` as mutable", result.unwrap_err().message);
+
+        let result = lower("struct P { x: i32 } fn f(p: &P) { p.x = 1; }");
+        assert_eq!("cannot assign through a `&` reference", result.unwrap_err().message);
+
+        let result = lower("fn f() -> i32 { return *null; }");
+        assert_eq!("cannot dereference `null`", result.unwrap_err().message);
+
+        let result = lower("fn f(p: &i32, q: *i8) -> i32 { return p == q; }");
+        assert_eq!("cannot compare &i32 with *i8", result.unwrap_err().message);
+    }
+
+    #[test]
+    fn test_generics() {
+        let module = lower(concat!(
+            "struct Pair<T> { first: T, second: T }",
+            "enum Option<T> { None, Some(T) }",
+            "fn max<T>(a: T, b: T) -> T { return match a > b { 0 => b, _ => a }; }",
+            "fn larger<T>(pair: Pair<T>) -> T { return max(pair.first, pair.second); }",
+            "fn ignore<T>(value: T) {}",
+            "fn unwrap_or<T>(option: Option<T>, default: T) -> T {",
+            "    return match option { Option::Some(value) => value, Option::None => default };",
+            "}",
+            "fn main() -> i32 {",
+            "    let small = max(1i8, 2i8);",
+            "    let none = Option::<i32>::None;",
+            "    ignore(Pair { first: 1i8, second: 2i8 });",
+            "    ignore([none, none]);",
+            "    return larger(Pair { first: 3, second: 4 }) + max::<i32>(5, 6) + unwrap_or(Option::Some(7), 0) + unwrap_or(none, 8);",
+            "}",
+        )).unwrap();
+
+        assert_eq!(Ok(()), verify_module(&module));
+
+        let names: Vec<&str> = module.functions.iter().map(|function| function.name.as_str()).collect();
+        assert_eq!(vec!["main", "max$a", "ignore$4PairIaE", "ignore$A2_6OptionIiE", "larger$i", "max$i", "unwrap_or$i"], names);
+
+        let result = lower("enum Option<T> { None, Some(T) } fn main() { let none = Option::None; }");
+        assert_eq!("cannot infer type parameter `T` of `Option`", result.unwrap_err().message);
+
+        // The body is checked for each instance
+        let result = lower("fn add<T>(a: T) -> T { return a + 1; } fn main() { let a = add(1); let b = add(2i8); }");
+        assert_eq!("expected i8, found i32", result.unwrap_err().message);
+
+        let result = lower("struct Box<T> { value: T } fn wrap<T>(value: T) { wrap(Box { value: value }); } fn main() { wrap(1); }");
+        assert!(result.unwrap_err().message.starts_with("reached the instantiation limit while instantiating `wrap<Box<Box<"));
+    }
+
+    #[test]
+    fn test_modules() {
+        let module = lower(concat!(
+            "mod shapes {",
+            "    pub struct Square { side: i32 }",
+            "    pub enum Shape { Empty, Square(Square) }",
+            "    pub fn area(shape: Shape) -> i32 { return match shape { Shape::Square(square) => side(square), Shape::Empty => 0 }; }",
+            "    fn side(square: Square) -> i32 { return square.side * square.side; }",
+            "    pub mod util { pub fn max<T>(a: T, b: T) -> T { return match a > b { 0 => b, _ => a }; } }",
+            "}",
+            "use shapes::Shape;",
+            "use shapes::util::max;",
+            "pub fn main() -> i32 {",
+            "    let square = Shape::Square(shapes::Square { side: 3 });",
+            "    return max(shapes::area(square), shapes::area(Shape::Empty));",
+            "}",
+        )).unwrap();
+
+        assert_eq!(Ok(()), verify_module(&module));
+
+        // Functions in modules get qualified names and are not exported
+        let names: Vec<&str> = module.functions.iter().map(|function| function.name.as_str()).collect();
+        assert_eq!(vec!["main", "shapes::area", "shapes::side", "shapes::util::max$i"], names);
+        assert!(module.function("main").unwrap().exported);
+
+        let result = lower("mod a { fn f() {} } fn main() { a::f(); }");
+        assert_eq!("`a::f` is private", result.unwrap_err().message);
+
+        let result = lower("mod a { pub fn f() {} } fn main() { f(); }");
+        assert_eq!("unknown function `f`", result.unwrap_err().message);
+    }
+}
@@ -0,0 +1,362 @@
+pub mod cfg;
+pub mod display;
+pub mod builder;
+pub mod layout;
+pub mod lowering;
+pub mod modules;
+pub mod patterns;
+pub mod verifier;
+
+#[allow(unused)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Type {
+    Void,
+    I8,
+    I16,
+    I32,
+    I64,
+    Ptr,
+}
+
+impl Type {
+    pub fn is_integer(self) -> bool {
+        matches!(self, Type::I8 | Type::I16 | Type::I32 | Type::I64)
+    }
+
+    pub fn bits(self) -> u32 {
+        match self {
+            Type::Void => 0,
+            Type::I8 => 8,
+            Type::I16 => 16,
+            Type::I32 | Type::Ptr => 32,
+            Type::I64 => 64,
+        }
+    }
+
+    // Wraps `value` to the width of the type and sign-extends it back
+    pub fn truncate(self, value: i64) -> i64 {
+        let shift = 64 - self.bits();
+
+        if shift == 0 || shift == 64 {
+            value
+        } else {
+            (value << shift) >> shift
+        }
+    }
+}
+
+// %N
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct Value(pub u32);
+
+// bbN
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct BlockId(pub u32);
+
+// @dN
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct DataId(pub u32);
+
+// $sN
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct SlotId(pub u32);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div, // signed
+    Rem, // signed, takes the sign of the dividend
+    Udiv,
+    Urem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Sar, // arithmetic
+    Shr, // logical
+    // Signed comparisons, the result is 1 or 0 in the type of the operands
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // Unsigned comparisons
+    Ult,
+    Ule,
+    Ugt,
+    Uge,
+}
+
+impl BinaryOp {
+    pub fn fold(self, ty: Type, lhs: i64, rhs: i64) -> Option<i64> {
+        let bits = ty.bits();
+        let unsigned_mask = if bits == 64 { u64::MAX } else { (1u64 << bits) - 1 };
+
+        let result = match self {
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Sub => lhs.wrapping_sub(rhs),
+            BinaryOp::Mul => lhs.wrapping_mul(rhs),
+            BinaryOp::Div => ty.truncate(lhs).checked_div(ty.truncate(rhs))?,
+            BinaryOp::Rem => ty.truncate(lhs).checked_rem(ty.truncate(rhs))?,
+            BinaryOp::Udiv => ((lhs as u64 & unsigned_mask).checked_div(rhs as u64 & unsigned_mask)?) as i64,
+            BinaryOp::Urem => ((lhs as u64 & unsigned_mask).checked_rem(rhs as u64 & unsigned_mask)?) as i64,
+            BinaryOp::And => lhs & rhs,
+            BinaryOp::Or => lhs | rhs,
+            BinaryOp::Xor => lhs ^ rhs,
+            BinaryOp::Shl => lhs.wrapping_shl(rhs as u32 % bits),
+            BinaryOp::Sar => ty.truncate(lhs).wrapping_shr(rhs as u32 % bits),
+            BinaryOp::Shr => ((lhs as u64 & unsigned_mask) >> (rhs as u32 % bits)) as i64,
+            BinaryOp::Eq => (ty.truncate(lhs) == ty.truncate(rhs)) as i64,
+            BinaryOp::Ne => (ty.truncate(lhs) != ty.truncate(rhs)) as i64,
+            BinaryOp::Lt => (ty.truncate(lhs) < ty.truncate(rhs)) as i64,
+            BinaryOp::Le => (ty.truncate(lhs) <= ty.truncate(rhs)) as i64,
+            BinaryOp::Gt => (ty.truncate(lhs) > ty.truncate(rhs)) as i64,
+            BinaryOp::Ge => (ty.truncate(lhs) >= ty.truncate(rhs)) as i64,
+            BinaryOp::Ult => ((lhs as u64 & unsigned_mask) < (rhs as u64 & unsigned_mask)) as i64,
+            BinaryOp::Ule => ((lhs as u64 & unsigned_mask) <= (rhs as u64 & unsigned_mask)) as i64,
+            BinaryOp::Ugt => ((lhs as u64 & unsigned_mask) > (rhs as u64 & unsigned_mask)) as i64,
+            BinaryOp::Uge => ((lhs as u64 & unsigned_mask) >= (rhs as u64 & unsigned_mask)) as i64,
+        };
+
+        Some(ty.truncate(result))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UnaryOp {
+    Neg,
+}
+
+impl UnaryOp {
+    pub fn fold(self, ty: Type, value: i64) -> i64 {
+        match self {
+            UnaryOp::Neg => ty.truncate(value.wrapping_neg()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum InstKind {
+    Const(i64), // integers, or constant addresses like null
+    Binary(BinaryOp, Value, Value),
+    Unary(UnaryOp, Value),
+    Copy(Value),
+    Bitcast(Value), // the bits of a Ptr as an I32 or the other way
+    DataAddress(DataId),
+    StackAddress(SlotId),
+    Load(Value, i32), // address and byte offset
+    Store(Value, i32, Value), // address, byte offset and value, `ty` is the type of the value
+    ElementAddress(Value, Value, u32), // base + index * scale, the scale is 1, 2, 4 or 8 like in x86 addressing
+    Call(String, Vec<Value>),
+    Phi(Vec<(BlockId, Value)>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Inst {
+    pub result: Option<Value>,
+    pub ty: Type,
+    pub kind: InstKind,
+}
+
+impl Inst {
+    pub fn operands(&self) -> Vec<Value> {
+        match &self.kind {
+            InstKind::Const(_) | InstKind::DataAddress(_) | InstKind::StackAddress(_) => Vec::new(),
+            InstKind::Binary(_, lhs, rhs) => vec![*lhs, *rhs],
+            InstKind::Unary(_, value) | InstKind::Copy(value) | InstKind::Bitcast(value) | InstKind::Load(value, _) => vec![*value],
+            InstKind::Store(address, _, value) => vec![*address, *value],
+            InstKind::ElementAddress(base, index, _) => vec![*base, *index],
+            InstKind::Call(_, args) => args.clone(),
+            InstKind::Phi(incoming) => incoming.iter().map(|(_, value)| *value).collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match &mut self.kind {
+            InstKind::Const(_) | InstKind::DataAddress(_) | InstKind::StackAddress(_) => Vec::new(),
+            InstKind::Binary(_, lhs, rhs) => vec![lhs, rhs],
+            InstKind::Unary(_, value) | InstKind::Copy(value) | InstKind::Bitcast(value) | InstKind::Load(value, _) => vec![value],
+            InstKind::Store(address, _, value) => vec![address, value],
+            InstKind::ElementAddress(base, index, _) => vec![base, index],
+            InstKind::Call(_, args) => args.iter_mut().collect(),
+            InstKind::Phi(incoming) => incoming.iter_mut().map(|(_, value)| value).collect(),
+        }
+    }
+
+    pub fn has_side_effects(&self) -> bool {
+        matches!(self.kind, InstKind::Call(..) | InstKind::Store(..))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Terminator {
+    Return(Option<Value>),
+    Jump(BlockId),
+    Branch(Value, BlockId, BlockId), // if value != 0 then .1 else .2
+    Switch(Value, Vec<BlockId>, BlockId), // .1[value], or .2 when the value is not an index into .1
+    Unreachable,
+    Trap(DataId), // aborts the program with the message
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Return(_) | Terminator::Unreachable | Terminator::Trap(_) => Vec::new(),
+            Terminator::Jump(target) => vec![*target],
+            Terminator::Branch(_, then_block, else_block) => vec![*then_block, *else_block],
+
+            Terminator::Switch(_, cases, default) => {
+                let mut successors = cases.clone();
+                successors.push(*default);
+                successors
+            },
+        }
+    }
+
+    pub fn successors_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Return(_) | Terminator::Unreachable | Terminator::Trap(_) => Vec::new(),
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch(_, then_block, else_block) => vec![then_block, else_block],
+            Terminator::Switch(_, cases, default) => cases.iter_mut().chain(std::iter::once(default)).collect(),
+        }
+    }
+
+    pub fn operands(&self) -> Vec<Value> {
+        match self {
+            Terminator::Return(Some(value)) | Terminator::Branch(value, _, _) | Terminator::Switch(value, _, _) => vec![*value],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Return(Some(value)) | Terminator::Branch(value, _, _) | Terminator::Switch(value, _, _) => vec![value],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub terminator: Option<Terminator>,
+}
+
+// Memory in the stack frame, for values which live in memory rather than
+// in SSA values
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StackSlot {
+    pub size: u32,
+    pub align: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub exported: bool,
+    pub params: Vec<Value>,
+    pub return_type: Type,
+    pub blocks: Vec<Block>,
+    pub value_types: Vec<Type>,
+    pub stack_slots: Vec<StackSlot>,
+}
+
+impl Function {
+    pub fn new(name: &str, return_type: Type) -> Self {
+        Self {
+            name: name.to_string(),
+            exported: false,
+            params: Vec::new(),
+            return_type,
+            blocks: Vec::new(),
+            value_types: Vec::new(),
+            stack_slots: Vec::new(),
+        }
+    }
+
+    pub fn new_value(&mut self, ty: Type) -> Value {
+        self.value_types.push(ty);
+        Value(self.value_types.len() as u32 - 1)
+    }
+
+    pub fn value_type(&self, value: Value) -> Type {
+        self.value_types[value.0 as usize]
+    }
+
+    pub fn block(&self, block: BlockId) -> &Block {
+        &self.blocks[block.0 as usize]
+    }
+
+    pub fn block_mut(&mut self, block: BlockId) -> &mut Block {
+        &mut self.blocks[block.0 as usize]
+    }
+
+    pub fn block_ids(&self) -> impl Iterator<Item = BlockId> {
+        (0..self.blocks.len() as u32).map(BlockId)
+    }
+
+    pub fn entry(&self) -> BlockId {
+        BlockId(0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Data {
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CallingConvention {
+    Cdecl, // the caller pops the arguments
+    Stdcall, // the callee pops the arguments
+}
+
+// A function declared in an `extern` block, resolved by the linker or
+// imported from `library` by the loader
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ExternalFunction {
+    pub name: String,
+    pub params: Vec<Type>,
+    pub return_type: Type,
+    pub convention: CallingConvention,
+    pub library: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub externals: Vec<ExternalFunction>,
+    pub data: Vec<Data>,
+}
+
+impl Module {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Data is read-only, so identical data is shared
+    pub fn add_data(&mut self, bytes: Vec<u8>) -> DataId {
+        if let Some(index) = self.data.iter().position(|data| data.bytes == bytes) {
+            return DataId(index as u32);
+        }
+
+        self.data.push(Data { bytes });
+        DataId(self.data.len() as u32 - 1)
+    }
+
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions
+            .iter()
+            .find(|function| function.name == name)
+    }
+
+    pub fn external(&self, name: &str) -> Option<&ExternalFunction> {
+        self.externals
+            .iter()
+            .find(|external| external.name == name)
+    }
+}
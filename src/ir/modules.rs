@@ -0,0 +1,365 @@
+// The module tree of a program and the names declared and imported in each
+// module. A path is resolved from the module it is written in: its first
+// name is declared or imported there, is public in the prelude, or is
+// `crate`, `super` or `self`, and each name after it has to be visible from
+// that module. A name is visible in the module it is in and the modules
+// inside it, or everywhere with `pub`
+
+use std::collections::HashMap;
+
+use crate::parser;
+
+pub type ModuleId = usize;
+
+pub const ROOT: ModuleId = 0;
+
+// The module of the root whose public names are in scope in every module,
+// the runtime library
+pub const PRELUDE: &str = "rt";
+
+// Structs, enums and modules are types, functions are values, so a struct
+// and a function can have the same name like in Rust
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Namespace {
+    Type,
+    Value,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Target {
+    Item(String), // the qualified name of a function, struct or enum, like `a::b::f`
+    Module(ModuleId),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum ResolveError {
+    NotFound, // nothing has the last name of the path
+    Other(String),
+}
+
+#[derive(Clone)]
+struct Name {
+    target: Target,
+    public: bool,
+}
+
+pub struct Module<'ast> {
+    pub parent: Option<ModuleId>,
+    pub path: String, // `a::b`, empty for the root
+    pub items: &'ast [parser::Item],
+    types: HashMap<String, Name>,
+    values: HashMap<String, Name>,
+}
+
+#[derive(Default)]
+pub struct ModuleTree<'ast> {
+    modules: Vec<Module<'ast>>,
+    prelude: Option<ModuleId>,
+}
+
+impl<'ast> ModuleTree<'ast> {
+    pub fn new(ast: &'ast parser::Module) -> Result<Self, String> {
+        let mut tree = ModuleTree::default();
+        let mut uses = Vec::new();
+
+        tree.add_module(None, String::new(), &ast.items, &mut uses)?;
+
+        if let Some(Name { target: Target::Module(prelude), .. }) = tree.modules[ROOT].types.get(PRELUDE) {
+            tree.prelude = Some(*prelude);
+        }
+
+        tree.resolve_uses(uses)?;
+
+        Ok(tree)
+    }
+
+    pub fn modules(&self) -> &[Module<'ast>] {
+        &self.modules
+    }
+
+    // The name of an item of the module in the whole program. Items of the
+    // root keep their names, so `main` and exported functions are found by
+    // the linker
+    pub fn qualify(&self, module: ModuleId, name: &str) -> String {
+        match self.modules[module].path.as_str() {
+            "" => name.to_string(),
+            path => format!("{}::{}", path, name),
+        }
+    }
+
+    // The qualified name of the item `path` refers to in `module`
+    pub fn resolve(&self, module: ModuleId, path: &[String], namespace: Namespace) -> Result<String, ResolveError> {
+        let (name, prefix) = path.split_last().unwrap();
+        let scope = self.resolve_module(module, prefix)?;
+
+        match self.lookup(module, scope, name, namespace, prefix.is_empty())? {
+            Target::Item(item) => Ok(item),
+            Target::Module(_) => Err(ResolveError::Other(format!("expected a type, found module `{}`", path.join("::")))),
+        }
+    }
+
+    // Like `resolve`, for a path written as one name like `a::b::T`
+    pub fn resolve_name(&self, module: ModuleId, name: &str, namespace: Namespace) -> Result<String, ResolveError> {
+        let path: Vec<String> = name.split("::").map(str::to_string).collect();
+        self.resolve(module, &path, namespace)
+    }
+
+    fn add_module(
+        &mut self,
+        parent: Option<ModuleId>,
+        path: String,
+        items: &'ast [parser::Item],
+        uses: &mut Vec<(ModuleId, &'ast parser::Use)>
+    ) -> Result<ModuleId, String> {
+        let id = self.modules.len();
+
+        self.modules.push(Module {
+            parent,
+            path,
+            items,
+            types: HashMap::new(),
+            values: HashMap::new(),
+        });
+
+        for item in items {
+            match item {
+                parser::Item::Function(function) => {
+                    let target = Target::Item(self.qualify(id, &function.name));
+                    self.declare(id, Namespace::Value, &function.name, Name { target, public: function.public }, "function")?;
+                },
+
+                parser::Item::Struct(definition) => {
+                    let target = Target::Item(self.qualify(id, &definition.name));
+                    self.declare(id, Namespace::Type, &definition.name, Name { target, public: definition.public }, "struct")?;
+                },
+
+                parser::Item::Enum(definition) => {
+                    let target = Target::Item(self.qualify(id, &definition.name));
+                    self.declare(id, Namespace::Type, &definition.name, Name { target, public: definition.public }, "enum")?;
+                },
+
+                // External functions keep their names, the linker looks for them
+                parser::Item::Extern(block) => {
+                    for function in &block.functions {
+                        let target = Target::Item(function.name.clone());
+                        self.declare(id, Namespace::Value, &function.name, Name { target, public: block.public }, "function")?;
+                    }
+                },
+
+                parser::Item::Module(declaration) => {
+                    let Some(items) = &declaration.items else {
+                        return Err(format!("module `{}` is not loaded", declaration.name));
+                    };
+
+                    let child = self.add_module(Some(id), self.qualify(id, &declaration.name), items, uses)?;
+                    let name = Name { target: Target::Module(child), public: declaration.public };
+
+                    self.declare(id, Namespace::Type, &declaration.name, name, "module")?;
+                },
+
+                parser::Item::Use(use_) => uses.push((id, use_)),
+            }
+        }
+
+        Ok(id)
+    }
+
+    fn declare(&mut self, module: ModuleId, namespace: Namespace, name: &str, entry: Name, kind: &str) -> Result<(), String> {
+        let names = match namespace {
+            Namespace::Type => &mut self.modules[module].types,
+            Namespace::Value => &mut self.modules[module].values,
+        };
+
+        if names.insert(name.to_string(), entry).is_some() {
+            return Err(format!("{} `{}` is defined more than once", kind, name));
+        }
+
+        Ok(())
+    }
+
+    // Imports can refer to the names other imports bring in, so they are
+    // resolved over and over until the rest cannot be
+    fn resolve_uses(&mut self, mut uses: Vec<(ModuleId, &'ast parser::Use)>) -> Result<(), String> {
+        while !uses.is_empty() {
+            let mut unresolved = Vec::new();
+            let mut error = None;
+
+            for (module, use_) in uses.iter().copied() {
+                match self.import(module, use_)? {
+                    Ok(()) => {},
+
+                    Err(message) => {
+                        unresolved.push((module, use_));
+                        error = Some(message);
+                    },
+                }
+            }
+
+            if unresolved.len() == uses.len() {
+                return Err(error.unwrap());
+            }
+
+            uses = unresolved;
+        }
+
+        Ok(())
+    }
+
+    // Brings the type and the value the path names into the module. The
+    // inner result is an error which a later import may resolve
+    fn import(&mut self, module: ModuleId, use_: &parser::Use) -> Result<Result<(), String>, String> {
+        let (name, prefix) = use_.path.split_last().unwrap();
+
+        let scope = match self.resolve_module(module, prefix) {
+            Ok(scope) => scope,
+            Err(ResolveError::Other(message)) => return Ok(Err(message)),
+            Err(ResolveError::NotFound) => unreachable!(),
+        };
+
+        let mut found = false;
+
+        for namespace in [Namespace::Type, Namespace::Value] {
+            let target = match self.lookup(module, scope, name, namespace, prefix.is_empty()) {
+                Ok(target) => target,
+                Err(ResolveError::NotFound) => continue,
+                Err(ResolveError::Other(message)) => return Ok(Err(message)),
+            };
+
+            self.declare(module, namespace, name, Name { target, public: use_.public }, "import")?;
+            found = true;
+        }
+
+        if !found {
+            return Ok(Err(format!("unresolved import `{}`", use_.path.join("::"))));
+        }
+
+        Ok(Ok(()))
+    }
+
+    fn resolve_module(&self, module: ModuleId, prefix: &[String]) -> Result<ModuleId, ResolveError> {
+        let mut current = module;
+
+        for (index, segment) in prefix.iter().enumerate() {
+            current = match segment.as_str() {
+                "crate" if index == 0 => ROOT,
+                "self" if index == 0 => module,
+
+                // `super::super::a`
+                "super" if prefix[..index].iter().all(|segment| segment == "super") => match self.modules[current].parent {
+                    Some(parent) => parent,
+                    None => return Err(ResolveError::Other("`super` used in the root module".to_string())),
+                },
+
+                _ => match self.lookup(module, current, segment, Namespace::Type, index == 0) {
+                    Ok(Target::Module(child)) => child,
+                    Ok(Target::Item(_)) => return Err(ResolveError::Other(format!("`{}` is not a module", segment))),
+                    Err(ResolveError::NotFound) => return Err(ResolveError::Other(format!("unknown module `{}`", segment))),
+                    Err(error) => return Err(error),
+                },
+            };
+        }
+
+        Ok(current)
+    }
+
+    // `name` in `scope`, which has to be visible from `module` unless it is
+    // found without a path
+    fn lookup(&self, module: ModuleId, scope: ModuleId, name: &str, namespace: Namespace, local: bool) -> Result<Target, ResolveError> {
+        let names = match namespace {
+            Namespace::Type => &self.modules[scope].types,
+            Namespace::Value => &self.modules[scope].values,
+        };
+
+        let Some(entry) = names.get(name) else {
+            return match self.prelude {
+                Some(prelude) if local && prelude != scope => self.lookup_prelude(prelude, name, namespace),
+                _ => Err(ResolveError::NotFound),
+            };
+        };
+
+        if !local && !entry.public && !self.is_inside(module, scope) {
+            return Err(ResolveError::Other(format!("`{}` is private", self.qualify(scope, name))));
+        }
+
+        Ok(entry.target.clone())
+    }
+
+    // Private names of the prelude are not found, so that they do not hide
+    // a misspelled name
+    fn lookup_prelude(&self, prelude: ModuleId, name: &str, namespace: Namespace) -> Result<Target, ResolveError> {
+        let names = match namespace {
+            Namespace::Type => &self.modules[prelude].types,
+            Namespace::Value => &self.modules[prelude].values,
+        };
+
+        match names.get(name) {
+            Some(entry) if entry.public => Ok(entry.target.clone()),
+            _ => Err(ResolveError::NotFound),
+        }
+    }
+
+    // Whether `module` is `ancestor` or inside it
+    fn is_inside(&self, module: ModuleId, ancestor: ModuleId) -> bool {
+        let mut current = Some(module);
+
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+
+            current = self.modules[id].parent;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::modules::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn build(source: &str) -> Result<(), String> {
+        let ast = Parser::new(Lexer::new(source)).parse_module().unwrap();
+        ModuleTree::new(&ast).map(|_| ())
+    }
+
+    #[test]
+    fn test_resolve() {
+        let source = concat!(
+            "mod shapes { pub struct Point {} struct Hidden {} pub mod area { pub fn f() {} } } ",
+            "mod prelude { pub use crate::shapes::area::f; } ",
+            "use shapes::Point;",
+        );
+
+        let ast = Parser::new(Lexer::new(source)).parse_module().unwrap();
+        let tree = ModuleTree::new(&ast).unwrap();
+        let path = |path: &str| -> Vec<String> { path.split("::").map(str::to_string).collect() };
+
+        assert_eq!(Ok("shapes::Point".to_string()), tree.resolve(ROOT, &path("Point"), Namespace::Type));
+        assert_eq!(Ok("shapes::area::f".to_string()), tree.resolve(ROOT, &path("prelude::f"), Namespace::Value));
+        assert_eq!(Err(ResolveError::NotFound), tree.resolve(ROOT, &path("Point"), Namespace::Value));
+
+        // Private items are visible inside their module only
+        let area = tree.modules().iter().position(|module| module.path == "shapes::area").unwrap();
+        assert_eq!(Ok("shapes::Hidden".to_string()), tree.resolve(area, &path("super::Hidden"), Namespace::Type));
+
+        assert_eq!(
+            Err(ResolveError::Other("`shapes::Hidden` is private".to_string())),
+            tree.resolve(ROOT, &path("shapes::Hidden"), Namespace::Type)
+        );
+
+        assert_eq!(Err("unresolved import `shapes::Circle`".to_string()), build("mod shapes {} use shapes::Circle;"));
+        assert_eq!(Err("struct `A` is defined more than once".to_string()), build("struct A {} struct A {}"));
+
+        // Public names of the prelude are in scope everywhere, unless they are shadowed
+        let ast = Parser::new(Lexer::new("mod rt { pub fn print() {} fn write() {} } mod a { fn print() {} }")).parse_module().unwrap();
+        let tree = ModuleTree::new(&ast).unwrap();
+        let a = tree.modules().iter().position(|module| module.path == "a").unwrap();
+
+        assert_eq!(Ok("rt::print".to_string()), tree.resolve(ROOT, &path("print"), Namespace::Value));
+        assert_eq!(Ok("a::print".to_string()), tree.resolve(a, &path("print"), Namespace::Value));
+        assert_eq!(Err(ResolveError::NotFound), tree.resolve(a, &path("write"), Namespace::Value));
+    }
+}
@@ -0,0 +1,267 @@
+// Exhaustiveness and reachability of `match` arms, with the usefulness
+// algorithm from "Warnings for pattern matching" (Maranget, 2007): a
+// pattern is useful after some rows when there is a value it matches and
+// none of the rows do. An arm which is not useful after the arms before it
+// is unreachable, and the match is exhaustive when `_` is not useful after
+// all of them
+
+// Patterns after type checking, bindings are wildcards
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Variant(usize, Vec<Pattern>), // index of the variant and its payload
+    Integer(i64),
+}
+
+// What the checker needs to know about the type of a pattern
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PatternType {
+    Enum(String, Vec<(String, Vec<PatternType>)>), // name, and the variants with their payloads
+    Integer,
+    Other, // only matched by wildcards
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum PatternError {
+    UnreachableArm(usize),
+    NotCovered(String), // a value no arm matches, like `Shape::Rect(_, _)`
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Constructor {
+    Variant(usize),
+    Integer(i64),
+}
+
+pub fn check_match(ty: &PatternType, arms: &[Pattern]) -> Result<(), PatternError> {
+    let mut rows = Vec::new();
+
+    for (index, arm) in arms.iter().enumerate() {
+        let row = vec![arm.clone()];
+
+        if useful(&rows, &row, &[ty]).is_none() {
+            return Err(PatternError::UnreachableArm(index));
+        }
+
+        rows.push(row);
+    }
+
+    match useful(&rows, &[Pattern::Wildcard], &[ty]) {
+        Some(witness) => Err(PatternError::NotCovered(display(&witness[0], ty))),
+        None => Ok(()),
+    }
+}
+
+pub fn display(pattern: &Pattern, ty: &PatternType) -> String {
+    match (pattern, ty) {
+        (Pattern::Variant(index, payload), PatternType::Enum(name, variants)) => {
+            let (variant, fields) = &variants[*index];
+
+            if payload.is_empty() {
+                return format!("{}::{}", name, variant);
+            }
+
+            let payload: Vec<String> = payload
+                .iter()
+                .zip(fields)
+                .map(|(pattern, ty)| display(pattern, ty))
+                .collect();
+
+            format!("{}::{}({})", name, variant, payload.join(", "))
+        },
+
+        (Pattern::Integer(value), _) => value.to_string(),
+        _ => "_".to_string(),
+    }
+}
+
+// Some row of values which `row` matches and `rows` do not, as patterns
+// with wildcards for any value
+fn useful(rows: &[Vec<Pattern>], row: &[Pattern], types: &[&PatternType]) -> Option<Vec<Pattern>> {
+    let Some((first, rest)) = row.split_first() else {
+        return if rows.is_empty() { Some(Vec::new()) } else { None };
+    };
+
+    let ty = types[0];
+
+    let constructor = match first {
+        Pattern::Variant(index, _) => Constructor::Variant(*index),
+        Pattern::Integer(value) => Constructor::Integer(*value),
+        Pattern::Wildcard => return useful_wildcard(rows, rest, types),
+    };
+
+    let mut specialized_row = arguments(first, ty, constructor);
+    specialized_row.extend_from_slice(rest);
+
+    let witness = useful(&specialize(rows, ty, constructor), &specialized_row, &field_types(ty, constructor, types))?;
+    Some(rebuild(witness, ty, constructor))
+}
+
+// When the rows use every variant of an enum, `_` is useful if it is for
+// one of the variants. Otherwise it is useful for the values the rows
+// don't cover, if the rest of the row is useful after the rows starting
+// with `_`
+fn useful_wildcard(rows: &[Vec<Pattern>], rest: &[Pattern], types: &[&PatternType]) -> Option<Vec<Pattern>> {
+    let ty = types[0];
+
+    let mut used = Vec::new();
+
+    for row in rows {
+        match &row[0] {
+            Pattern::Variant(index, _) => used.push(Constructor::Variant(*index)),
+            Pattern::Integer(value) => used.push(Constructor::Integer(*value)),
+            Pattern::Wildcard => {},
+        }
+    }
+
+    if let PatternType::Enum(_, variants) = ty {
+        let complete = (0..variants.len()).all(|index| used.contains(&Constructor::Variant(index)));
+
+        if complete {
+            return (0..variants.len()).find_map(|index| {
+                let constructor = Constructor::Variant(index);
+                let mut row = vec![Pattern::Wildcard; variants[index].1.len()];
+                row.extend_from_slice(rest);
+
+                let witness = useful(&specialize(rows, ty, constructor), &row, &field_types(ty, constructor, types))?;
+                Some(rebuild(witness, ty, constructor))
+            });
+        }
+    }
+
+    let default: Vec<Vec<Pattern>> = rows
+        .iter()
+        .filter(|row| row[0] == Pattern::Wildcard)
+        .map(|row| row[1..].to_vec())
+        .collect();
+
+    let mut witness = useful(&default, rest, &types[1..])?;
+
+    // Some variant nothing matches, or any value if nothing matches at all
+    let missing = match ty {
+        PatternType::Enum(_, variants) if !used.is_empty() => (0..variants.len())
+            .find(|index| !used.contains(&Constructor::Variant(*index)))
+            .map(|index| Pattern::Variant(index, vec![Pattern::Wildcard; variants[index].1.len()]))
+            .unwrap(),
+
+        _ => Pattern::Wildcard,
+    };
+
+    witness.insert(0, missing);
+    Some(witness)
+}
+
+// The rows for values built with `constructor`, with its arguments in
+// place of the first pattern
+fn specialize(rows: &[Vec<Pattern>], ty: &PatternType, constructor: Constructor) -> Vec<Vec<Pattern>> {
+    rows.iter()
+        .filter(|row| match (&row[0], constructor) {
+            (Pattern::Wildcard, _) => true,
+            (Pattern::Variant(index, _), Constructor::Variant(other)) => *index == other,
+            (Pattern::Integer(value), Constructor::Integer(other)) => *value == other,
+            _ => false,
+        })
+        .map(|row| {
+            let mut specialized = arguments(&row[0], ty, constructor);
+            specialized.extend_from_slice(&row[1..]);
+            specialized
+        })
+        .collect()
+}
+
+fn arguments(pattern: &Pattern, ty: &PatternType, constructor: Constructor) -> Vec<Pattern> {
+    match (pattern, ty, constructor) {
+        (Pattern::Variant(_, payload), _, _) => payload.clone(),
+        (Pattern::Wildcard, PatternType::Enum(_, variants), Constructor::Variant(index)) => {
+            vec![Pattern::Wildcard; variants[index].1.len()]
+        },
+
+        _ => Vec::new(),
+    }
+}
+
+// The types of the arguments of `constructor` followed by the rest of `types`
+fn field_types<'a>(ty: &'a PatternType, constructor: Constructor, types: &[&'a PatternType]) -> Vec<&'a PatternType> {
+    let mut fields: Vec<&PatternType> = match (ty, constructor) {
+        (PatternType::Enum(_, variants), Constructor::Variant(index)) => variants[index].1.iter().collect(),
+        _ => Vec::new(),
+    };
+
+    fields.extend_from_slice(&types[1..]);
+    fields
+}
+
+// Puts the arguments at the start of `witness` back into `constructor`
+fn rebuild(mut witness: Vec<Pattern>, ty: &PatternType, constructor: Constructor) -> Vec<Pattern> {
+    let pattern = match (ty, constructor) {
+        (PatternType::Enum(_, variants), Constructor::Variant(index)) => {
+            let payload = witness.drain(..variants[index].1.len()).collect();
+            Pattern::Variant(index, payload)
+        },
+
+        (_, Constructor::Integer(value)) => Pattern::Integer(value),
+        (_, Constructor::Variant(index)) => Pattern::Variant(index, Vec::new()),
+    };
+
+    witness.insert(0, pattern);
+    witness
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::patterns::*;
+
+    fn option(payload: PatternType) -> PatternType {
+        PatternType::Enum("Option".to_string(), vec![
+            ("None".to_string(), Vec::new()),
+            ("Some".to_string(), vec![payload]),
+        ])
+    }
+
+    fn some(pattern: Pattern) -> Pattern {
+        Pattern::Variant(1, vec![pattern])
+    }
+
+    #[test]
+    fn test_exhaustiveness() {
+        let ty = option(option(PatternType::Integer));
+        let none = Pattern::Variant(0, Vec::new());
+
+        assert_eq!(Ok(()), check_match(&ty, &[some(some(Pattern::Wildcard)), some(none.clone()), none.clone()]));
+        assert_eq!(Ok(()), check_match(&PatternType::Integer, &[Pattern::Integer(1), Pattern::Wildcard]));
+
+        assert_eq!(
+            Err(PatternError::NotCovered("Option::Some(Option::None)".to_string())),
+            check_match(&ty, &[some(some(Pattern::Wildcard)), none.clone()])
+        );
+
+        assert_eq!(
+            Err(PatternError::NotCovered("Option::Some(Option::Some(_))".to_string())),
+            check_match(&ty, &[some(some(Pattern::Integer(0))), some(none.clone()), none.clone()])
+        );
+
+        assert_eq!(
+            Err(PatternError::NotCovered("_".to_string())),
+            check_match(&PatternType::Integer, &[Pattern::Integer(1)])
+        );
+    }
+
+    #[test]
+    fn test_unreachable_arms() {
+        let ty = option(PatternType::Integer);
+
+        assert_eq!(
+            Err(PatternError::UnreachableArm(2)),
+            check_match(&ty, &[some(Pattern::Wildcard), Pattern::Wildcard, Pattern::Variant(0, Vec::new())])
+        );
+
+        assert_eq!(
+            Err(PatternError::UnreachableArm(1)),
+            check_match(&PatternType::Integer, &[Pattern::Wildcard, Pattern::Integer(1)])
+        );
+
+        // An empty enum has no values
+        let never = PatternType::Enum("Never".to_string(), Vec::new());
+        assert_eq!(Ok(()), check_match(&never, &[]));
+    }
+}
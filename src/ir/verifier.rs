@@ -0,0 +1,440 @@
+use std::fmt;
+
+use super::cfg::ControlFlowGraph;
+use super::{BlockId, Function, Inst, InstKind, Module, Terminator, Type, Value};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct VerifierError {
+    pub function: String,
+    pub message: String,
+}
+
+impl fmt::Display for VerifierError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "in function `{}`: {}", self.function, self.message)
+    }
+}
+
+// Where a value is defined: parameters are defined before the entry block
+#[derive(Clone, Copy)]
+enum Definition {
+    Param,
+    Inst(BlockId, usize),
+}
+
+pub fn verify_module(module: &Module) -> Result<(), Vec<VerifierError>> {
+    let mut errors = Vec::new();
+
+    for function in &module.functions {
+        let mut verifier = FunctionVerifier::new(module, function);
+        verifier.verify();
+        errors.append(&mut verifier.errors);
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+struct FunctionVerifier<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    definitions: Vec<Option<Definition>>,
+    errors: Vec<VerifierError>,
+}
+
+impl<'a> FunctionVerifier<'a> {
+    fn new(module: &'a Module, function: &'a Function) -> Self {
+        Self {
+            module,
+            function,
+            definitions: vec![None; function.value_types.len()],
+            errors: Vec::new(),
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        self.errors.push(VerifierError {
+            function: self.function.name.clone(),
+            message,
+        });
+    }
+
+    fn verify(&mut self) {
+        if self.function.blocks.is_empty() {
+            self.error("function has no blocks".to_string());
+            return;
+        }
+
+        if !self.verify_terminators() {
+            return;
+        }
+
+        self.collect_definitions();
+
+        if !self.errors.is_empty() {
+            return;
+        }
+
+        let cfg = ControlFlowGraph::new(self.function);
+
+        for block_id in self.function.block_ids() {
+            self.verify_block(&cfg, block_id);
+        }
+    }
+
+    fn verify_terminators(&mut self) -> bool {
+        let block_count = self.function.blocks.len() as u32;
+        let mut valid = true;
+
+        for block_id in self.function.block_ids() {
+            match &self.function.block(block_id).terminator {
+                None => {
+                    self.error(format!("{} has no terminator", block_id));
+                    valid = false;
+                },
+
+                Some(terminator) => {
+                    for successor in terminator.successors() {
+                        if successor.0 >= block_count {
+                            self.error(format!("{} branches to unknown block {}", block_id, successor));
+                            valid = false;
+                        }
+                    }
+                },
+            }
+        }
+
+        valid
+    }
+
+    fn collect_definitions(&mut self) {
+        for &param in &self.function.params {
+            self.define(param, Definition::Param);
+        }
+
+        for block_id in self.function.block_ids() {
+            for (index, inst) in self.function.block(block_id).insts.iter().enumerate() {
+                match inst.result {
+                    Some(result) => {
+                        self.define(result, Definition::Inst(block_id, index));
+
+                        if self.function.value_types.get(result.0 as usize) != Some(&inst.ty) {
+                            self.error(format!("type of {} does not match its instruction", result));
+                        }
+                    },
+
+                    // A store has the type of the value it writes
+                    None if inst.ty != Type::Void && !matches!(inst.kind, InstKind::Store(..)) => {
+                        self.error(format!("`{}` in {} produces a {} but has no result", inst, block_id, inst.ty));
+                    },
+
+                    None => {},
+                }
+            }
+        }
+    }
+
+    fn define(&mut self, value: Value, definition: Definition) {
+        match self.definitions.get(value.0 as usize) {
+            None => self.error(format!("{} has no type", value)),
+            Some(Some(_)) => self.error(format!("{} is defined more than once", value)),
+            Some(None) => self.definitions[value.0 as usize] = Some(definition),
+        }
+    }
+
+    fn verify_block(&mut self, cfg: &ControlFlowGraph, block_id: BlockId) {
+        let block = self.function.block(block_id);
+        let mut seen_non_phi = false;
+
+        for (index, inst) in block.insts.iter().enumerate() {
+            if let InstKind::Phi(incoming) = &inst.kind {
+                if seen_non_phi {
+                    self.error(format!("phi `{}` in {} is not at the start of the block", inst, block_id));
+                }
+
+                self.verify_phi(cfg, block_id, inst, incoming);
+            } else {
+                seen_non_phi = true;
+
+                for operand in inst.operands() {
+                    self.verify_use(cfg, operand, block_id, index);
+                }
+
+                self.verify_inst_types(inst, block_id);
+            }
+        }
+
+        let terminator = block.terminator.as_ref().unwrap();
+
+        for operand in terminator.operands() {
+            self.verify_use(cfg, operand, block_id, block.insts.len());
+        }
+
+        self.verify_terminator_types(terminator, block_id);
+    }
+
+    fn verify_phi(&mut self, cfg: &ControlFlowGraph, block_id: BlockId, inst: &Inst, incoming: &[(BlockId, Value)]) {
+        let mut predecessors = cfg.predecessors(block_id).to_vec();
+        let mut incoming_blocks: Vec<BlockId> = incoming.iter().map(|(block, _)| *block).collect();
+
+        predecessors.sort();
+        predecessors.dedup();
+        incoming_blocks.sort();
+
+        if predecessors != incoming_blocks {
+            self.error(format!("phi `{}` in {} does not match the block predecessors", inst, block_id));
+        }
+
+        for &(predecessor, value) in incoming {
+            if (predecessor.0 as usize) < self.function.blocks.len() {
+                let end = self.function.block(predecessor).insts.len();
+                self.verify_use(cfg, value, predecessor, end);
+            }
+
+            self.expect_type(value, inst.ty, &format!("phi in {}", block_id));
+        }
+    }
+
+    fn verify_use(&mut self, cfg: &ControlFlowGraph, value: Value, block_id: BlockId, index: usize) {
+        let definition = match self.definitions.get(value.0 as usize) {
+            Some(Some(definition)) => *definition,
+            _ => {
+                self.error(format!("{} is used in {} but never defined", value, block_id));
+                return;
+            },
+        };
+
+        // Uses in unreachable code can't be checked for dominance
+        if !cfg.is_reachable(block_id) {
+            return;
+        }
+
+        let dominates = match definition {
+            Definition::Param => true,
+            Definition::Inst(def_block, def_index) if def_block == block_id => def_index < index,
+            Definition::Inst(def_block, _) => cfg.dominates(def_block, block_id),
+        };
+
+        if !dominates {
+            self.error(format!("definition of {} does not dominate its use in {}", value, block_id));
+        }
+    }
+
+    fn verify_inst_types(&mut self, inst: &Inst, block_id: BlockId) {
+        let context = format!("`{}` in {}", inst, block_id);
+
+        match &inst.kind {
+            InstKind::Const(_) => {
+                if !inst.ty.is_integer() && inst.ty != Type::Ptr {
+                    self.error(format!("{}: constant must be an integer or a pointer", context));
+                }
+            },
+
+            InstKind::Binary(_, lhs, rhs) => {
+                if !inst.ty.is_integer() {
+                    self.error(format!("{}: operands must be integers", context));
+                }
+
+                self.expect_type(*lhs, inst.ty, &context);
+                self.expect_type(*rhs, inst.ty, &context);
+            },
+
+            InstKind::Unary(_, value) => {
+                if !inst.ty.is_integer() {
+                    self.error(format!("{}: operand must be an integer", context));
+                }
+
+                self.expect_type(*value, inst.ty, &context);
+            },
+
+            InstKind::Copy(value) => self.expect_type(*value, inst.ty, &context),
+
+            InstKind::Bitcast(value) => {
+                let from = self.function.value_type(*value);
+
+                if ![Type::I32, Type::Ptr].contains(&from) || ![Type::I32, Type::Ptr].contains(&inst.ty) {
+                    self.error(format!("{}: bitcast must be between i32 and ptr", context));
+                }
+            },
+
+            InstKind::DataAddress(data) => {
+                if inst.ty != Type::Ptr {
+                    self.error(format!("{}: address must be a pointer", context));
+                }
+
+                if data.0 as usize >= self.module.data.len() {
+                    self.error(format!("{}: unknown data {}", context, data));
+                }
+            },
+
+            InstKind::StackAddress(slot) => {
+                if inst.ty != Type::Ptr {
+                    self.error(format!("{}: address must be a pointer", context));
+                }
+
+                if slot.0 as usize >= self.function.stack_slots.len() {
+                    self.error(format!("{}: unknown stack slot {}", context, slot));
+                }
+            },
+
+            InstKind::Load(address, _) => {
+                if inst.ty == Type::Void {
+                    self.error(format!("{}: cannot load void", context));
+                }
+
+                self.expect_type(*address, Type::Ptr, &context);
+            },
+
+            InstKind::Store(address, _, value) => {
+                self.expect_type(*address, Type::Ptr, &context);
+                self.expect_type(*value, inst.ty, &context);
+            },
+
+            InstKind::ElementAddress(base, index, scale) => {
+                if inst.ty != Type::Ptr {
+                    self.error(format!("{}: address must be a pointer", context));
+                }
+
+                if ![1, 2, 4, 8].contains(scale) {
+                    self.error(format!("{}: scale must be 1, 2, 4 or 8", context));
+                }
+
+                self.expect_type(*base, Type::Ptr, &context);
+                self.expect_type(*index, Type::I32, &context);
+            },
+
+            InstKind::Call(name, args) => {
+                let (params, return_type) = if let Some(callee) = self.module.function(name) {
+                    let params: Vec<Type> = callee.params
+                        .iter()
+                        .map(|param| callee.value_type(*param))
+                        .collect();
+
+                    (params, callee.return_type)
+                } else if let Some(external) = self.module.external(name) {
+                    (external.params.clone(), external.return_type)
+                } else {
+                    self.error(format!("{}: unknown function `{}`", context, name));
+                    return;
+                };
+
+                if return_type != inst.ty {
+                    self.error(format!("{}: `{}` returns {}", context, name, return_type));
+                }
+
+                if params.len() != args.len() {
+                    self.error(format!("{}: `{}` takes {} arguments", context, name, params.len()));
+                    return;
+                }
+
+                for (param, arg) in params.iter().zip(args) {
+                    self.expect_type(*arg, *param, &context);
+                }
+            },
+
+            InstKind::Phi(_) => unreachable!(),
+        }
+    }
+
+    fn verify_terminator_types(&mut self, terminator: &Terminator, block_id: BlockId) {
+        let context = format!("`{}` in {}", terminator, block_id);
+
+        match terminator {
+            Terminator::Return(Some(value)) => {
+                self.expect_type(*value, self.function.return_type, &context);
+            },
+
+            Terminator::Return(None) => {
+                if self.function.return_type != Type::Void {
+                    self.error(format!("{}: function must return {}", context, self.function.return_type));
+                }
+            },
+
+            Terminator::Branch(condition, _, _) => {
+                if !self.function.value_type(*condition).is_integer() {
+                    self.error(format!("{}: condition must be an integer", context));
+                }
+            },
+
+            Terminator::Switch(value, _, _) => {
+                if !self.function.value_type(*value).is_integer() {
+                    self.error(format!("{}: value must be an integer", context));
+                }
+            },
+
+            Terminator::Trap(message) => {
+                if message.0 as usize >= self.module.data.len() {
+                    self.error(format!("{}: unknown data {}", context, message));
+                }
+            },
+
+            Terminator::Jump(_) | Terminator::Unreachable => {},
+        }
+    }
+
+    fn expect_type(&mut self, value: Value, expected: Type, context: &str) {
+        match self.function.value_types.get(value.0 as usize) {
+            Some(&actual) if actual != expected => {
+                self.error(format!("{}: expected {} to be {}, found {}", context, value, expected, actual));
+            },
+
+            _ => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::builder::FunctionBuilder;
+    use crate::ir::verifier::*;
+    use crate::ir::BinaryOp;
+
+    #[test]
+    fn test_valid_function() {
+        let mut function = Function::new("main", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        builder.switch_to_block(entry);
+
+        let lhs = builder.iconst(Type::I32, 2);
+        let rhs = builder.iconst(Type::I32, 3);
+        let sum = builder.binary(BinaryOp::Add, lhs, rhs);
+        builder.ret(Some(sum));
+
+        let module = Module { functions: vec![function], ..Module::default() };
+
+        assert_eq!(Ok(()), verify_module(&module));
+    }
+
+    #[test]
+    fn test_use_before_definition() {
+        let mut function = Function::new("main", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        let exit = builder.create_block();
+        let other = builder.create_block();
+
+        builder.switch_to_block(entry);
+        let condition = builder.iconst(Type::I32, 1);
+        builder.branch(condition, exit, other);
+
+        builder.switch_to_block(other);
+        let value = builder.iconst(Type::I32, 7);
+        builder.jump(exit);
+
+        // `value` is defined on only one path into `exit`
+        builder.switch_to_block(exit);
+        builder.ret(Some(value));
+
+        let module = Module { functions: vec![function], ..Module::default() };
+        let errors = verify_module(&module).unwrap_err();
+
+        assert_eq!(1, errors.len());
+        assert!(errors[0].message.contains("does not dominate"));
+    }
+}
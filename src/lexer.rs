@@ -1,162 +1,625 @@
+use std::borrow::Cow;
+
+use crate::diagnostic::Diagnostic;
 use crate::token::Token;
 
-pub struct Lexer {
-    source_chars: Vec<char>,
-    source_offset: usize,
-    tokens: Vec<Token>,
+const INTEGER_SUFFIXES: &[&str] = &["i8", "i16", "i32", "i64", "u8", "u16", "u32", "u64"];
+const FLOAT_SUFFIXES: &[&str] = &["f32", "f64"];
+
+// Longest first, so that `<<=` is not taken for `<` `<=`
+const OPERATORS: &[(&str, Token<'static>)] = &[
+    ("<<=", Token::ShiftLeftEqual),
+    (">>=", Token::ShiftRightEqual),
+    ("->", Token::Arrow),
+    ("=>", Token::FatArrow),
+    ("::", Token::ColonColon),
+    ("==", Token::EqualEqual),
+    ("!=", Token::NotEqual),
+    ("<=", Token::LessEqual),
+    (">=", Token::GreaterEqual),
+    ("&&", Token::AndAnd),
+    ("||", Token::OrOr),
+    ("**", Token::StarStar),
+    ("<<", Token::ShiftLeft),
+    (">>", Token::ShiftRight),
+    ("+=", Token::PlusEqual),
+    ("-=", Token::MinusEqual),
+    ("*=", Token::StarEqual),
+    ("/=", Token::SlashEqual),
+    ("%=", Token::PercentEqual),
+    ("&=", Token::AmpersandEqual),
+    ("|=", Token::PipeEqual),
+    ("^=", Token::CaretEqual),
+    ("+", Token::Plus),
+    ("-", Token::Minus),
+    ("*", Token::Star),
+    ("/", Token::Slash),
+    ("%", Token::Percent),
+    ("&", Token::Ampersand),
+    ("|", Token::Pipe),
+    ("^", Token::Caret),
+    ("!", Token::Bang),
+    ("<", Token::Less),
+    (">", Token::Greater),
+    ("=", Token::Equal),
+    ("(", Token::LParen),
+    (")", Token::RParen),
+    ("{", Token::LBrace),
+    ("}", Token::RBrace),
+    ("[", Token::LBracket),
+    ("]", Token::RBracket),
+    (",", Token::Comma),
+    (":", Token::Colon),
+    (";", Token::Semicolon),
+    (".", Token::Dot),
+];
+
+// Reserved words, `r#` turns them back into identifiers
+pub const KEYWORDS: &[(&str, Token<'static>)] = &[
+    ("fn", Token::Fn),
+    ("let", Token::Let),
+    ("mut", Token::Mut),
+    ("if", Token::If),
+    ("else", Token::Else),
+    ("while", Token::While),
+    ("loop", Token::Loop),
+    ("return", Token::Return),
+    ("true", Token::True),
+    ("false", Token::False),
+    ("null", Token::Null),
+    ("struct", Token::Struct),
+    ("enum", Token::Enum),
+    ("match", Token::Match),
+    ("extern", Token::Extern),
+    ("pub", Token::Pub),
+    ("use", Token::Use),
+    ("mod", Token::Mod),
+    ("const", Token::Const),
+];
+
+pub struct Lexer<'a> {
+    source: &'a str,
+    source_offset: usize, // in bytes, always on a character boundary
+    diagnostics: Vec<Diagnostic>,
 }
 
-impl Lexer {
-    pub fn new(source: String) -> Self {
-        Self {
-            source_chars: source.chars().collect(),
-            source_offset: 0,
-            tokens: Vec::new(),
-        }
-    }
+// Names may use any Unicode letters, `café` and `имя` are identifiers
+fn is_identifier_start(ch: char) -> bool {
+    ch.is_alphabetic() || ch == '_'
+}
 
-    fn add_token(&mut self, token: Token) {
-        self.tokens.push(token);
-    }
+fn is_identifier_continue(ch: char) -> bool {
+    is_identifier_start(ch) || ch.is_ascii_digit()
+}
 
-    pub fn tokenize(&mut self) -> &[Token] {
-        self.source_offset = 0;
-        self.tokens.clear();
+// Tokens are produced one at a time while the parser asks for them,
+// whitespace and comments are skipped
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
 
-        while let Some(current_character) = self.peek() {
-            let token_opt = match current_character {
-                ch if ch.is_whitespace() => {
-                    self.next();
+    fn next(&mut self) -> Option<Token<'a>> {
+        while let Some(&byte) = self.source.as_bytes().get(self.source_offset) {
+            let token_opt = match byte {
+                b' ' | b'\t' | b'\r' | b'\n' => {
+                    self.source_offset += 1;
                     None
                 },
 
-                ch if ch.is_alphabetic() || ch == '_' => {
+                b'r' if self.raw_string_hashes().is_some() => {
+                    Some(self.tokenize_raw_string_literal())
+                }
+
+                b'b' if self.peek_at(1) == Some('"') => {
+                    Some(self.tokenize_string_literal(true))
+                }
+
+                b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
                     Some(self.tokenize_identifier())
                 }
 
-                ch if ch == '"' => {
-                    Some(self.tokenize_string_literal())
+                b'"' => {
+                    Some(self.tokenize_string_literal(false))
                 }
 
-                ch if ch.is_digit(10) => {
+                b'\'' => {
+                    Some(self.tokenize_char_literal())
+                }
+
+                b'0'..=b'9' => {
                     Some(self.tokenize_number_literal())
                 }
 
-                ch if "+-*/=(){},:;".contains(ch) => {
-                    Some(self.tokenize_single_character())
+                b'/' if self.peek_at(1) == Some('/') => {
+                    self.tokenize_line_comment()
                 }
 
-                ch => {
-                    println!("skip unknown character: `{}`", ch);
-                    self.next();
+                b'/' if self.peek_at(1) == Some('*') => {
+                    self.skip_block_comment();
                     None
                 }
+
+                _ if self.peek_operator().is_some() => {
+                    Some(self.tokenize_operator())
+                }
+
+                // Not ASCII, decode the whole character
+                _ => {
+                    let ch = self.peek().unwrap();
+
+                    if is_identifier_start(ch) {
+                        Some(self.tokenize_identifier())
+                    } else {
+                        if !ch.is_whitespace() {
+                            self.error(self.source_offset, format!("unknown character `{}`", ch));
+                        }
+
+                        self.advance();
+                        None
+                    }
+                }
             };
 
-            if let Some(token) = token_opt {
-                self.add_token(token);
+            if token_opt.is_some() {
+                return token_opt;
             }
         }
 
-        &self.tokens
+        None
     }
+}
 
-    fn tokenize_identifier(&mut self) -> Token {
-        let mut identifier_builder = String::new();
-        let mut first = true;
-        
-        while let Some(current_char) = self.peek() {
-            let is_first_char_valid = current_char.is_alphabetic() ||
-                current_char == '_';
-            
-            let is_tail_char_valid = is_first_char_valid ||
-                current_char.is_digit(10);
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            source,
+            source_offset: 0,
+            diagnostics: Vec::new(),
+        }
+    }
 
-            if first {
-                first = false;
+    // Errors in the tokens produced so far
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
 
-                if !is_first_char_valid {
-                    break;
-                }
-            } else {
-                if !is_tail_char_valid {
-                    break;
+    fn error(&mut self, offset: usize, message: String) {
+        let before = &self.source[..offset];
+        let line = before.bytes().filter(|&byte| byte == b'\n').count() + 1;
+        let column = before.chars().rev().take_while(|&ch| ch != '\n').count() + 1;
+
+        self.diagnostics.push(Diagnostic::new(line, column, message));
+    }
+
+    // `// text` is skipped, `/// text` becomes a doc comment for the next
+    // item. Four or more slashes make an ordinary comment again
+    fn tokenize_line_comment(&mut self) -> Option<Token<'a>> {
+        let doc = self.peek_at(2) == Some('/') && self.peek_at(3) != Some('/');
+
+        self.advance();
+        self.advance();
+
+        if doc {
+            self.advance();
+        }
+
+        let start = self.source_offset;
+
+        while self.take_if(|ch| ch != '\n').is_some() {}
+
+        doc.then_some(Token::DocComment(&self.source[start..self.source_offset]))
+    }
+
+    // Block comments nest, `/* a /* b */ c */` is one comment
+    fn skip_block_comment(&mut self) {
+        let start = self.source_offset;
+        let mut depth = 0;
+
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some('/'), Some('*')) => {
+                    self.advance();
+                    self.advance();
+                    depth += 1;
+                },
+
+                (Some('*'), Some('/')) => {
+                    self.advance();
+                    self.advance();
+                    depth -= 1;
+
+                    if depth == 0 {
+                        return;
+                    }
+                },
+
+                (Some(_), _) => self.advance(),
+
+                (None, _) => {
+                    self.error(start, "unterminated block comment".to_string());
+                    return;
+                },
+            }
+        }
+    }
+
+    // Identifiers and keywords, `r#name` is an identifier even if `name` is
+    // a keyword
+    fn tokenize_identifier(&mut self) -> Token<'a> {
+        let raw = self.peek() == Some('r') &&
+            self.peek_at(1) == Some('#') &&
+            self.peek_at(2).is_some_and(is_identifier_start);
+
+        if raw {
+            self.advance();
+            self.advance();
+        }
+
+        let start = self.source_offset;
+
+        // The first character was checked by the caller
+        self.advance();
+
+        while self.take_if(is_identifier_continue).is_some() {}
+
+        let identifier = &self.source[start..self.source_offset];
+
+        let keyword = KEYWORDS
+            .iter()
+            .find(|(keyword, _)| *keyword == identifier);
+
+        match keyword {
+            Some((_, token)) if !raw => token.clone(),
+            _ => Token::Identifier(identifier),
+        }
+    }
+
+    // `"..."`, or `b"..."` when `byte` is set. Byte strings only take ASCII
+    // characters but their `\x` escapes go up to `\xFF`. The value borrows
+    // from the source unless an escape makes it differ
+    fn tokenize_string_literal(&mut self, byte: bool) -> Token<'a> {
+        let start = self.source_offset;
+        let mut owned: Option<Vec<u8>> = None;
+
+        if byte {
+            self.advance();
+        }
+
+        self.advance();
+
+        let content_start = self.source_offset;
+
+        let content_end = loop {
+            let offset = self.source_offset;
+
+            let code = match self.peek() {
+                None => {
+                    self.error(start, "unterminated string literal".to_string());
+                    break offset;
+                },
+
+                Some('"') => {
+                    self.advance();
+                    break offset;
+                },
+
+                Some('\\') => {
+                    let source = self.source;
+                    owned.get_or_insert_with(|| source.as_bytes()[content_start..offset].to_vec());
+
+                    self.tokenize_escape(byte)
+                },
+
+                Some(current_char) => {
+                    self.advance();
+
+                    if byte && !current_char.is_ascii() {
+                        self.error(offset, "non-ASCII character in a byte string".to_string());
+                    }
+
+                    Some(current_char as u32)
+                },
+            };
+
+            if let (Some(code), Some(owned)) = (code, &mut owned) {
+                if byte {
+                    owned.push(code as u8);
+                } else {
+                    let mut utf8 = [0; 4];
+                    owned.extend_from_slice(char::from_u32(code).unwrap().encode_utf8(&mut utf8).as_bytes());
                 }
             }
+        };
+
+        let borrowed = &self.source[content_start..content_end];
+
+        match (byte, owned) {
+            (true, Some(bytes)) => Token::ByteStringLiteral(Cow::Owned(bytes)),
+            (true, None) => Token::ByteStringLiteral(Cow::Borrowed(borrowed.as_bytes())),
+            (false, Some(bytes)) => Token::StringLiteral(Cow::Owned(String::from_utf8(bytes).unwrap())),
+            (false, None) => Token::StringLiteral(Cow::Borrowed(borrowed)),
+        }
+    }
+
+    // Number of `#` in `r#"`, if a raw string starts here
+    fn raw_string_hashes(&self) -> Option<usize> {
+        if self.peek() != Some('r') {
+            return None;
+        }
+
+        let mut hashes = 0;
+
+        while self.peek_at(1 + hashes) == Some('#') {
+            hashes += 1;
+        }
+
+        (self.peek_at(1 + hashes) == Some('"')).then_some(hashes)
+    }
 
-            identifier_builder.push(current_char);
+    // `r"..."` or `r#"..."#` without escapes, the closing quote has to be
+    // followed by as many `#` as the opening one
+    fn tokenize_raw_string_literal(&mut self) -> Token<'a> {
+        let start = self.source_offset;
+        let hashes = self.raw_string_hashes().unwrap();
 
-            self.next();
+        for _ in 0..hashes + 2 {
+            self.advance();
         }
 
-        Token::Identifier(identifier_builder)
+        let content_start = self.source_offset;
+
+        let content_end = loop {
+            let offset = self.source_offset;
+
+            match self.peek_and_next() {
+                None => {
+                    self.error(start, "unterminated raw string literal".to_string());
+                    break offset;
+                },
+
+                Some('"') if (0..hashes).all(|index| self.peek_at(index) == Some('#')) => {
+                    for _ in 0..hashes {
+                        self.advance();
+                    }
+
+                    break offset;
+                },
+
+                Some(_) => {},
+            }
+        };
+
+        Token::StringLiteral(Cow::Borrowed(&self.source[content_start..content_end]))
     }
 
-    fn tokenize_string_literal(&mut self) -> Token {
-        let mut string_builder = String::new();
+    fn tokenize_char_literal(&mut self) -> Token<'a> {
+        let start = self.source_offset;
+
+        self.advance();
+
+        let code = match self.peek() {
+            Some('\\') => self.tokenize_escape(false),
 
-        self.take_if(|ch| ch == '"')
-            .expect("string literal begin quote");
+            Some('\'') => {
+                self.error(start, "empty character literal".to_string());
+                None
+            },
 
-        while let Some(current_char) = self.take_if(|ch| ch != '"') {
-            string_builder.push(current_char);
+            Some(current_char) if current_char != '\n' => {
+                self.advance();
+                Some(current_char as u32)
+            },
+
+            _ => None,
+        };
+
+        if self.take_if(|ch| ch == '\'').is_none() {
+            self.error(start, "unterminated character literal".to_string());
         }
 
-        self.take_if(|ch| ch == '"')
-            .expect("string literal end quote");
+        Token::CharLiteral(code.and_then(char::from_u32).unwrap_or('\0'))
+    }
+
+    // `\n`, `\t`, `\r`, `\\`, `\"`, `\'`, `\0`, `\xNN` and `\u{NNNN}`. Invalid
+    // escapes are reported and give `None`
+    fn tokenize_escape(&mut self, byte: bool) -> Option<u32> {
+        let start = self.source_offset;
+
+        self.advance();
+
+        let code = match self.peek_and_next()? {
+            'n' => '\n' as u32,
+            't' => '\t' as u32,
+            'r' => '\r' as u32,
+            '\\' => '\\' as u32,
+            '"' => '"' as u32,
+            '\'' => '\'' as u32,
+            '0' => 0,
 
-        Token::StringLiteral(string_builder)
+            'x' => {
+                let digits: String = (0..2).filter_map(|_| self.take_if(|ch| ch.is_ascii_hexdigit())).collect();
+
+                if digits.len() != 2 {
+                    self.error(start, "`\\x` must be followed by two hexadecimal digits".to_string());
+                    return None;
+                }
+
+                let code = u32::from_str_radix(&digits, 16).unwrap();
+
+                if code > 0x7F && !byte {
+                    self.error(start, format!("`\\x{}` is out of range, only byte strings take values above `\\x7F`", digits));
+                    return None;
+                }
+
+                code
+            },
+
+            'u' if !byte => {
+                let mut digits = String::new();
+                let opened = self.take_if(|ch| ch == '{').is_some();
+
+                while let Some(current_char) = self.take_if(|ch| ch.is_ascii_hexdigit()) {
+                    digits.push(current_char);
+                }
+
+                let closed = self.take_if(|ch| ch == '}').is_some();
+
+                let code = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .filter(|_| opened && closed && digits.len() <= 6)
+                    .filter(|&code| char::from_u32(code).is_some());
+
+                if code.is_none() {
+                    self.error(start, "invalid unicode escape, expected `\\u{...}` with a valid code point".to_string());
+                }
+
+                return code;
+            },
+
+            other => {
+                self.error(start, format!("unknown escape `\\{}`", other));
+                return None;
+            },
+        };
+
+        Some(code)
     }
 
-    fn tokenize_number_literal(&mut self) -> Token {
-        let mut digits_builder = String::new();
+    // Integers in decimal, hexadecimal (0x), binary (0b) or octal (0o) and
+    // decimal floats like 3.14e-2. Digits may be separated by `_` and the
+    // literal may end with a type suffix: 42u8, 7i64, 1f32
+    fn tokenize_number_literal(&mut self) -> Token<'a> {
+        let start = self.source_offset;
+
+        let radix = match (self.peek(), self.peek_at(1)) {
+            (Some('0'), Some('x')) => 16,
+            (Some('0'), Some('b')) => 2,
+            (Some('0'), Some('o')) => 8,
+            _ => 10,
+        };
+
+        if radix != 10 {
+            self.advance();
+            self.advance();
+        }
+
+        let mut digits = self.take_digits(radix);
+        let mut float = false;
+
+        if radix == 10 {
+            // `1.x` is left alone, the dot has to be followed by a digit
+            if self.peek() == Some('.') && self.peek_at(1).is_some_and(|ch| ch.is_ascii_digit()) {
+                self.advance();
+                digits.push('.');
+                digits += &self.take_digits(10);
+                float = true;
+            }
+
+            let sign = matches!(self.peek_at(1), Some('+' | '-'));
+            let exponent_digit = self.peek_at(if sign { 2 } else { 1 });
+
+            if matches!(self.peek(), Some('e' | 'E')) && exponent_digit.is_some_and(|ch| ch.is_ascii_digit()) {
+                self.advance();
+                digits.push('e');
+
+                if sign {
+                    digits.push(self.peek_and_next().unwrap());
+                }
+
+                digits += &self.take_digits(10);
+                float = true;
+            }
+        }
+
+        let suffix_start = self.source_offset;
+
+        while self.take_if(|ch| ch.is_alphanumeric() || ch == '_').is_some() {}
+
+        let suffix = &self.source[suffix_start..self.source_offset];
+        let suffix = (!suffix.is_empty()).then_some(suffix);
+
+        match suffix {
+            Some(suffix) if FLOAT_SUFFIXES.contains(&suffix) && radix == 10 => float = true,
+            Some(suffix) if INTEGER_SUFFIXES.contains(&suffix) && !float => {},
+            Some(suffix) => self.error(start, format!("invalid suffix `{}` for a number literal", suffix)),
+            None => {},
+        }
+
+        if digits.is_empty() {
+            self.error(start, "missing digits in a number literal".to_string());
+            return Token::IntegerLiteral(0, suffix);
+        }
+
+        if let Some(digit) = digits.chars().find(|ch| !ch.is_digit(radix) && !float) {
+            self.error(start, format!("invalid digit `{}` in a base {} literal", digit, radix));
+            return Token::IntegerLiteral(0, suffix);
+        }
 
-        while let Some(current_char) = self.take_if(|ch| ch.is_digit(10)) {
-            digits_builder.push(current_char);
+        if float {
+            return Token::FloatLiteral(digits.parse().unwrap(), suffix);
         }
 
-        let parsed_number = digits_builder
-            .parse()
-            .unwrap();
+        match u64::from_str_radix(&digits, radix) {
+            Ok(value) => Token::IntegerLiteral(value, suffix),
 
-        Token::NumberLiteral(parsed_number)
+            Err(_) => {
+                self.error(start, "integer literal is too large".to_string());
+                Token::IntegerLiteral(0, suffix)
+            },
+        }
     }
 
-    fn tokenize_single_character(&mut self) -> Token {
-        match self.peek_and_next().unwrap() {
-            '+' => Token::Plus,
-            '-' => Token::Minus,
-            '*' => Token::Star,
-            '/' => Token::Slash,
-            '=' => Token::Equal,
-            '(' => Token::LParen,
-            ')' => Token::RParen,
-            '{' => Token::LBrace,
-            '}' => Token::RBrace,
-            ',' => Token::Comma,
-            ':' => Token::Colon,
-            ';' => Token::Semicolon,
+    // Digits of a number without the `_` separators. Any decimal digit is
+    // taken so that `0b102` is reported instead of becoming two numbers
+    fn take_digits(&mut self, radix: u32) -> String {
+        let mut digits = String::new();
 
-            //
-            _ => unreachable!(),
+        while let Some(current_char) = self.take_if(|ch| ch.is_ascii_digit() || ch.is_digit(radix) || ch == '_') {
+            if current_char != '_' {
+                digits.push(current_char);
+            }
         }
+
+        digits
     }
 
-    fn next(&mut self) {
-        self.source_offset += 1;
+    // The longest operator at the current position
+    fn peek_operator(&self) -> Option<&'static (&'static str, Token<'static>)> {
+        let rest = &self.source.as_bytes()[self.source_offset..];
+
+        OPERATORS.iter().find(|(text, _)| rest.starts_with(text.as_bytes()))
+    }
+
+    // Operators are ASCII, so their length in bytes is their length
+    fn tokenize_operator(&mut self) -> Token<'a> {
+        let (text, token) = self.peek_operator().unwrap();
+
+        self.source_offset += text.len();
+
+        token.clone()
     }
 
+    fn advance(&mut self) {
+        if let Some(current_char) = self.peek() {
+            self.source_offset += current_char.len_utf8();
+        }
+    }
+
+    // ASCII is decoded without looking at the following bytes
     fn peek(&self) -> Option<char> {
-        if self.source_offset < self.source_chars.len() {
-            Some(self.source_chars[self.source_offset])
-        } else {
-            None
+        match *self.source.as_bytes().get(self.source_offset)? {
+            byte if byte.is_ascii() => Some(byte as char),
+            _ => self.source[self.source_offset..].chars().next(),
         }
     }
 
+    // `distance` counts characters, not bytes
+    fn peek_at(&self, distance: usize) -> Option<char> {
+        self.source[self.source_offset..].chars().nth(distance)
+    }
+
     fn peek_and_next(&mut self) -> Option<char> {
         let result = self.peek();
-        self.next();
+        self.advance();
         result
     }
 
@@ -165,7 +628,7 @@ impl Lexer {
 
         if let Some(current_char) = current {
             if f(current_char) {
-                self.next();
+                self.advance();
                 return Some(current_char);
             }
         }
@@ -173,3 +636,167 @@ impl Lexer {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::*;
+
+    fn tokenize(source: &str) -> (Vec<Token<'_>>, Vec<Diagnostic>) {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.by_ref().collect();
+        (tokens, lexer.diagnostics().to_vec())
+    }
+
+    #[test]
+    fn test_comments() {
+        let (tokens, diagnostics) = tokenize("/// Adds\n//// not a doc\na // b\n/* c /* d */ e */ / f");
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(vec![
+            Token::DocComment(" Adds"),
+            Token::Identifier("a"),
+            Token::Slash,
+            Token::Identifier("f"),
+        ], tokens);
+
+        let (_, diagnostics) = tokenize("a\n  /* b /* c */");
+        assert_eq!(vec![Diagnostic::new(2, 3, "unterminated block comment".to_string())], diagnostics);
+    }
+
+    #[test]
+    fn test_operators() {
+        let (tokens, _) = tokenize("a <<= b >> c != !d && e[0].f -> g %= h::i => j");
+
+        assert_eq!(vec![
+            Token::Identifier("a"),
+            Token::ShiftLeftEqual,
+            Token::Identifier("b"),
+            Token::ShiftRight,
+            Token::Identifier("c"),
+            Token::NotEqual,
+            Token::Bang,
+            Token::Identifier("d"),
+            Token::AndAnd,
+            Token::Identifier("e"),
+            Token::LBracket,
+            Token::IntegerLiteral(0, None),
+            Token::RBracket,
+            Token::Dot,
+            Token::Identifier("f"),
+            Token::Arrow,
+            Token::Identifier("g"),
+            Token::PercentEqual,
+            Token::Identifier("h"),
+            Token::ColonColon,
+            Token::Identifier("i"),
+            Token::FatArrow,
+            Token::Identifier("j"),
+        ], tokens);
+    }
+
+    #[test]
+    fn test_keywords() {
+        let (tokens, _) = tokenize("pub fn r#fn() { let mut return_value = true; }");
+
+        assert_eq!(vec![
+            Token::Pub,
+            Token::Fn,
+            Token::Identifier("fn"),
+            Token::LParen,
+            Token::RParen,
+            Token::LBrace,
+            Token::Let,
+            Token::Mut,
+            Token::Identifier("return_value"),
+            Token::Equal,
+            Token::True,
+            Token::Semicolon,
+            Token::RBrace,
+        ], tokens);
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let (tokens, diagnostics) = tokenize(r##"
+            "a\tb\n\"\\\0\x41\u{e9}" r"C:\dir" r#"say "hi""# b"\xFF\n" 'x' '\''
+        "##);
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(vec![
+            Token::StringLiteral("a\tb\n\"\\\0A\u{e9}".into()),
+            Token::StringLiteral("C:\\dir".into()),
+            Token::StringLiteral("say \"hi\"".into()),
+            Token::ByteStringLiteral(vec![0xFF, b'\n'].into()),
+            Token::CharLiteral('x'),
+            Token::CharLiteral('\''),
+        ], tokens);
+
+        let (_, diagnostics) = tokenize(r#""\q" "\x80" "\u{110000}" b"é" '' "open"#);
+        let messages: Vec<&str> = diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect();
+
+        assert_eq!(vec![
+            "unknown escape `\\q`",
+            "`\\x80` is out of range, only byte strings take values above `\\x7F`",
+            "invalid unicode escape, expected `\\u{...}` with a valid code point",
+            "non-ASCII character in a byte string",
+            "empty character literal",
+            "unterminated string literal",
+        ], messages);
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let (tokens, diagnostics) = tokenize("0xFF_u8 0b1010 0o17 1_000_000 7i64 0x1f32 3.14e-2 1e3 2f32 1.x");
+
+        assert!(diagnostics.is_empty());
+        assert_eq!(vec![
+            Token::IntegerLiteral(255, Some("u8")),
+            Token::IntegerLiteral(10, None),
+            Token::IntegerLiteral(15, None),
+            Token::IntegerLiteral(1_000_000, None),
+            Token::IntegerLiteral(7, Some("i64")),
+            Token::IntegerLiteral(0x1f32, None),
+            Token::FloatLiteral(3.14e-2, None),
+            Token::FloatLiteral(1e3, None),
+            Token::FloatLiteral(2.0, Some("f32")),
+            Token::IntegerLiteral(1, None),
+        ], tokens[..10]);
+
+        let (_, diagnostics) = tokenize("18446744073709551616 0b102 0x 5u7 1.5i32");
+        let messages: Vec<&str> = diagnostics.iter().map(|diagnostic| diagnostic.message.as_str()).collect();
+
+        assert_eq!(vec![
+            "integer literal is too large",
+            "invalid digit `2` in a base 2 literal",
+            "missing digits in a number literal",
+            "invalid suffix `u7` for a number literal",
+            "invalid suffix `i32` for a number literal",
+        ], messages);
+    }
+
+    #[test]
+    fn test_borrowed_tokens() {
+        let mut lexer = Lexer::new("café \"plain\" \"line\\n\" r#имя");
+
+        assert_eq!(Some(Token::Identifier("café")), lexer.next());
+        assert!(matches!(lexer.next(), Some(Token::StringLiteral(Cow::Borrowed("plain")))));
+        assert!(matches!(lexer.next(), Some(Token::StringLiteral(Cow::Owned(text))) if text == "line\n"));
+        assert_eq!(Some(Token::Identifier("имя")), lexer.next());
+        assert_eq!(None, lexer.next());
+
+        // Columns count characters, not bytes
+        let (_, diagnostics) = tokenize("é /* ");
+        assert_eq!(vec![Diagnostic::new(1, 3, "unterminated block comment".to_string())], diagnostics);
+    }
+
+    #[test]
+    fn test_unknown_character() {
+        let (tokens, diagnostics) = tokenize("a @ b\n  ¤\u{3000}c");
+
+        assert_eq!(vec![Token::Identifier("a"), Token::Identifier("b"), Token::Identifier("c")], tokens);
+        assert_eq!(vec![
+            Diagnostic::new(1, 3, "unknown character `@`".to_string()),
+            Diagnostic::new(2, 3, "unknown character `¤`".to_string()),
+        ], diagnostics);
+    }
+}
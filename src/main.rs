@@ -1,39 +1,299 @@
+mod diagnostic;
 mod token;
 mod lexer;
 mod parser;
 mod exe_writer;
+mod import_builder;
+mod export_builder;
+mod object_writer;
+mod so_writer;
 mod codegen;
+mod ir;
+mod opt;
+mod runtime;
 mod x86;
 
-use std::{fs::File, io::Read};
+use std::{fs::File, io::Read, path::{Path, PathBuf}, process};
 
+use diagnostic::Diagnostic;
 use exe_writer::ExeWriter;
+use ir::lowering::LoweringOptions;
 use lexer::Lexer;
+use object_writer::{ObjectFormat, ObjectWriter};
+use opt::{OptLevel, PassManager};
 use parser::Parser;
+use so_writer::SoWriter;
+use x86::selection::EntryPoint;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Emit {
+    Tokens,
+    Ast,
+    Ir,
+    Object,
+    Exe,
+    Dll,
+    SharedObject,
+}
 
 fn main() {
-    let mut writer = ExeWriter::new();
+    let mut emit = Emit::Exe;
+    let mut opt_level = OptLevel::O0;
+    let mut dynamic_base = false;
+    let mut lowering_options = LoweringOptions::default();
+    let mut object_format = ObjectFormat::Coff;
+    let mut link_runtime = true;
+    let mut path = String::from("app.dl");
+
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--emit=tokens" => emit = Emit::Tokens,
+            "--emit=ast" => emit = Emit::Ast,
+            "--emit=ir" => emit = Emit::Ir,
+            "--emit=obj" => emit = Emit::Object,
+            "--emit=exe" => emit = Emit::Exe,
+            "--emit=dll" => emit = Emit::Dll,
+            "--emit=so" => emit = Emit::SharedObject,
+            "--object-format=coff" => object_format = ObjectFormat::Coff,
+            "--object-format=elf" => object_format = ObjectFormat::Elf,
+            "-O0" => opt_level = OptLevel::O0,
+            "-O1" => opt_level = OptLevel::O1,
+            "-O2" => opt_level = OptLevel::O2,
+            "--dynamic-base" => dynamic_base = true,
+            "--no-bounds-checks" => lowering_options.bounds_checks = false,
+            "--no-runtime" => link_runtime = false,
+
+            arg if arg.starts_with('-') => {
+                eprintln!("unknown option: `{}`", arg);
+                process::exit(1);
+            }
+
+            arg => path = arg.to_string(),
+        }
+    }
+
+    // A directory is compiled from its `main.dl`
+    if Path::new(&path).is_dir() {
+        path = Path::new(&path).join("main.dl").to_string_lossy().into_owned();
+    }
+
+    if emit == Emit::Tokens {
+        let code = read_file(Path::new(&path));
+        let mut lexer = Lexer::new(&code);
+
+        for token in lexer.by_ref() {
+            println!("token: {:?}", token);
+        }
+
+        report_diagnostics(&path, lexer.diagnostics());
+        return;
+    }
+
+    let mut ast = parse_file(Path::new(&path));
+    load_modules(Path::new(&path), &mut ast.items, &mut vec![PathBuf::from(&path)]);
+
+    if emit == Emit::Ast {
+        println!("{:#?}", ast);
+        return;
+    }
+
+    // The runtime calls kernel32 on Windows and the C library on Linux
+    let runtime_format = match emit {
+        Emit::SharedObject => ObjectFormat::Elf,
+        Emit::Object => object_format,
+        _ => ObjectFormat::Coff,
+    };
+
+    if link_runtime {
+        ast.items.push(runtime::module(runtime_format));
+    }
 
-    writer.write();
+    let mut module = match ir::lowering::lower_module(&ast, &lowering_options) {
+        Ok(module) => module,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            process::exit(1);
+        }
+    };
+
+    // The entry point of an executable calls `main`
+    if emit == Emit::Exe && module.function("main").is_none() {
+        eprintln!("{}: no `main` function, an executable must have one", path);
+        process::exit(1);
+    }
+
+    verify(&path, &module);
+
+    if opt_level > OptLevel::O0 {
+        PassManager::for_level(opt_level).run(&mut module);
+        verify(&path, &module);
+    }
+
+    if emit == Emit::Ir {
+        print!("{}", module);
+        return;
+    }
+
+    if emit == Emit::Object {
+        let codegen = x86::selection::compile_module(&module, EntryPoint::None);
+        let mut writer = ObjectWriter::new(object_format);
+
+        for external in &module.externals {
+            if external.convention == ir::CallingConvention::Stdcall {
+                writer.add_stdcall(&external.name, external.params.len() * 4);
+            }
+        }
+
+        for (index, data) in module.data.iter().enumerate() {
+            let symbol = x86::selection::data_symbol(ir::DataId(index as u32));
+            writer.add_rdata(&symbol, &data.bytes);
+        }
+
+        writer.write(&codegen, &format!("compiled.{}", object_format.extension()));
+        return;
+    }
+
+    if emit == Emit::SharedObject {
+        let codegen = x86::selection::compile_module(&module, EntryPoint::None);
+        let mut writer = SoWriter::new();
+
+        for (index, data) in module.data.iter().enumerate() {
+            let symbol = x86::selection::data_symbol(ir::DataId(index as u32));
+            writer.add_rdata(&symbol, &data.bytes);
+        }
+
+        for function in module.functions.iter().filter(|function| function.exported) {
+            writer.add_export(&function.name);
+        }
+
+        for external in &module.externals {
+            writer.add_needed(external.library.as_deref().unwrap_or(so_writer::DEFAULT_LIBRARY));
+        }
+
+        writer.write(&codegen, "compiled.so");
+        return;
+    }
 
+    let (entry_point, entry_symbol, output) = match emit {
+        Emit::Dll => (EntryPoint::Dll, x86::selection::DLL_ENTRY_SYMBOL, "compiled.dll"),
+        _ => (EntryPoint::Executable, x86::selection::ENTRY_SYMBOL, "compiled.exe"),
+    };
+
+    let codegen = x86::selection::compile_module(&module, entry_point);
+
+    let mut writer = ExeWriter::new(entry_symbol);
+    writer.set_dynamic_base(dynamic_base);
+    writer.set_dll(emit == Emit::Dll);
+
+    for (index, data) in module.data.iter().enumerate() {
+        let symbol = x86::selection::data_symbol(ir::DataId(index as u32));
+        writer.add_rdata(&symbol, &data.bytes);
+    }
+
+    for (dll, function) in x86::selection::IMPORTS {
+        writer.add_import(dll, function);
+    }
+
+    for external in &module.externals {
+        let dll = external.library.as_deref().unwrap_or(x86::selection::DEFAULT_LIBRARY);
+        writer.add_import(dll, &external.name);
+    }
+
+    if emit == Emit::Dll {
+        for function in module.functions.iter().filter(|function| function.exported) {
+            writer.add_export(&function.name);
+        }
+    }
+
+    writer.write(&codegen, output);
+}
+
+fn read_file(path: &Path) -> String {
     let mut code = String::new();
-    
-    let mut file = File::open("test.dl").unwrap();
-    file.read_to_string(&mut code).unwrap();
 
-    let mut lexer = Lexer::new(code.into());
+    let result = File::open(path).and_then(|mut file| file.read_to_string(&mut code));
+
+    if let Err(error) = result {
+        eprintln!("{}: {}", path.display(), error);
+        process::exit(1);
+    }
+
+    code
+}
+
+fn parse_file(path: &Path) -> parser::Module {
+    let code = read_file(path);
+    let path = path.to_string_lossy();
 
-    let tokens = lexer.tokenize();
+    let mut parser = Parser::new(Lexer::new(&code));
+    let result = parser.parse_module();
 
-    for token in tokens.iter() {
-        println!("token: {:?}", token);
+    // A syntax error is often caused by a lexical one
+    report_diagnostics(&path, parser.diagnostics());
+
+    match result {
+        Ok(ast) => ast,
+        Err(()) => {
+            eprintln!("{}: {}", path, parser.error().unwrap_or("syntax error"));
+            process::exit(1);
+        }
     }
+}
+
+// `mod foo;` in a file loads `foo.dl` next to it, also from the modules
+// declared inline in the file. `loading` are the files the modules are
+// loaded from, to report a module which includes itself
+fn load_modules(path: &Path, items: &mut [parser::Item], loading: &mut Vec<PathBuf>) {
+    for item in items {
+        let parser::Item::Module(declaration) = item else {
+            continue;
+        };
+
+        if let Some(items) = &mut declaration.items {
+            load_modules(path, items, loading);
+            continue;
+        }
+
+        let module_path = path.with_file_name(format!("{}.dl", declaration.name));
 
-    println!();
+        if !module_path.is_file() {
+            eprintln!("{}: cannot find module `{}`, expected `{}`", path.display(), declaration.name, module_path.display());
+            process::exit(1);
+        }
 
-    let mut parser = Parser::new(tokens);
+        if loading.contains(&module_path) {
+            eprintln!("{}: module `{}` includes itself", path.display(), declaration.name);
+            process::exit(1);
+        }
 
-    let ast = parser.parse();
+        let mut ast = parse_file(&module_path);
+
+        loading.push(module_path.clone());
+        load_modules(&module_path, &mut ast.items, loading);
+        loading.pop();
+
+        declaration.items = Some(ast.items);
+    }
+}
 
-    println!("{:?}", ast.unwrap());
+fn report_diagnostics(path: &str, diagnostics: &[Diagnostic]) {
+    if diagnostics.is_empty() {
+        return;
+    }
+
+    for diagnostic in diagnostics {
+        eprintln!("{}:{}", path, diagnostic);
+    }
+
+    process::exit(1);
+}
+
+fn verify(path: &str, module: &ir::Module) {
+    if let Err(errors) = ir::verifier::verify_module(module) {
+        for error in errors {
+            eprintln!("{}: invalid IR: {}", path, error);
+        }
+
+        process::exit(1);
+    }
 }
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use object::write::{Object, Relocation, StandardSection, Symbol, SymbolId, SymbolSection};
+use object::{
+    Architecture, BinaryFormat, Endianness, RelocationEncoding, RelocationKind, SymbolFlags, SymbolKind,
+    SymbolScope,
+};
+
+use crate::codegen::{self, Codegen};
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ObjectFormat {
+    Coff,
+    Elf,
+}
+
+impl ObjectFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ObjectFormat::Coff => "obj",
+            ObjectFormat::Elf => "o",
+        }
+    }
+}
+
+// Writes a relocatable object instead of a linked image. Every function is a
+// global symbol, calls to functions defined elsewhere are left to the linker
+pub struct ObjectWriter {
+    format: ObjectFormat,
+    rdata: Vec<(String, Vec<u8>)>,
+    stdcall: HashMap<String, usize>,
+}
+
+impl ObjectWriter {
+    pub fn new(format: ObjectFormat) -> Self {
+        Self {
+            format,
+            rdata: Vec::new(),
+            stdcall: HashMap::new(),
+        }
+    }
+
+    // COFF names stdcall functions `_name@N`, N being the size of the
+    // arguments in bytes, so calls must refer to the decorated name
+    pub fn add_stdcall(&mut self, symbol: &str, argument_size: usize) {
+        self.stdcall.insert(symbol.to_string(), argument_size);
+    }
+
+    pub fn add_rdata(&mut self, symbol: &str, bytes: &[u8]) {
+        self.rdata.push((symbol.to_string(), bytes.to_vec()));
+    }
+
+    pub fn build(&self, codegen: &Codegen) -> Vec<u8> {
+        let binary_format = match self.format {
+            ObjectFormat::Coff => BinaryFormat::Coff,
+            ObjectFormat::Elf => BinaryFormat::Elf,
+        };
+
+        let mut object = Object::new(binary_format, Architecture::I386, Endianness::Little);
+
+        let text = object.section_id(StandardSection::Text);
+        let text_offset = object.append_section_data(text, codegen.get_bytes(), 16);
+
+        // Sorted so that the symbol table does not depend on hash map order
+        let mut functions: Vec<(&String, &usize)> = codegen.symbols().iter().collect();
+        functions.sort_by_key(|(_, offset)| **offset);
+
+        // `.L` symbols are local labels, like the targets of jump tables
+        for (name, offset) in functions {
+            let scope = if name.starts_with(".L") { SymbolScope::Compilation } else { SymbolScope::Linkage };
+
+            object.add_symbol(Symbol {
+                name: name.as_bytes().to_vec(),
+                value: text_offset + *offset as u64,
+                size: 0,
+                kind: SymbolKind::Text,
+                scope,
+                weak: false,
+                section: SymbolSection::Section(text),
+                flags: SymbolFlags::None,
+            });
+        }
+
+        if !self.rdata.is_empty() {
+            let rdata = object.section_id(StandardSection::ReadOnlyData);
+
+            for (name, bytes) in &self.rdata {
+                let offset = object.append_section_data(rdata, bytes, 4);
+
+                object.add_symbol(Symbol {
+                    name: name.as_bytes().to_vec(),
+                    value: offset,
+                    size: bytes.len() as u64,
+                    kind: SymbolKind::Data,
+                    scope: SymbolScope::Compilation,
+                    weak: false,
+                    section: SymbolSection::Section(rdata),
+                    flags: SymbolFlags::None,
+                });
+            }
+        }
+
+        for relocation in codegen.relocations() {
+            let symbol = self.symbol(&mut object, &relocation.symbol);
+
+            let (kind, addend) = match relocation.kind {
+                codegen::RelocationKind::Absolute32 => (RelocationKind::Absolute, 0),
+                codegen::RelocationKind::Relative32 => (RelocationKind::Relative, -4),
+            };
+
+            object.add_relocation(text, Relocation {
+                offset: text_offset + relocation.offset as u64,
+                size: 32,
+                kind,
+                encoding: RelocationEncoding::Generic,
+                symbol,
+                addend,
+            }).unwrap();
+        }
+
+        object.write().unwrap()
+    }
+
+    pub fn write(&self, codegen: &Codegen, path: &str) {
+        std::fs::write(path, self.build(codegen)).unwrap();
+    }
+
+    // Symbols which are not defined here become undefined externals
+    fn symbol(&self, object: &mut Object, name: &str) -> SymbolId {
+        let name = match self.stdcall.get(name) {
+            Some(argument_size) if self.format == ObjectFormat::Coff => format!("{}@{}", name, argument_size),
+            _ => name.to_string(),
+        };
+
+        if let Some(symbol) = object.symbol_id(name.as_bytes()) {
+            return symbol;
+        }
+
+        object.add_symbol(Symbol {
+            name: name.as_bytes().to_vec(),
+            value: 0,
+            size: 0,
+            kind: SymbolKind::Text,
+            scope: SymbolScope::Dynamic,
+            weak: false,
+            section: SymbolSection::Undefined,
+            flags: SymbolFlags::None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use object::{Object, ObjectSymbol};
+
+    use crate::object_writer::*;
+
+    #[test]
+    fn test_external_call() {
+        let mut codegen = Codegen::new();
+
+        codegen.define_symbol("main");
+        codegen.call_symbol("puts");
+        codegen.emit(crate::x86::instruction_table::Kind::Ret, None, None);
+        codegen.finish();
+
+        for format in [ObjectFormat::Coff, ObjectFormat::Elf] {
+            let bytes = ObjectWriter::new(format).build(&codegen);
+            let file = object::File::parse(&*bytes).unwrap();
+
+            let main = file.symbols().find(|symbol| symbol.name().unwrap().ends_with("main")).unwrap();
+            assert!(main.is_global() && main.is_definition());
+
+            let puts = file.symbols().find(|symbol| symbol.name().unwrap().ends_with("puts")).unwrap();
+            assert!(puts.is_undefined());
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use crate::ir::{BlockId, Function, InstKind, Value};
+
+use super::Pass;
+
+// Replaces uses of `copy` results, and of phis whose incoming values are all
+// the same, with the original value. The dead copies are left for DCE.
+pub struct CopyPropagation;
+
+impl Pass for CopyPropagation {
+    fn run(&self, function: &mut Function) -> bool {
+        let mut aliases: HashMap<Value, Value> = HashMap::new();
+
+        loop {
+            let mut found = false;
+
+            for block in &function.blocks {
+                for inst in &block.insts {
+                    let result = match inst.result {
+                        Some(result) if !aliases.contains_key(&result) => result,
+                        _ => continue,
+                    };
+
+                    let source = match &inst.kind {
+                        InstKind::Copy(source) => Some(resolve(&aliases, *source)),
+                        InstKind::Phi(incoming) => unique_incoming(&aliases, result, incoming),
+                        _ => None,
+                    };
+
+                    if let Some(source) = source {
+                        if source != result {
+                            aliases.insert(result, source);
+                            found = true;
+                        }
+                    }
+                }
+            }
+
+            if !found {
+                break;
+            }
+        }
+
+        if aliases.is_empty() {
+            return false;
+        }
+
+        let mut changed = false;
+
+        for block in &mut function.blocks {
+            let operands = block.insts
+                .iter_mut()
+                .flat_map(|inst| inst.operands_mut())
+                .chain(block.terminator.as_mut().unwrap().operands_mut());
+
+            for operand in operands {
+                let resolved = resolve(&aliases, *operand);
+
+                if resolved != *operand {
+                    *operand = resolved;
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+fn resolve(aliases: &HashMap<Value, Value>, mut value: Value) -> Value {
+    while let Some(&source) = aliases.get(&value) {
+        value = source;
+    }
+
+    value
+}
+
+// A phi like `%3 = phi [bb1, %1], [bb2, %1], [bb3, %3]` is just `%1`
+fn unique_incoming(aliases: &HashMap<Value, Value>, result: Value, incoming: &[(BlockId, Value)]) -> Option<Value> {
+    let mut unique = None;
+
+    for &(_, value) in incoming {
+        let value = resolve(aliases, value);
+
+        if value == result {
+            continue;
+        }
+
+        match unique {
+            None => unique = Some(value),
+            Some(unique) if unique == value => {},
+            Some(_) => return None,
+        }
+    }
+
+    unique
+}
@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::cfg::ControlFlowGraph;
+use crate::ir::{BlockId, Function, InstKind, Terminator, Value};
+
+use super::Pass;
+
+// Removes unreachable blocks and instructions whose results are never used
+pub struct DeadCodeElimination;
+
+impl Pass for DeadCodeElimination {
+    fn run(&self, function: &mut Function) -> bool {
+        let removed_blocks = remove_unreachable_blocks(function);
+        let removed_insts = remove_dead_insts(function);
+
+        removed_blocks || removed_insts
+    }
+}
+
+fn remove_unreachable_blocks(function: &mut Function) -> bool {
+    let cfg = ControlFlowGraph::new(function);
+
+    let reachable: Vec<bool> = function.block_ids()
+        .map(|block| cfg.is_reachable(block))
+        .collect();
+
+    if reachable.iter().all(|reachable| *reachable) {
+        return false;
+    }
+
+    let mut remap = Vec::new();
+    let mut next = 0;
+
+    for &is_reachable in &reachable {
+        if is_reachable {
+            remap.push(Some(BlockId(next)));
+            next += 1;
+        } else {
+            remap.push(None);
+        }
+    }
+
+    let blocks = std::mem::take(&mut function.blocks);
+
+    for (block, is_reachable) in blocks.into_iter().zip(&reachable) {
+        if *is_reachable {
+            function.blocks.push(block);
+        }
+    }
+
+    let new_id = |block: BlockId| remap[block.0 as usize].unwrap();
+
+    for block in &mut function.blocks {
+        for inst in &mut block.insts {
+            if let InstKind::Phi(incoming) = &mut inst.kind {
+                incoming.retain(|(predecessor, _)| remap[predecessor.0 as usize].is_some());
+
+                for (predecessor, _) in incoming.iter_mut() {
+                    *predecessor = new_id(*predecessor);
+                }
+            }
+        }
+
+        match block.terminator.as_mut().unwrap() {
+            Terminator::Jump(target) => *target = new_id(*target),
+
+            Terminator::Branch(_, then_block, else_block) => {
+                *then_block = new_id(*then_block);
+                *else_block = new_id(*else_block);
+            },
+
+            Terminator::Switch(_, cases, default) => {
+                for case in cases.iter_mut() {
+                    *case = new_id(*case);
+                }
+
+                *default = new_id(*default);
+            },
+
+            Terminator::Return(_) | Terminator::Unreachable | Terminator::Trap(_) => {},
+        }
+    }
+
+    true
+}
+
+fn remove_dead_insts(function: &mut Function) -> bool {
+    let mut live: HashSet<Value> = HashSet::new();
+    let mut worklist: Vec<Value> = Vec::new();
+
+    for block in &function.blocks {
+        worklist.extend(block.terminator.as_ref().unwrap().operands());
+
+        for inst in &block.insts {
+            if inst.has_side_effects() {
+                worklist.extend(inst.operands());
+            }
+        }
+    }
+
+    let mut definitions = HashMap::new();
+
+    for block in &function.blocks {
+        for inst in &block.insts {
+            if let Some(result) = inst.result {
+                definitions.insert(result, inst);
+            }
+        }
+    }
+
+    while let Some(value) = worklist.pop() {
+        if !live.insert(value) {
+            continue;
+        }
+
+        if let Some(inst) = definitions.get(&value) {
+            worklist.extend(inst.operands());
+        }
+    }
+
+    let mut changed = false;
+
+    for block in &mut function.blocks {
+        let before = block.insts.len();
+
+        block.insts.retain(|inst| {
+            inst.has_side_effects() || inst.result.is_some_and(|result| live.contains(&result))
+        });
+
+        changed |= block.insts.len() != before;
+    }
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::builder::FunctionBuilder;
+    use crate::ir::{BinaryOp, Type};
+    use crate::opt::dead_code_elimination::*;
+
+    #[test]
+    fn test_unused_values_are_removed() {
+        let mut function = Function::new("main", Type::Void);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        let dead = builder.create_block();
+
+        builder.switch_to_block(entry);
+        let x = builder.iconst(Type::I32, 1);
+        let y = builder.iconst(Type::I32, 2);
+        builder.binary(BinaryOp::Add, x, y);
+        builder.ret(None);
+
+        builder.switch_to_block(dead);
+        builder.ret(None);
+
+        assert!(DeadCodeElimination.run(&mut function));
+        assert_eq!(1, function.blocks.len());
+        assert!(function.blocks[0].insts.is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+pub mod copy_propagation;
+pub mod dead_code_elimination;
+pub mod sccp;
+pub mod simplify;
+pub mod simplify_cfg;
+
+use crate::ir::{Function, Module};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+}
+
+pub trait Pass {
+    // Returns true if the function was changed
+    fn run(&self, function: &mut Function) -> bool;
+}
+
+// Passes are repeated until none of them changes anything
+const MAX_ITERATIONS: usize = 16;
+
+pub struct PassManager {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn for_level(level: OptLevel) -> Self {
+        let mut manager = Self::new();
+
+        if level >= OptLevel::O2 {
+            manager.add(Box::new(sccp::ConditionalConstantPropagation));
+        }
+
+        if level >= OptLevel::O1 {
+            manager.add(Box::new(simplify::AlgebraicSimplification));
+            manager.add(Box::new(copy_propagation::CopyPropagation));
+            manager.add(Box::new(dead_code_elimination::DeadCodeElimination));
+            manager.add(Box::new(simplify_cfg::CfgSimplification));
+        }
+
+        manager
+    }
+
+    pub fn add(&mut self, pass: Box<dyn Pass>) {
+        self.passes.push(pass);
+    }
+
+    pub fn run(&self, module: &mut Module) {
+        for function in &mut module.functions {
+            self.run_function(function);
+        }
+    }
+
+    fn run_function(&self, function: &mut Function) {
+        for _ in 0..MAX_ITERATIONS {
+            let mut changed = false;
+
+            for pass in &self.passes {
+                changed |= pass.run(function);
+            }
+
+            if !changed {
+                break;
+            }
+        }
+    }
+}
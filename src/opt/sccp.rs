@@ -0,0 +1,385 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{BlockId, Function, Inst, InstKind, Terminator, Value};
+
+use super::Pass;
+
+// Sparse conditional constant propagation (Wegman-Zadeck). Values are only
+// evaluated along edges that can actually be taken, so constants flowing
+// through phis and branches on constant conditions are folded as well
+pub struct ConditionalConstantPropagation;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Lattice {
+    Undefined,
+    Constant(i64),
+    Overdefined,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Undefined, other) | (other, Lattice::Undefined) => other,
+            (Lattice::Constant(a), Lattice::Constant(b)) if a == b => self,
+            _ => Lattice::Overdefined,
+        }
+    }
+}
+
+// The users of a value, evaluated again when the value changes
+#[derive(Debug, Clone, Copy)]
+enum Use {
+    Inst(BlockId, usize),
+    Terminator(BlockId),
+}
+
+struct Analysis {
+    values: HashMap<Value, Lattice>,
+    executable_blocks: HashSet<BlockId>,
+    executable_edges: HashSet<(BlockId, BlockId)>,
+    uses: HashMap<Value, Vec<Use>>,
+    cfg_worklist: Vec<(BlockId, BlockId)>,
+    ssa_worklist: Vec<Value>,
+}
+
+impl Analysis {
+    fn new(function: &Function) -> Self {
+        let mut analysis = Self {
+            values: HashMap::new(),
+            executable_blocks: HashSet::new(),
+            executable_edges: HashSet::new(),
+            uses: HashMap::new(),
+            cfg_worklist: Vec::new(),
+            ssa_worklist: Vec::new(),
+        };
+
+        for param in &function.params {
+            analysis.values.insert(*param, Lattice::Overdefined);
+        }
+
+        for block_id in function.block_ids() {
+            let block = function.block(block_id);
+
+            for (index, inst) in block.insts.iter().enumerate() {
+                for operand in inst.operands() {
+                    analysis.uses.entry(operand).or_default().push(Use::Inst(block_id, index));
+                }
+            }
+
+            for operand in block.terminator.as_ref().unwrap().operands() {
+                analysis.uses.entry(operand).or_default().push(Use::Terminator(block_id));
+            }
+        }
+
+        analysis
+    }
+
+    fn get(&self, value: Value) -> Lattice {
+        self.values.get(&value).copied().unwrap_or(Lattice::Undefined)
+    }
+
+    fn update(&mut self, value: Value, lattice: Lattice) -> bool {
+        let old = self.get(value);
+        let new = old.meet(lattice);
+
+        self.values.insert(value, new);
+        new != old
+    }
+
+    // A block is evaluated completely when its first edge becomes
+    // executable, after that only its phis see the new edges. Instructions
+    // are evaluated again only when one of their operands changes
+    fn run(&mut self, function: &Function) {
+        self.executable_blocks.insert(function.entry());
+        self.visit_block(function, function.entry());
+
+        loop {
+            if let Some((from, to)) = self.cfg_worklist.pop() {
+                if !self.executable_edges.insert((from, to)) {
+                    continue;
+                }
+
+                if self.executable_blocks.insert(to) {
+                    self.visit_block(function, to);
+                    continue;
+                }
+
+                for (index, inst) in function.block(to).insts.iter().enumerate() {
+                    if matches!(inst.kind, InstKind::Phi(_)) {
+                        self.visit_inst(function, to, index);
+                    }
+                }
+            } else if let Some(value) = self.ssa_worklist.pop() {
+                for user in self.uses.get(&value).cloned().unwrap_or_default() {
+                    match user {
+                        Use::Inst(block, index) if self.executable_blocks.contains(&block) => {
+                            self.visit_inst(function, block, index);
+                        },
+
+                        Use::Terminator(block) if self.executable_blocks.contains(&block) => {
+                            self.visit_terminator(function, block);
+                        },
+
+                        _ => {},
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn visit_block(&mut self, function: &Function, block: BlockId) {
+        for index in 0..function.block(block).insts.len() {
+            self.visit_inst(function, block, index);
+        }
+
+        self.visit_terminator(function, block);
+    }
+
+    fn visit_inst(&mut self, function: &Function, block: BlockId, index: usize) {
+        let inst = &function.block(block).insts[index];
+
+        if let Some(result) = inst.result {
+            let lattice = self.evaluate(block, inst);
+
+            if self.update(result, lattice) {
+                self.ssa_worklist.push(result);
+            }
+        }
+    }
+
+    fn visit_terminator(&mut self, function: &Function, block: BlockId) {
+        let terminator = function.block(block).terminator.as_ref().unwrap();
+
+        let targets = match terminator {
+            Terminator::Branch(condition, then_block, else_block) => match self.get(*condition) {
+                Lattice::Undefined => Vec::new(),
+                Lattice::Constant(0) => vec![*else_block],
+                Lattice::Constant(_) => vec![*then_block],
+                Lattice::Overdefined => terminator.successors(),
+            },
+
+            Terminator::Switch(value, cases, default) => match self.get(*value) {
+                Lattice::Undefined => Vec::new(),
+                Lattice::Constant(value) => vec![switch_target(value, cases, *default)],
+                Lattice::Overdefined => terminator.successors(),
+            },
+
+            _ => terminator.successors(),
+        };
+
+        for target in targets {
+            self.cfg_worklist.push((block, target));
+        }
+    }
+
+    fn evaluate(&self, block: BlockId, inst: &Inst) -> Lattice {
+        match &inst.kind {
+            InstKind::Const(value) => Lattice::Constant(*value),
+            InstKind::Copy(value) | InstKind::Bitcast(value) => self.get(*value),
+
+            InstKind::Binary(op, lhs, rhs) => match (self.get(*lhs), self.get(*rhs)) {
+                (Lattice::Constant(lhs), Lattice::Constant(rhs)) => match op.fold(inst.ty, lhs, rhs) {
+                    Some(value) => Lattice::Constant(value),
+                    None => Lattice::Overdefined,
+                },
+                (Lattice::Overdefined, _) | (_, Lattice::Overdefined) => Lattice::Overdefined,
+                _ => Lattice::Undefined,
+            },
+
+            InstKind::Unary(op, value) => match self.get(*value) {
+                Lattice::Constant(value) => Lattice::Constant(op.fold(inst.ty, value)),
+                lattice => lattice,
+            },
+
+            InstKind::Phi(incoming) => incoming
+                .iter()
+                .filter(|(predecessor, _)| self.executable_edges.contains(&(*predecessor, block)))
+                .fold(Lattice::Undefined, |lattice, (_, value)| lattice.meet(self.get(*value))),
+
+            // Memory is not tracked
+            InstKind::DataAddress(_) | InstKind::StackAddress(_) | InstKind::Load(..) | InstKind::Store(..) |
+            InstKind::ElementAddress(..) | InstKind::Call(..) => Lattice::Overdefined,
+        }
+    }
+}
+
+impl Pass for ConditionalConstantPropagation {
+    fn run(&self, function: &mut Function) -> bool {
+        let mut analysis = Analysis::new(function);
+        analysis.run(function);
+
+        let mut changed = false;
+
+        for block_id in function.block_ids() {
+            if !analysis.executable_blocks.contains(&block_id) {
+                continue;
+            }
+
+            let block = function.block_mut(block_id);
+            let insts = std::mem::take(&mut block.insts);
+
+            // Folded phis become constants placed after the remaining phis
+            let mut phis = Vec::new();
+            let mut folded_phis = Vec::new();
+            let mut rest = Vec::new();
+
+            for mut inst in insts {
+                let constant = match (inst.result, &inst.kind) {
+                    (_, InstKind::Const(_)) => None,
+                    (_, InstKind::Call(..)) => None,
+
+                    (Some(result), _) => match analysis.get(result) {
+                        Lattice::Constant(value) => Some(value),
+                        _ => None,
+                    },
+
+                    (None, _) => None,
+                };
+
+                let is_phi = matches!(inst.kind, InstKind::Phi(_));
+
+                if let Some(value) = constant {
+                    inst.kind = InstKind::Const(value);
+                    changed = true;
+                }
+
+                match (is_phi, constant) {
+                    (true, None) => phis.push(inst),
+                    (true, Some(_)) => folded_phis.push(inst),
+                    (false, _) => rest.push(inst),
+                }
+            }
+
+            block.insts = phis;
+            block.insts.append(&mut folded_phis);
+            block.insts.append(&mut rest);
+
+            // Branches on constant conditions and switches on constants
+            // become jumps
+            let terminator = block.terminator.as_ref().unwrap();
+
+            let taken = match terminator {
+                Terminator::Branch(condition, then_block, else_block) if then_block != else_block => {
+                    match analysis.get(*condition) {
+                        Lattice::Constant(0) => Some(*else_block),
+                        Lattice::Constant(_) => Some(*then_block),
+                        _ => None,
+                    }
+                },
+
+                Terminator::Switch(value, cases, default) => match analysis.get(*value) {
+                    Lattice::Constant(value) => Some(switch_target(value, cases, *default)),
+                    _ => None,
+                },
+
+                _ => None,
+            };
+
+            if let Some(taken) = taken {
+                let successors = terminator.successors();
+                block.terminator = Some(Terminator::Jump(taken));
+
+                for untaken in successors.into_iter().filter(|&successor| successor != taken) {
+                    for inst in &mut function.block_mut(untaken).insts {
+                        if let InstKind::Phi(incoming) = &mut inst.kind {
+                            incoming.retain(|(predecessor, _)| *predecessor != block_id);
+                        }
+                    }
+                }
+
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+fn switch_target(value: i64, cases: &[BlockId], default: BlockId) -> BlockId {
+    usize::try_from(value)
+        .ok()
+        .and_then(|index| cases.get(index))
+        .copied()
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::builder::FunctionBuilder;
+    use crate::ir::{BinaryOp, Type};
+    use crate::opt::sccp::*;
+
+    #[test]
+    fn test_constant_branch_and_phi() {
+        let mut function = Function::new("f", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        let then_block = builder.create_block();
+        let else_block = builder.create_block();
+        let merge = builder.create_block();
+
+        builder.switch_to_block(entry);
+        let one = builder.iconst(Type::I32, 1);
+        builder.branch(one, then_block, else_block);
+
+        builder.switch_to_block(then_block);
+        let two = builder.iconst(Type::I32, 2);
+        builder.jump(merge);
+
+        builder.switch_to_block(else_block);
+        let three = builder.iconst(Type::I32, 3);
+        builder.jump(merge);
+
+        builder.switch_to_block(merge);
+        let phi = builder.phi(Type::I32, vec![(then_block, two), (else_block, three)]);
+        let result = builder.binary(BinaryOp::Add, phi, one);
+        builder.ret(Some(result));
+
+        assert!(ConditionalConstantPropagation.run(&mut function));
+
+        assert_eq!(Some(Terminator::Jump(then_block)), function.block(entry).terminator);
+        assert_eq!(InstKind::Const(2), function.block(merge).insts[0].kind);
+        assert_eq!(InstKind::Const(3), function.block(merge).insts[1].kind);
+    }
+
+    #[test]
+    fn test_loop_phi() {
+        let mut function = Function::new("f", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        let header = builder.create_block();
+        let body = builder.create_block();
+        let exit = builder.create_block();
+
+        builder.switch_to_block(entry);
+        let condition = builder.add_param(Type::I32);
+        let one = builder.iconst(Type::I32, 1);
+        builder.jump(header);
+
+        builder.switch_to_block(header);
+        let phi = builder.phi(Type::I32, vec![(entry, one)]);
+        builder.branch(condition, body, exit);
+
+        // The value coming back around the loop is the same constant
+        builder.switch_to_block(body);
+        let product = builder.binary(BinaryOp::Mul, phi, one);
+        builder.jump(header);
+
+        builder.switch_to_block(exit);
+        builder.ret(Some(phi));
+
+        if let InstKind::Phi(incoming) = &mut function.block_mut(header).insts[0].kind {
+            incoming.push((body, product));
+        }
+
+        assert!(ConditionalConstantPropagation.run(&mut function));
+
+        assert_eq!(InstKind::Const(1), function.block(header).insts[0].kind);
+        assert_eq!(InstKind::Const(1), function.block(body).insts[0].kind);
+        assert_eq!(Some(Terminator::Branch(condition, body, exit)), function.block(header).terminator);
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use crate::ir::{BinaryOp, Function, Inst, InstKind, Type, Value};
+
+use super::Pass;
+
+// Constant folding, algebraic identities (x + 0, x * 1, ...) and strength
+// reduction of multiplication and division by powers of two into shifts
+pub struct AlgebraicSimplification;
+
+impl Pass for AlgebraicSimplification {
+    fn run(&self, function: &mut Function) -> bool {
+        let mut constants: HashMap<Value, i64> = HashMap::new();
+
+        for block in &function.blocks {
+            for inst in &block.insts {
+                if let (InstKind::Const(value), Some(result)) = (&inst.kind, inst.result) {
+                    constants.insert(result, *value);
+                }
+            }
+        }
+
+        let mut changed = false;
+
+        for index in 0..function.blocks.len() {
+            let insts = std::mem::take(&mut function.blocks[index].insts);
+            let mut simplifier = Simplifier {
+                function,
+                constants: &mut constants,
+                insts: Vec::new(),
+                changed: false,
+            };
+
+            for inst in insts {
+                simplifier.simplify(inst);
+            }
+
+            changed |= simplifier.changed;
+
+            let insts = simplifier.insts;
+            function.blocks[index].insts = insts;
+        }
+
+        changed
+    }
+}
+
+enum Rewrite {
+    Keep,
+    Const(i64),
+    Copy(Value),
+    Kind(InstKind),
+}
+
+struct Simplifier<'a> {
+    function: &'a mut Function,
+    constants: &'a mut HashMap<Value, i64>,
+    insts: Vec<Inst>,
+    changed: bool,
+}
+
+impl<'a> Simplifier<'a> {
+    fn simplify(&mut self, mut inst: Inst) {
+        let rewrite = match &inst.kind {
+            InstKind::Binary(op, lhs, rhs) => self.simplify_binary(*op, inst.ty, *lhs, *rhs),
+
+            InstKind::Unary(op, value) => match self.constant(*value) {
+                Some(value) => Rewrite::Const(op.fold(inst.ty, value)),
+                None => Rewrite::Keep,
+            },
+
+            _ => Rewrite::Keep,
+        };
+
+        let kind = match rewrite {
+            Rewrite::Keep => {
+                self.insts.push(inst);
+                return;
+            },
+
+            Rewrite::Const(value) => {
+                self.constants.insert(inst.result.unwrap(), value);
+                InstKind::Const(value)
+            },
+
+            Rewrite::Copy(value) => InstKind::Copy(value),
+            Rewrite::Kind(kind) => kind,
+        };
+
+        inst.kind = kind;
+        self.insts.push(inst);
+        self.changed = true;
+    }
+
+    fn simplify_binary(&mut self, op: BinaryOp, ty: Type, lhs: Value, rhs: Value) -> Rewrite {
+        let lhs_constant = self.constant(lhs);
+        let rhs_constant = self.constant(rhs);
+
+        if let (Some(lhs), Some(rhs)) = (lhs_constant, rhs_constant) {
+            return match op.fold(ty, lhs, rhs) {
+                Some(value) => Rewrite::Const(value),
+                None => Rewrite::Keep,
+            };
+        }
+
+        match (op, lhs_constant, rhs_constant) {
+            (BinaryOp::Add, _, Some(0)) => Rewrite::Copy(lhs),
+            (BinaryOp::Add, Some(0), _) => Rewrite::Copy(rhs),
+            (BinaryOp::Sub, _, Some(0)) => Rewrite::Copy(lhs),
+            (BinaryOp::Sub, _, _) if lhs == rhs => Rewrite::Const(0),
+
+            (BinaryOp::Mul, _, Some(1)) => Rewrite::Copy(lhs),
+            (BinaryOp::Mul, Some(1), _) => Rewrite::Copy(rhs),
+            (BinaryOp::Mul, _, Some(0)) | (BinaryOp::Mul, Some(0), _) => Rewrite::Const(0),
+
+            // x * 2^k => x << k
+            (BinaryOp::Mul, _, Some(n)) if is_power_of_two(n) => {
+                let shift = self.emit_const(ty, n.trailing_zeros() as i64);
+                Rewrite::Kind(InstKind::Binary(BinaryOp::Shl, lhs, shift))
+            },
+
+            (BinaryOp::Mul, Some(n), _) if is_power_of_two(n) => {
+                let shift = self.emit_const(ty, n.trailing_zeros() as i64);
+                Rewrite::Kind(InstKind::Binary(BinaryOp::Shl, rhs, shift))
+            },
+
+            (BinaryOp::Div, _, Some(1)) => Rewrite::Copy(lhs),
+
+            // Signed division rounds towards zero, so negative dividends are
+            // biased by 2^k - 1 before the arithmetic shift
+            (BinaryOp::Div, _, Some(n)) if is_power_of_two(n) && ty == Type::I32 => {
+                let k = n.trailing_zeros() as i64;
+
+                let sign_shift = self.emit_const(ty, 31);
+                let sign = self.emit(ty, InstKind::Binary(BinaryOp::Sar, lhs, sign_shift));
+                let bias_shift = self.emit_const(ty, 32 - k);
+                let bias = self.emit(ty, InstKind::Binary(BinaryOp::Shr, sign, bias_shift));
+                let biased = self.emit(ty, InstKind::Binary(BinaryOp::Add, lhs, bias));
+                let shift = self.emit_const(ty, k);
+
+                Rewrite::Kind(InstKind::Binary(BinaryOp::Sar, biased, shift))
+            },
+
+            (BinaryOp::Udiv, _, Some(1)) => Rewrite::Copy(lhs),
+
+            // x / 2^k => x >> k and x % 2^k => x & (2^k - 1) for unsigned x
+            (BinaryOp::Udiv, _, Some(n)) if is_power_of_two(n) => {
+                let shift = self.emit_const(ty, n.trailing_zeros() as i64);
+                Rewrite::Kind(InstKind::Binary(BinaryOp::Shr, lhs, shift))
+            },
+
+            (BinaryOp::Urem, _, Some(n)) if is_power_of_two(n) => {
+                let mask = self.emit_const(ty, n - 1);
+                Rewrite::Kind(InstKind::Binary(BinaryOp::And, lhs, mask))
+            },
+
+            (BinaryOp::Shl | BinaryOp::Sar | BinaryOp::Shr, _, Some(0)) => Rewrite::Copy(lhs),
+
+            _ => Rewrite::Keep,
+        }
+    }
+
+    fn constant(&self, value: Value) -> Option<i64> {
+        self.constants.get(&value).copied()
+    }
+
+    fn emit_const(&mut self, ty: Type, value: i64) -> Value {
+        let result = self.emit(ty, InstKind::Const(value));
+        self.constants.insert(result, value);
+        result
+    }
+
+    fn emit(&mut self, ty: Type, kind: InstKind) -> Value {
+        let result = self.function.new_value(ty);
+        self.insts.push(Inst { result: Some(result), ty, kind });
+        result
+    }
+}
+
+fn is_power_of_two(value: i64) -> bool {
+    value > 1 && (value & (value - 1)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::builder::FunctionBuilder;
+    use crate::opt::simplify::*;
+
+    fn run_binary(op: BinaryOp, lhs: i64, rhs: i64) -> i64 {
+        let mut function = Function::new("f", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        builder.switch_to_block(entry);
+
+        let lhs = builder.iconst(Type::I32, lhs);
+        let rhs = builder.iconst(Type::I32, rhs);
+        let result = builder.binary(op, lhs, rhs);
+        builder.ret(Some(result));
+
+        AlgebraicSimplification.run(&mut function);
+
+        match function.blocks[0].insts.last().unwrap().kind {
+            InstKind::Const(value) => value,
+            _ => panic!("not folded"),
+        }
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        assert_eq!(-1, run_binary(BinaryOp::Sub, 2, 3));
+        assert_eq!(i32::MIN as i64, run_binary(BinaryOp::Add, i32::MAX as i64, 1));
+        assert_eq!(-2, run_binary(BinaryOp::Div, -7, 3));
+        assert_eq!(-1, run_binary(BinaryOp::Rem, -7, 3));
+        assert_eq!(6, run_binary(BinaryOp::Xor, 5, 3));
+        assert_eq!(1, run_binary(BinaryOp::Lt, -1, 0));
+        assert_eq!(0, run_binary(BinaryOp::Ult, -1, 0));
+
+        // -1 is u32::MAX and 4000000000 is above i32::MAX as unsigned
+        assert_eq!(1, run_binary(BinaryOp::Ugt, -1, 0));
+        assert_eq!(0, run_binary(BinaryOp::Ule, -1, 0));
+        assert_eq!(2000000000, run_binary(BinaryOp::Udiv, 4000000000, 2));
+        assert_eq!(1, run_binary(BinaryOp::Urem, 4000000001, 2));
+        assert_eq!(0x7FFFFFFF, run_binary(BinaryOp::Shr, -1, 1));
+    }
+
+    #[test]
+    fn test_division_by_power_of_two() {
+        let mut function = Function::new("f", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let x = builder.add_param(Type::I32);
+        let entry = builder.create_block();
+        builder.switch_to_block(entry);
+
+        let eight = builder.iconst(Type::I32, 8);
+        let result = builder.binary(BinaryOp::Div, x, eight);
+        builder.ret(Some(result));
+
+        assert!(AlgebraicSimplification.run(&mut function));
+
+        let insts = &function.blocks[0].insts;
+
+        assert!(insts.iter().all(|inst| !matches!(inst.kind, InstKind::Binary(BinaryOp::Div, _, _))));
+
+        // Evaluate the shift sequence for a few dividends
+        for dividend in [-17i64, -8, -1, 0, 7, 8, 100] {
+            let mut values: HashMap<Value, i64> = HashMap::new();
+            values.insert(x, dividend);
+
+            for inst in insts {
+                let value = match inst.kind {
+                    InstKind::Const(value) => value,
+                    InstKind::Binary(op, lhs, rhs) => op.fold(Type::I32, values[&lhs], values[&rhs]).unwrap(),
+                    _ => unreachable!(),
+                };
+
+                values.insert(inst.result.unwrap(), value);
+            }
+
+            assert_eq!(dividend / 8, values[&result]);
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use crate::ir::cfg::ControlFlowGraph;
+use crate::ir::{BlockId, Function, Inst, InstKind, Terminator};
+
+use super::Pass;
+
+// Threads jumps through empty blocks which only jump on, and merges blocks
+// into their only predecessor when it jumps to them. The blocks left without
+// predecessors are removed by dead code elimination
+pub struct CfgSimplification;
+
+impl Pass for CfgSimplification {
+    fn run(&self, function: &mut Function) -> bool {
+        let threaded = thread_jumps(function);
+        let merged = merge_blocks(function);
+
+        threaded || merged
+    }
+}
+
+// Each edge skips one empty block per run, so a cycle of them can't loop
+// forever
+fn thread_jumps(function: &mut Function) -> bool {
+    let mut changed = false;
+
+    for block in function.block_ids() {
+        for forwarder in function.block(block).terminator.as_ref().unwrap().successors() {
+            let Some(target) = forwarding_target(function, forwarder) else {
+                continue;
+            };
+
+            let successors = function.block(block).terminator.as_ref().unwrap().successors();
+
+            // Already threaded, for a branch or switch with several edges to it
+            if !successors.contains(&forwarder) {
+                continue;
+            }
+
+            // Instruction selection moves the values of phis at the end of
+            // the predecessor, so the edge to them must not become critical.
+            // The phis take the value coming from the empty block
+            if has_phis(function, target) {
+                if successors.len() > 1 {
+                    continue;
+                }
+
+                for inst in &mut function.block_mut(target).insts {
+                    if let InstKind::Phi(incoming) = &mut inst.kind {
+                        if let Some(&(_, value)) = incoming.iter().find(|(predecessor, _)| *predecessor == forwarder) {
+                            incoming.push((block, value));
+                        }
+                    }
+                }
+            }
+
+            for successor in function.block_mut(block).terminator.as_mut().unwrap().successors_mut() {
+                if *successor == forwarder {
+                    *successor = target;
+                }
+            }
+
+            changed = true;
+        }
+
+        let terminator = function.block_mut(block).terminator.as_mut().unwrap();
+
+        if let Terminator::Branch(_, then_block, else_block) = *terminator {
+            if then_block == else_block {
+                *terminator = Terminator::Jump(then_block);
+                changed = true;
+            }
+        }
+    }
+
+    changed
+}
+
+// The block jumped to by an empty block
+fn forwarding_target(function: &Function, block: BlockId) -> Option<BlockId> {
+    if block == function.entry() || !function.block(block).insts.is_empty() {
+        return None;
+    }
+
+    match function.block(block).terminator {
+        Some(Terminator::Jump(target)) if target != block => Some(target),
+        _ => None,
+    }
+}
+
+fn has_phis(function: &Function, block: BlockId) -> bool {
+    function.block(block).insts.iter().any(|inst| matches!(inst.kind, InstKind::Phi(_)))
+}
+
+fn merge_blocks(function: &mut Function) -> bool {
+    let mut changed = false;
+
+    while let Some((block, successor)) = find_merge(function) {
+        let merged = std::mem::take(function.block_mut(successor));
+        function.block_mut(successor).terminator = Some(Terminator::Unreachable);
+
+        let terminator = merged.terminator.unwrap();
+
+        for next in terminator.successors() {
+            for inst in &mut function.block_mut(next).insts {
+                if let InstKind::Phi(incoming) = &mut inst.kind {
+                    for (predecessor, _) in incoming.iter_mut() {
+                        if *predecessor == successor {
+                            *predecessor = block;
+                        }
+                    }
+                }
+            }
+        }
+
+        // With a single predecessor the phis are copies
+        let insts = merged.insts.into_iter().map(|inst| match inst.kind {
+            InstKind::Phi(incoming) if incoming.len() == 1 => Inst {
+                result: inst.result,
+                ty: inst.ty,
+                kind: InstKind::Copy(incoming[0].1),
+            },
+
+            _ => inst,
+        });
+
+        let target = function.block_mut(block);
+        target.insts.extend(insts);
+        target.terminator = Some(terminator);
+        changed = true;
+    }
+
+    changed
+}
+
+// A reachable block which jumps to a block with no other predecessor
+fn find_merge(function: &Function) -> Option<(BlockId, BlockId)> {
+    let cfg = ControlFlowGraph::new(function);
+
+    cfg.reverse_postorder().iter().find_map(|&block| match function.block(block).terminator {
+        Some(Terminator::Jump(successor))
+            if successor != block && successor != function.entry() && cfg.predecessors(successor) == [block] => {
+            Some((block, successor))
+        },
+
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::builder::FunctionBuilder;
+    use crate::ir::verifier::verify_module;
+    use crate::ir::{Module, Type};
+    use crate::opt::dead_code_elimination::DeadCodeElimination;
+    use crate::opt::simplify_cfg::*;
+    use crate::opt::{OptLevel, PassManager};
+
+    #[test]
+    fn test_threading_and_merging() {
+        let mut function = Function::new("f", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        let first = builder.create_block();
+        let second = builder.create_block();
+        let other = builder.create_block();
+        let join = builder.create_block();
+        let exit = builder.create_block();
+
+        builder.switch_to_block(entry);
+        let condition = builder.add_param(Type::I32);
+        let one = builder.iconst(Type::I32, 1);
+        let two = builder.iconst(Type::I32, 2);
+        builder.branch(condition, first, other);
+
+        builder.switch_to_block(first);
+        builder.jump(second);
+
+        builder.switch_to_block(second);
+        builder.jump(join);
+
+        builder.switch_to_block(other);
+        builder.jump(join);
+
+        builder.switch_to_block(join);
+        let phi = builder.phi(Type::I32, vec![(second, one), (other, two)]);
+        builder.jump(exit);
+
+        builder.switch_to_block(exit);
+        builder.ret(Some(phi));
+
+        let mut module = Module::new();
+        module.functions.push(function);
+
+        let mut manager = PassManager::new();
+        manager.add(Box::new(CfgSimplification));
+        manager.add(Box::new(DeadCodeElimination));
+        manager.run(&mut module);
+
+        assert_eq!(Ok(()), verify_module(&module));
+
+        // The empty blocks before the phi are kept, the edges from the
+        // branch to it would be critical
+        let function = &module.functions[0];
+        assert_eq!(4, function.blocks.len());
+        assert_eq!(Some(Terminator::Branch(condition, BlockId(1), BlockId(2))), function.block(entry).terminator);
+        assert_eq!(Some(Terminator::Return(Some(phi))), function.block(BlockId(3)).terminator);
+    }
+
+    #[test]
+    fn test_folded_branch() {
+        let mut function = Function::new("f", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        let first = builder.create_block();
+        let second = builder.create_block();
+        let other = builder.create_block();
+        let join = builder.create_block();
+
+        builder.switch_to_block(entry);
+        let one = builder.iconst(Type::I32, 1);
+        builder.branch(one, first, other);
+
+        builder.switch_to_block(first);
+        builder.jump(second);
+
+        builder.switch_to_block(second);
+        let two = builder.iconst(Type::I32, 2);
+        builder.jump(join);
+
+        builder.switch_to_block(other);
+        builder.jump(join);
+
+        builder.switch_to_block(join);
+        let phi = builder.phi(Type::I32, vec![(second, two), (other, one)]);
+        builder.ret(Some(phi));
+
+        let mut module = Module::new();
+        module.functions.push(function);
+        PassManager::for_level(OptLevel::O2).run(&mut module);
+
+        let function = &module.functions[0];
+        assert_eq!(1, function.blocks.len());
+        assert!(matches!(function.blocks[0].terminator, Some(Terminator::Return(Some(_)))));
+    }
+}
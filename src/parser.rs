@@ -1,24 +1,70 @@
+use crate::diagnostic::Diagnostic;
+use crate::lexer::{Lexer, KEYWORDS};
 use crate::token::Token;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum BinaryOperator {
     Plus,
     Minus,
     Multiply,
     Divide,
+    Remainder,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    ShiftLeft,
+    ShiftRight,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    LogicalAnd,
+    LogicalOr,
+    Power,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum UnaryOperator {
     Plus,
     Minus,
+    Not,
+    Reference, // `&a`
+    ReferenceMut, // `&mut a`
+    Deref, // `*p`
 }
 
 #[derive(Debug)]
 pub enum Expression {
     Binary(BinaryOperator, Box<Expression>, Box<Expression>),
     Unary(UnaryOperator, Box<Expression>),
-    Integer(i64),
+    Assign(Option<BinaryOperator>, Box<Expression>, Box<Expression>), // `a = b`, or `a op= b`
+    Integer(u64, Option<String>), // value and type suffix
+    Float(f64, Option<String>),
+    String(String),
+    ByteString(Vec<u8>),
+    Char(char),
+    Boolean(bool),
+    Null,
+    Identifier(String),
+    Call(Box<Expression>, Vec<Expression>), // callee and arguments
+    Index(Box<Expression>, Box<Expression>), // `a[i]`
+    Field(Box<Expression>, String), // `s.f`
+    Group(Box<Expression>), // `(a)`
+    Struct(String, Vec<Type>, Vec<(String, Expression)>), // `Point { x: 1, y: 2 }`, `Pair::<i32> { .. }`
+    Array(Vec<Expression>), // `[1, 2, 3]`
+    Path(Vec<String>, Vec<Type>), // `Shape::Circle`, `max::<i32>`, with the type arguments
+    Match(Box<Expression>, Vec<(Pattern, Expression)>), // scrutinee and arms
+}
+
+#[derive(Debug)]
+pub enum Pattern {
+    Wildcard, // `_`
+    Binding(String),
+    Integer(i128, Option<String>), // `-1`, `2u8`
+    Boolean(bool),
+    Variant(Vec<String>, Vec<Pattern>), // `Shape::Rect(w, _)`, path and payload
 }
 
 #[derive(Debug)]
@@ -26,104 +72,808 @@ pub struct Symbol {
 
 }
 
-/* #[derive(Debug)]
+#[derive(Debug)]
+pub enum Type {
+    Named(String),
+    Generic(String, Vec<Type>), // `Pair<i32>`
+    #[allow(unused)]
+    Pointer(bool, Box<Type>), // `*const T` when false, `*mut T` or `*T` otherwise
+    Reference(bool, Box<Type>), // `&T`, or `&mut T` when true
+    Array(Box<Type>, u64), // `[T; N]`
+    Slice(Box<Type>), // `&[T]`
+}
+
+#[derive(Debug)]
 pub enum Statement {
-    Return(Box<Expression>),
-    Function(Symbol, Vec<Statement>),
-} */
+    Let(String, bool, Option<Type>, Box<Expression>), // name, `mut`, type, value
+    Return(Option<Box<Expression>>),
+    Expression(Box<Expression>),
+}
+
+#[derive(Debug)]
+pub struct Parameter {
+    pub name: String,
+    pub type_: Type,
+}
+
+#[derive(Debug)]
+pub struct Function {
+    #[allow(unused)]
+    pub doc: Vec<String>, // `///` lines before the item, for documentation tools
+    pub public: bool,
+    pub name: String,
+    pub generics: Vec<String>, // `fn max<T>`
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<Type>,
+    pub body: Vec<Statement>,
+}
+
+// A function implemented outside of the module, `fn name(...) -> type;`
+#[derive(Debug)]
+pub struct ExternFunction {
+    #[allow(unused)]
+    pub doc: Vec<String>, // `///` lines before the item, for documentation tools
+    pub name: String,
+    pub parameters: Vec<Parameter>,
+    pub return_type: Option<Type>,
+}
+
+// `extern "abi" "library" { ... }`, the library is optional
+#[derive(Debug)]
+pub struct ExternBlock {
+    #[allow(unused)]
+    pub doc: Vec<String>, // `///` lines before the item, for documentation tools
+    pub public: bool, // for all of its functions
+    pub abi: String,
+    pub library: Option<String>,
+    pub functions: Vec<ExternFunction>,
+}
+
+// `struct Name<T, ...> { field: type, ... }`
+#[derive(Debug)]
+pub struct Struct {
+    #[allow(unused)]
+    pub doc: Vec<String>, // `///` lines before the item, for documentation tools
+    pub public: bool,
+    pub name: String,
+    pub generics: Vec<String>,
+    pub fields: Vec<Parameter>,
+}
+
+// `Name` or `Name(type, ...)`
+#[derive(Debug)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Type>,
+}
+
+// `enum Name<T, ...> { Variant, ... }`
+#[derive(Debug)]
+pub struct Enum {
+    #[allow(unused)]
+    pub doc: Vec<String>, // `///` lines before the item, for documentation tools
+    pub public: bool,
+    pub name: String,
+    pub generics: Vec<String>,
+    pub variants: Vec<Variant>,
+}
+
+// `mod name { ... }`, or `mod name;` with the items in `name.dl` next to
+// the file, which are filled in when the file is loaded
+#[derive(Debug)]
+pub struct ModuleDeclaration {
+    #[allow(unused)]
+    pub doc: Vec<String>, // `///` lines before the item, for documentation tools
+    pub public: bool,
+    pub name: String,
+    pub items: Option<Vec<Item>>, // none until the file is loaded
+}
+
+// `use a::b;` brings `b` into the module, `pub use` exports it too
+#[derive(Debug)]
+pub struct Use {
+    pub public: bool,
+    pub path: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Item {
+    Function(Function),
+    Extern(ExternBlock),
+    Struct(Struct),
+    Enum(Enum),
+    Module(ModuleDeclaration),
+    Use(Use),
+}
+
+#[derive(Debug)]
+pub struct Module {
+    pub items: Vec<Item>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Associativity {
+    Left,
+    Right,
+    None, // `a < b < c` is an error
+}
+
+#[derive(Clone, Copy)]
+enum Infix {
+    Assign(Option<BinaryOperator>),
+    Binary(BinaryOperator),
+}
+
+// Operators with their precedence, higher binds tighter. The levels follow
+// Rust: assignment, `||`, `&&`, comparisons, `|`, `^`, `&`, shifts, `+ -`,
+// `* / %`, prefix operators, `**`, calls. Unlike C, bitwise operators bind
+// tighter than comparisons
+const INFIX_OPERATORS: &[(Token<'static>, Infix, u8, Associativity)] = &[
+    (Token::Equal, Infix::Assign(None), 1, Associativity::Right),
+    (Token::PlusEqual, Infix::Assign(Some(BinaryOperator::Plus)), 1, Associativity::Right),
+    (Token::MinusEqual, Infix::Assign(Some(BinaryOperator::Minus)), 1, Associativity::Right),
+    (Token::StarEqual, Infix::Assign(Some(BinaryOperator::Multiply)), 1, Associativity::Right),
+    (Token::SlashEqual, Infix::Assign(Some(BinaryOperator::Divide)), 1, Associativity::Right),
+    (Token::PercentEqual, Infix::Assign(Some(BinaryOperator::Remainder)), 1, Associativity::Right),
+    (Token::AmpersandEqual, Infix::Assign(Some(BinaryOperator::BitwiseAnd)), 1, Associativity::Right),
+    (Token::PipeEqual, Infix::Assign(Some(BinaryOperator::BitwiseOr)), 1, Associativity::Right),
+    (Token::CaretEqual, Infix::Assign(Some(BinaryOperator::BitwiseXor)), 1, Associativity::Right),
+    (Token::ShiftLeftEqual, Infix::Assign(Some(BinaryOperator::ShiftLeft)), 1, Associativity::Right),
+    (Token::ShiftRightEqual, Infix::Assign(Some(BinaryOperator::ShiftRight)), 1, Associativity::Right),
+    (Token::OrOr, Infix::Binary(BinaryOperator::LogicalOr), 2, Associativity::Left),
+    (Token::AndAnd, Infix::Binary(BinaryOperator::LogicalAnd), 3, Associativity::Left),
+    (Token::EqualEqual, Infix::Binary(BinaryOperator::Equal), 4, Associativity::None),
+    (Token::NotEqual, Infix::Binary(BinaryOperator::NotEqual), 4, Associativity::None),
+    (Token::Less, Infix::Binary(BinaryOperator::Less), 4, Associativity::None),
+    (Token::LessEqual, Infix::Binary(BinaryOperator::LessEqual), 4, Associativity::None),
+    (Token::Greater, Infix::Binary(BinaryOperator::Greater), 4, Associativity::None),
+    (Token::GreaterEqual, Infix::Binary(BinaryOperator::GreaterEqual), 4, Associativity::None),
+    (Token::Pipe, Infix::Binary(BinaryOperator::BitwiseOr), 5, Associativity::Left),
+    (Token::Caret, Infix::Binary(BinaryOperator::BitwiseXor), 6, Associativity::Left),
+    (Token::Ampersand, Infix::Binary(BinaryOperator::BitwiseAnd), 7, Associativity::Left),
+    (Token::ShiftLeft, Infix::Binary(BinaryOperator::ShiftLeft), 8, Associativity::Left),
+    (Token::ShiftRight, Infix::Binary(BinaryOperator::ShiftRight), 8, Associativity::Left),
+    (Token::Plus, Infix::Binary(BinaryOperator::Plus), 9, Associativity::Left),
+    (Token::Minus, Infix::Binary(BinaryOperator::Minus), 9, Associativity::Left),
+    (Token::Star, Infix::Binary(BinaryOperator::Multiply), 10, Associativity::Left),
+    (Token::Slash, Infix::Binary(BinaryOperator::Divide), 10, Associativity::Left),
+    (Token::Percent, Infix::Binary(BinaryOperator::Remainder), 10, Associativity::Left),
+    (Token::StarStar, Infix::Binary(BinaryOperator::Power), 12, Associativity::Right),
+];
+
+// `-a * b` is `(-a) * b` but `-a ** b` is `-(a ** b)`
+const PREFIX_OPERATORS: &[(Token<'static>, UnaryOperator, u8)] = &[
+    (Token::Minus, UnaryOperator::Minus, 11),
+    (Token::Plus, UnaryOperator::Plus, 11),
+    (Token::Bang, UnaryOperator::Not, 11),
+    (Token::Ampersand, UnaryOperator::Reference, 11),
+    (Token::Star, UnaryOperator::Deref, 11),
+];
+
+#[derive(Clone, Copy)]
+enum Postfix {
+    Call,
+    Index,
+    Field,
+}
 
+const POSTFIX_OPERATORS: &[(Token<'static>, Postfix, u8)] = &[
+    (Token::LParen, Postfix::Call, 13),
+    (Token::LBracket, Postfix::Index, 13),
+    (Token::Dot, Postfix::Field, 13),
+];
+
+// Reads the tokens while parsing, one token ahead of the current position
 pub struct Parser<'a> {
-    tokens: &'a [Token],
-    offset: usize,
+    tokens: Lexer<'a>,
+    current: Option<Token<'a>>,
+    doc: Vec<&'a str>, // doc comments before `current`
+    error: Option<String>,
+    no_struct_literal: bool, // in `match x {`, the brace starts the arms
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a [Token]) -> Self {
-        Self {
+    pub fn new(tokens: Lexer<'a>) -> Self {
+        let mut parser = Self {
             tokens,
-            offset: 0,
-        }
+            current: None,
+            doc: Vec::new(),
+            error: None,
+            no_struct_literal: false,
+        };
+
+        parser.next();
+        parser
     }
 
-    pub fn parse(&mut self) -> Result<Box<Expression>, ()> {
-        self.offset = 0;
+    // Lexical errors in the part of the source read so far, these explain a
+    // syntax error better than `error`
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.tokens.diagnostics()
+    }
 
+    // Explains the last syntax error when there is more to say than
+    // "syntax error"
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    #[allow(unused)]
+    pub fn parse(&mut self) -> Result<Box<Expression>, ()> {
         self.parse_expression()
     }
 
-/*     fn parse_statement() -> Result<Box<Statement>, ()> {
+    pub fn parse_module(&mut self) -> Result<Module, ()> {
+        let mut items = Vec::new();
+
+        // A doc comment at the end of the file still needs an item
+        while self.peek().is_some() || !self.doc.is_empty() {
+            items.push(self.parse_item()?);
+        }
+
+        Ok(Module { items })
+    }
+
+    fn parse_item(&mut self) -> Result<Item, ()> {
+        let doc = self.take_doc_comments();
+        let public = self.peek() == Some(&Token::Pub);
+
+        if public {
+            self.next();
+        }
+
+        if self.peek() == Some(&Token::Fn) {
+            return Ok(Item::Function(self.parse_function(doc, public)?));
+        }
+
+        if self.peek() == Some(&Token::Extern) {
+            return Ok(Item::Extern(self.parse_extern_block(doc, public)?));
+        }
+
+        if self.peek() == Some(&Token::Struct) {
+            return Ok(Item::Struct(self.parse_struct(doc, public)?));
+        }
+
+        if self.peek() == Some(&Token::Enum) {
+            return Ok(Item::Enum(self.parse_enum(doc, public)?));
+        }
+
+        if self.peek() == Some(&Token::Mod) {
+            return Ok(Item::Module(self.parse_module_declaration(doc, public)?));
+        }
+
+        if self.peek() == Some(&Token::Use) {
+            return Ok(Item::Use(self.parse_use(public)?));
+        }
+
         Err(())
-    } */
+    }
 
-    fn parse_expression(&mut self) -> Result<Box<Expression>, ()> {
-        self.parse_addition()
+    fn parse_module_declaration(&mut self, doc: Vec<String>, public: bool) -> Result<ModuleDeclaration, ()> {
+        self.expect(Token::Mod)?;
+
+        let name = self.expect_identifier()?;
+
+        if self.peek() == Some(&Token::Semicolon) {
+            self.next();
+            return Ok(ModuleDeclaration { doc, public, name, items: None });
+        }
+
+        let mut items = Vec::new();
+
+        self.expect(Token::LBrace)?;
+
+        while self.peek() != Some(&Token::RBrace) || !self.doc.is_empty() {
+            if self.peek().is_none() {
+                return Err(());
+            }
+
+            items.push(self.parse_item()?);
+        }
+
+        self.expect(Token::RBrace)?;
+
+        Ok(ModuleDeclaration { doc, public, name, items: Some(items) })
+    }
+
+    fn parse_use(&mut self, public: bool) -> Result<Use, ()> {
+        self.expect(Token::Use)?;
+
+        let mut path = vec![self.expect_identifier()?];
+
+        while self.peek() == Some(&Token::ColonColon) {
+            self.next();
+            path.push(self.expect_identifier()?);
+        }
+
+        self.expect(Token::Semicolon)?;
+
+        Ok(Use { public, path })
     }
 
-    fn parse_addition(&mut self) -> Result<Box<Expression>, ()> {
-        let mut expression = self.parse_multiplication()?;
+    fn parse_extern_block(&mut self, doc: Vec<String>, public: bool) -> Result<ExternBlock, ()> {
+        self.expect(Token::Extern)?;
+
+        let abi = self.expect_string()?;
+
+        let library = match self.peek() {
+            Some(Token::StringLiteral(_)) => Some(self.expect_string()?),
+            _ => None,
+        };
+
+        let mut functions = Vec::new();
+
+        self.expect(Token::LBrace)?;
 
         loop {
-            let binary_operator = match self.peek() {
-                Some(Token::Plus) => BinaryOperator::Plus,
-                Some(Token::Minus) => BinaryOperator::Minus,
+            let doc = self.take_doc_comments();
 
-                _ => break,
-            };
+            if doc.is_empty() && self.peek() == Some(&Token::RBrace) {
+                break;
+            }
 
-            self.next();
+            self.expect(Token::Fn)?;
+
+            let name = self.expect_identifier()?;
+            let (parameters, return_type) = self.parse_signature()?;
 
-            let right_expression = self.parse_multiplication()?;
+            self.expect(Token::Semicolon)?;
 
-            expression = Box::new(Expression::Binary(binary_operator, expression, right_expression));
+            functions.push(ExternFunction { doc, name, parameters, return_type });
         }
-        
-        Ok(expression)
+
+        self.expect(Token::RBrace)?;
+
+        Ok(ExternBlock { doc, public, abi, library, functions })
     }
 
-    fn parse_multiplication(&mut self) -> Result<Box<Expression>, ()> {
-        let mut expression = self.parse_unary()?;
+    fn parse_struct(&mut self, doc: Vec<String>, public: bool) -> Result<Struct, ()> {
+        self.expect(Token::Struct)?;
+
+        let name = self.expect_identifier()?;
+        let generics = self.parse_generics()?;
+        let mut fields = Vec::new();
+
+        self.expect(Token::LBrace)?;
+
+        // A trailing comma is allowed
+        while self.peek() != Some(&Token::RBrace) {
+            let name = self.expect_identifier()?;
+            self.expect(Token::Colon)?;
+            let type_ = self.parse_type()?;
+
+            fields.push(Parameter { name, type_ });
+
+            if self.peek() != Some(&Token::RBrace) {
+                self.expect(Token::Comma)?;
+            }
+        }
+
+        self.expect(Token::RBrace)?;
+
+        Ok(Struct { doc, public, name, generics, fields })
+    }
+
+    fn parse_enum(&mut self, doc: Vec<String>, public: bool) -> Result<Enum, ()> {
+        self.expect(Token::Enum)?;
+
+        let name = self.expect_identifier()?;
+        let generics = self.parse_generics()?;
+        let mut variants = Vec::new();
+
+        self.expect(Token::LBrace)?;
+
+        // `A(i32,),` is allowed
+        while self.peek() != Some(&Token::RBrace) {
+            let name = self.expect_identifier()?;
+            let mut fields = Vec::new();
+
+            if self.peek() == Some(&Token::LParen) {
+                self.next();
+
+                while self.peek() != Some(&Token::RParen) {
+                    fields.push(self.parse_type()?);
+
+                    if self.peek() != Some(&Token::RParen) {
+                        self.expect(Token::Comma)?;
+                    }
+                }
+
+                self.expect(Token::RParen)?;
+            }
+
+            variants.push(Variant { name, fields });
+
+            if self.peek() != Some(&Token::RBrace) {
+                self.expect(Token::Comma)?;
+            }
+        }
+
+        self.expect(Token::RBrace)?;
+
+        Ok(Enum { doc, public, name, generics, variants })
+    }
+
+    // `<T, U>` after the name of an item, `<T,>` is allowed
+    fn parse_generics(&mut self) -> Result<Vec<String>, ()> {
+        let mut generics = Vec::new();
+
+        if self.peek() != Some(&Token::Less) {
+            return Ok(generics);
+        }
+
+        self.next();
+
+        while self.peek() != Some(&Token::Greater) {
+            generics.push(self.expect_identifier()?);
+
+            if self.peek() != Some(&Token::Greater) {
+                self.expect(Token::Comma)?;
+            }
+        }
+
+        self.expect(Token::Greater)?;
+
+        Ok(generics)
+    }
+
+    // `<i32, Pair<u8>>`, the `<` is already read
+    fn parse_type_arguments(&mut self) -> Result<Vec<Type>, ()> {
+        let mut arguments = Vec::new();
 
         loop {
-            let binary_operator = match self.peek() {
-                Some(Token::Star) => BinaryOperator::Multiply,
-                Some(Token::Slash) => BinaryOperator::Divide,
+            arguments.push(self.parse_type()?);
+
+            if self.peek() != Some(&Token::Comma) {
+                break;
+            }
+
+            self.next();
+        }
 
-                _ => break,
+        // The lexer reads `>>` in `Pair<Pair<i32>>` as a shift
+        if self.peek() == Some(&Token::ShiftRight) {
+            self.current = Some(Token::Greater);
+        } else {
+            self.expect(Token::Greater)?;
+        }
+
+        Ok(arguments)
+    }
+
+    fn parse_function(&mut self, doc: Vec<String>, public: bool) -> Result<Function, ()> {
+        self.expect(Token::Fn)?;
+
+        let name = self.expect_identifier()?;
+        let generics = self.parse_generics()?;
+        let (parameters, return_type) = self.parse_signature()?;
+        let body = self.parse_block()?;
+
+        Ok(Function {
+            doc,
+            public,
+            name,
+            generics,
+            parameters,
+            return_type,
+            body,
+        })
+    }
+
+    // Parameters and the optional return type
+    fn parse_signature(&mut self) -> Result<(Vec<Parameter>, Option<Type>), ()> {
+        let mut parameters = Vec::new();
+
+        self.expect(Token::LParen)?;
+
+        while self.peek() != Some(&Token::RParen) {
+            if !parameters.is_empty() {
+                self.expect(Token::Comma)?;
+            }
+
+            let name = self.expect_identifier()?;
+            self.expect(Token::Colon)?;
+            let type_ = self.parse_type()?;
+
+            parameters.push(Parameter { name, type_ });
+        }
+
+        self.expect(Token::RParen)?;
+
+        let return_type = if self.peek() == Some(&Token::Arrow) {
+            self.next();
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        Ok((parameters, return_type))
+    }
+
+    fn parse_type(&mut self) -> Result<Type, ()> {
+        if self.peek() == Some(&Token::Star) {
+            self.next();
+
+            let mutable = match self.peek() {
+                Some(Token::Const) => false,
+                Some(Token::Mut) => true,
+                _ => return Ok(Type::Pointer(true, Box::new(self.parse_type()?))),
             };
 
             self.next();
 
-            let right_expression = self.parse_unary()?;
+            return Ok(Type::Pointer(mutable, Box::new(self.parse_type()?)));
+        }
+
+        // `&[T]` is a slice, `&[T; N]` a reference to an array
+        if self.peek() == Some(&Token::Ampersand) {
+            self.next();
+
+            let mutable = self.peek() == Some(&Token::Mut);
+
+            if mutable {
+                self.next();
+            } else if self.peek() == Some(&Token::LBracket) {
+                self.next();
+
+                let element = self.parse_type()?;
+
+                if self.peek() == Some(&Token::RBracket) {
+                    self.next();
+                    return Ok(Type::Slice(Box::new(element)));
+                }
 
-            expression = Box::new(Expression::Binary(binary_operator, expression, right_expression));
+                return Ok(Type::Reference(false, Box::new(self.parse_array_length(element)?)));
+            }
+
+            return Ok(Type::Reference(mutable, Box::new(self.parse_type()?)));
         }
-        
-        Ok(expression)
+
+        if self.peek() == Some(&Token::LBracket) {
+            self.next();
+
+            let element = self.parse_type()?;
+            return self.parse_array_length(element);
+        }
+
+        // `a::b::T` is kept as one name
+        let mut name = self.expect_identifier()?;
+
+        while self.peek() == Some(&Token::ColonColon) {
+            self.next();
+            name = format!("{}::{}", name, self.expect_identifier()?);
+        }
+
+        if self.peek() == Some(&Token::Less) {
+            self.next();
+            return Ok(Type::Generic(name, self.parse_type_arguments()?));
+        }
+
+        Ok(Type::Named(name))
     }
 
-    fn parse_unary(&mut self) -> Result<Box<Expression>, ()> {
-        let unary_operator_opt = match self.peek() {
-            Some(Token::Minus) => Some(UnaryOperator::Minus),
-            Some(Token::Plus) => Some(UnaryOperator::Plus),
-            _ => None,
+    // `; N]` after the element type of an array
+    fn parse_array_length(&mut self, element: Type) -> Result<Type, ()> {
+        self.expect(Token::Semicolon)?;
+
+        // The length is a plain integer, there are no constants yet
+        let length = match self.peek() {
+            Some(Token::IntegerLiteral(value, None)) => *value,
+            _ => {
+                self.error = Some("expected the length of the array".to_string());
+                return Err(());
+            },
         };
 
-        if let Some(unary_operator) = unary_operator_opt {
+        self.next();
+        self.expect(Token::RBracket)?;
+
+        Ok(Type::Array(Box::new(element), length))
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Statement>, ()> {
+        let mut statements = Vec::new();
+
+        self.expect(Token::LBrace)?;
+
+        while self.peek() != Some(&Token::RBrace) {
+            if self.peek().is_none() {
+                return Err(());
+            }
+
+            statements.push(self.parse_statement()?);
+        }
+
+        self.expect(Token::RBrace)?;
+
+        Ok(statements)
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, ()> {
+        let statement = if self.peek() == Some(&Token::Let) {
+            self.next();
+
+            let mutable = self.peek() == Some(&Token::Mut);
+
+            if mutable {
+                self.next();
+            }
+
+            let name = self.expect_identifier()?;
+
+            let type_ = if self.peek() == Some(&Token::Colon) {
+                self.next();
+                Some(self.parse_type()?)
+            } else {
+                None
+            };
+
+            self.expect(Token::Equal)?;
+
+            Statement::Let(name, mutable, type_, self.parse_expression()?)
+        } else if self.peek() == Some(&Token::Return) {
             self.next();
-            let expression = self.parse_unary()?;
-            Ok(Box::new(Expression::Unary(unary_operator, expression)))
+
+            if self.peek() == Some(&Token::Semicolon) {
+                Statement::Return(None)
+            } else {
+                Statement::Return(Some(self.parse_expression()?))
+            }
         } else {
-            self.parse_primary()
+            Statement::Expression(self.parse_expression()?)
+        };
+
+        self.expect(Token::Semicolon)?;
+
+        Ok(statement)
+    }
+
+    fn parse_expression(&mut self) -> Result<Box<Expression>, ()> {
+        self.parse_operators(0)
+    }
+
+    // Precedence climbing: takes operators binding at least as tight as
+    // `min_precedence`, the operand on the right gets one level more for
+    // left associative operators and the same level for right associative
+    // ones
+    fn parse_operators(&mut self, min_precedence: u8) -> Result<Box<Expression>, ()> {
+        let mut expression = self.parse_prefix()?;
+
+        loop {
+            if let Some(&(_, operator, precedence)) = self.peek_postfix() {
+                if precedence < min_precedence {
+                    break;
+                }
+
+                expression = self.parse_postfix(operator, expression)?;
+                continue;
+            }
+
+            let Some(&(_, operator, precedence, associativity)) = self.peek_infix() else {
+                break;
+            };
+
+            if precedence < min_precedence {
+                break;
+            }
+
+            self.next();
+
+            let right_precedence = match associativity {
+                Associativity::Right => precedence,
+                Associativity::Left | Associativity::None => precedence + 1,
+            };
+
+            let right_expression = self.parse_operators(right_precedence)?;
+
+            expression = Box::new(match operator {
+                Infix::Assign(operator) => Expression::Assign(operator, expression, right_expression),
+                Infix::Binary(operator) => Expression::Binary(operator, expression, right_expression),
+            });
+
+            let chained = self.peek_infix()
+                .is_some_and(|&(_, _, next_precedence, _)| next_precedence == precedence);
+
+            if associativity == Associativity::None && chained {
+                self.error = Some("comparison operators cannot be chained".to_string());
+                return Err(());
+            }
+        }
+
+        Ok(expression)
+    }
+
+    fn parse_prefix(&mut self) -> Result<Box<Expression>, ()> {
+        match self.peek_prefix() {
+            Some(&(_, mut operator, precedence)) => {
+                self.next();
+
+                if matches!(operator, UnaryOperator::Reference) && self.peek() == Some(&Token::Mut) {
+                    self.next();
+                    operator = UnaryOperator::ReferenceMut;
+                }
+
+                let expression = self.parse_operators(precedence)?;
+                Ok(Box::new(Expression::Unary(operator, expression)))
+            },
+
+            None => self.parse_primary(),
         }
     }
 
     fn parse_primary(&mut self) -> Result<Box<Expression>, ()> {
         if let Some(token) = self.peek() {
             let expression = match token {
-                Token::NumberLiteral(n) => Expression::Integer(*n),
+                Token::IntegerLiteral(value, suffix) => Expression::Integer(*value, suffix.map(str::to_string)),
+                Token::FloatLiteral(value, suffix) => Expression::Float(*value, suffix.map(str::to_string)),
+                Token::StringLiteral(s) => Expression::String(s.to_string()),
+                Token::ByteStringLiteral(bytes) => Expression::ByteString(bytes.to_vec()),
+                Token::CharLiteral(ch) => Expression::Char(*ch),
+                Token::True => Expression::Boolean(true),
+                Token::False => Expression::Boolean(false),
+                Token::Null => Expression::Null,
+
+                // `Name {` starts a struct literal, except in a scrutinee
+                Token::Identifier(name) => {
+                    let name = name.to_string();
+                    self.next();
+
+                    if self.peek() == Some(&Token::ColonColon) {
+                        let (path, arguments) = self.parse_path(name)?;
+
+                        // `Pair::<i32> { .. }` and `shapes::Point { .. }`
+                        if self.peek() == Some(&Token::LBrace) && !self.no_struct_literal {
+                            return self.parse_struct_literal(path.join("::"), arguments);
+                        }
 
-                //
-                _ => unreachable!(),
+                        return Ok(Box::new(Expression::Path(path, arguments)));
+                    }
+
+                    if self.peek() == Some(&Token::LBrace) && !self.no_struct_literal {
+                        return self.parse_struct_literal(name, Vec::new());
+                    }
+
+                    return Ok(Box::new(Expression::Identifier(name)));
+                },
+
+                // Struct literals are unambiguous again inside parentheses
+                Token::LParen => {
+                    self.next();
+
+                    let no_struct_literal = std::mem::replace(&mut self.no_struct_literal, false);
+                    let expression = self.parse_expression()?;
+                    self.no_struct_literal = no_struct_literal;
+
+                    self.expect(Token::RParen)?;
+
+                    return Ok(Box::new(Expression::Group(expression)));
+                },
+
+                Token::Match => return self.parse_match(),
+
+                Token::LBracket => {
+                    self.next();
+
+                    let mut elements = Vec::new();
+
+                    // `[a, b,]` is allowed
+                    while self.peek() != Some(&Token::RBracket) {
+                        elements.push(*self.parse_expression()?);
+
+                        if self.peek() != Some(&Token::RBracket) {
+                            self.expect(Token::Comma)?;
+                        }
+                    }
+
+                    self.expect(Token::RBracket)?;
+
+                    return Ok(Box::new(Expression::Array(elements)));
+                },
+
+                _ => {
+                    self.check_reserved_word();
+                    return Err(());
+                },
             };
             
             self.next();
@@ -134,15 +884,406 @@ impl<'a> Parser<'a> {
         Err(())
     }
 
-    fn peek(&self) -> Option<&Token> {
-        if self.offset < self.tokens.len() {
-            Some(&self.tokens[self.offset])
+    // `a::b::c`, the first name is already read. One of the names can be
+    // followed by type arguments, like in `max::<i32>` or `Option::<i32>::None`
+    fn parse_path(&mut self, first: String) -> Result<(Vec<String>, Vec<Type>), ()> {
+        let mut path = vec![first];
+        let mut arguments = Vec::new();
+
+        while self.peek() == Some(&Token::ColonColon) {
+            self.next();
+
+            if self.peek() == Some(&Token::Less) && arguments.is_empty() {
+                self.next();
+                arguments = self.parse_type_arguments()?;
+            } else {
+                path.push(self.expect_identifier()?);
+            }
+        }
+
+        Ok((path, arguments))
+    }
+
+    // `match x { pattern => expression, ... }`, the comma after the last arm
+    // is optional
+    fn parse_match(&mut self) -> Result<Box<Expression>, ()> {
+        self.expect(Token::Match)?;
+
+        let no_struct_literal = std::mem::replace(&mut self.no_struct_literal, true);
+        let scrutinee = self.parse_expression();
+        self.no_struct_literal = no_struct_literal;
+
+        let scrutinee = scrutinee?;
+        let mut arms = Vec::new();
+
+        self.expect(Token::LBrace)?;
+
+        while self.peek() != Some(&Token::RBrace) {
+            let pattern = self.parse_pattern()?;
+            self.expect(Token::FatArrow)?;
+            let expression = self.parse_expression()?;
+
+            arms.push((pattern, *expression));
+
+            if self.peek() != Some(&Token::RBrace) {
+                self.expect(Token::Comma)?;
+            }
+        }
+
+        self.expect(Token::RBrace)?;
+
+        Ok(Box::new(Expression::Match(scrutinee, arms)))
+    }
+
+    fn parse_pattern(&mut self) -> Result<Pattern, ()> {
+        let negative = self.peek() == Some(&Token::Minus);
+
+        if negative {
+            self.next();
+        }
+
+        let pattern = match self.peek() {
+            Some(Token::IntegerLiteral(value, suffix)) => {
+                let value = if negative { -(*value as i128) } else { *value as i128 };
+                Pattern::Integer(value, suffix.map(str::to_string))
+            },
+
+            _ if negative => return Err(()),
+            Some(Token::True) => Pattern::Boolean(true),
+            Some(Token::False) => Pattern::Boolean(false),
+
+            Some(Token::Identifier(name)) => {
+                let name = name.to_string();
+                self.next();
+
+                if self.peek() != Some(&Token::ColonColon) {
+                    return Ok(if name == "_" { Pattern::Wildcard } else { Pattern::Binding(name) });
+                }
+
+                let (path, arguments) = self.parse_path(name)?;
+                let mut payload = Vec::new();
+
+                // The type of the scrutinee has the type arguments
+                if !arguments.is_empty() {
+                    self.error = Some("type arguments are not allowed in patterns".to_string());
+                    return Err(());
+                }
+
+                // `Some(a, b,)` is allowed
+                if self.peek() == Some(&Token::LParen) {
+                    self.next();
+
+                    while self.peek() != Some(&Token::RParen) {
+                        payload.push(self.parse_pattern()?);
+
+                        if self.peek() != Some(&Token::RParen) {
+                            self.expect(Token::Comma)?;
+                        }
+                    }
+
+                    self.expect(Token::RParen)?;
+                }
+
+                return Ok(Pattern::Variant(path, payload));
+            },
+
+            _ => {
+                self.check_reserved_word();
+                return Err(());
+            },
+        };
+
+        self.next();
+
+        Ok(pattern)
+    }
+
+    fn parse_struct_literal(&mut self, name: String, arguments: Vec<Type>) -> Result<Box<Expression>, ()> {
+        let mut fields = Vec::new();
+
+        self.expect(Token::LBrace)?;
+
+        while self.peek() != Some(&Token::RBrace) {
+            let field = self.expect_identifier()?;
+            self.expect(Token::Colon)?;
+            let value = self.parse_expression()?;
+
+            fields.push((field, *value));
+
+            if self.peek() != Some(&Token::RBrace) {
+                self.expect(Token::Comma)?;
+            }
+        }
+
+        self.expect(Token::RBrace)?;
+
+        Ok(Box::new(Expression::Struct(name, arguments, fields)))
+    }
+
+    fn parse_postfix(&mut self, operator: Postfix, expression: Box<Expression>) -> Result<Box<Expression>, ()> {
+        self.next();
+
+        let expression = match operator {
+            Postfix::Call => {
+                let mut arguments = Vec::new();
+
+                // `f(a, b,)` is allowed
+                while self.peek() != Some(&Token::RParen) {
+                    arguments.push(*self.parse_expression()?);
+
+                    if self.peek() != Some(&Token::RParen) {
+                        self.expect(Token::Comma)?;
+                    }
+                }
+
+                self.expect(Token::RParen)?;
+
+                Expression::Call(expression, arguments)
+            },
+
+            Postfix::Index => {
+                let index = self.parse_expression()?;
+
+                self.expect(Token::RBracket)?;
+
+                Expression::Index(expression, index)
+            },
+
+            Postfix::Field => Expression::Field(expression, self.expect_identifier()?),
+        };
+
+        Ok(Box::new(expression))
+    }
+
+    fn expect(&mut self, token: Token<'a>) -> Result<(), ()> {
+        if self.peek() == Some(&token) {
+            self.next();
+            Ok(())
         } else {
-            None
+            Err(())
         }
     }
 
+    fn expect_identifier(&mut self) -> Result<String, ()> {
+        if let Some(Token::Identifier(name)) = self.peek() {
+            let name = name.to_string();
+            self.next();
+            Ok(name)
+        } else {
+            self.check_reserved_word();
+            Err(())
+        }
+    }
+
+    fn peek_infix(&self) -> Option<&'static (Token<'static>, Infix, u8, Associativity)> {
+        INFIX_OPERATORS.iter().find(|(token, ..)| Some(token) == self.peek())
+    }
+
+    fn peek_prefix(&self) -> Option<&'static (Token<'static>, UnaryOperator, u8)> {
+        PREFIX_OPERATORS.iter().find(|(token, ..)| Some(token) == self.peek())
+    }
+
+    fn peek_postfix(&self) -> Option<&'static (Token<'static>, Postfix, u8)> {
+        POSTFIX_OPERATORS.iter().find(|(token, ..)| Some(token) == self.peek())
+    }
+
+    // Explains the error if a keyword is used where a name is expected
+    fn check_reserved_word(&mut self) {
+        let keyword = KEYWORDS
+            .iter()
+            .find(|(_, token)| Some(token) == self.peek());
+
+        if let Some((keyword, _)) = keyword {
+            self.error = Some(format!("`{0}` is a reserved word, write `r#{0}` to use it as a name", keyword));
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, ()> {
+        if let Some(Token::StringLiteral(value)) = self.peek() {
+            let value = value.to_string();
+            self.next();
+            Ok(value)
+        } else {
+            Err(())
+        }
+    }
+
+    // Doc comments directly before the current token
+    fn take_doc_comments(&mut self) -> Vec<String> {
+        self.doc.drain(..).map(str::to_string).collect()
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.current.as_ref()
+    }
+
+    // Doc comments are trivia, they are collected on the way for
+    // `take_doc_comments` and dropped at the next token
     fn next(&mut self) {
-        self.offset += 1;
+        self.doc.clear();
+
+        loop {
+            match self.tokens.next() {
+                Some(Token::DocComment(text)) => self.doc.push(text),
+
+                token => {
+                    self.current = token;
+                    break;
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::*;
+
+    #[test]
+    fn test_doc_comments() {
+        let mut parser = Parser::new(Lexer::new("/// Adds\n/// two numbers\nfn add() { /// ignored\n return; }\n///"));
+
+        // The trailing doc comment documents nothing
+        assert!(parser.parse_module().is_err());
+
+        let module = Parser::new(Lexer::new("/// Adds\n/// two numbers\nfn add() { /// ignored\n return; }")).parse_module().unwrap();
+
+        let Item::Function(function) = &module.items[0] else { panic!() };
+        assert_eq!(vec![" Adds", " two numbers"], function.doc);
+    }
+
+    #[test]
+    fn test_reserved_words() {
+        let mut parser = Parser::new(Lexer::new("fn main() { let loop = 1; }"));
+
+        assert!(parser.parse_module().is_err());
+        assert_eq!(Some("`loop` is a reserved word, write `r#loop` to use it as a name"), parser.error());
+
+        assert!(Parser::new(Lexer::new("fn main() { let r#loop = 1; }")).parse_module().is_ok());
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let module = Parser::new(Lexer::new("fn main() { x += 1 + 2 * 3 << 1 & 7 == 6 || !y && z; }")).parse_module().unwrap();
+
+        let Item::Function(function) = &module.items[0] else { panic!() };
+        let Statement::Expression(expression) = &function.body[0] else { panic!() };
+        let Expression::Assign(Some(BinaryOperator::Plus), _, value) = &**expression else { panic!() };
+        let Expression::Binary(BinaryOperator::LogicalOr, lhs, rhs) = &**value else { panic!() };
+        let Expression::Binary(BinaryOperator::Equal, lhs, _) = &**lhs else { panic!() };
+        let Expression::Binary(BinaryOperator::BitwiseAnd, lhs, _) = &**lhs else { panic!() };
+        assert!(matches!(**lhs, Expression::Binary(BinaryOperator::ShiftLeft, _, _)));
+        assert!(matches!(**rhs, Expression::Binary(BinaryOperator::LogicalAnd, _, _)));
+
+        let mut parser = Parser::new(Lexer::new("fn main() { let a = 1 < 2 < 3; }"));
+
+        assert!(parser.parse_module().is_err());
+        assert_eq!(Some("comparison operators cannot be chained"), parser.error());
+    }
+
+    #[test]
+    fn test_expression_trees() {
+        let expression = Parser::new(Lexer::new(include_str!("../test.dl"))).parse().unwrap();
+
+        assert_eq!(concat!(
+            "Binary(Plus, Binary(Minus, Binary(Multiply, Integer(2, None), Integer(123456, None)), Integer(654321, None)), ",
+            "Binary(Divide, Unary(Minus, Integer(9, None)), Unary(Minus, Unary(Minus, Unary(Minus, Integer(3, None))))))",
+        ), format!("{:?}", expression));
+
+        // Right associative
+        let expression = Parser::new(Lexer::new("a = b += -c ** d ** e")).parse().unwrap();
+
+        assert_eq!(concat!(
+            "Assign(None, Identifier(\"a\"), Assign(Some(Plus), Identifier(\"b\"), ",
+            "Unary(Minus, Binary(Power, Identifier(\"c\"), Binary(Power, Identifier(\"d\"), Identifier(\"e\"))))))",
+        ), format!("{:?}", expression));
+
+        let expression = Parser::new(Lexer::new("-f(1, 2) * 3")).parse().unwrap();
+
+        assert_eq!(
+            "Binary(Multiply, Unary(Minus, Call(Identifier(\"f\"), [Integer(1, None), Integer(2, None)])), Integer(3, None))",
+            format!("{:?}", expression)
+        );
+    }
+
+    #[test]
+    fn test_postfix_expressions() {
+        let expression = Parser::new(Lexer::new("(1 + 2) * f(a, b,)[i].x")).parse().unwrap();
+
+        assert_eq!(concat!(
+            "Binary(Multiply, Group(Binary(Plus, Integer(1, None), Integer(2, None))), ",
+            "Field(Index(Call(Identifier(\"f\"), [Identifier(\"a\"), Identifier(\"b\")]), Identifier(\"i\")), \"x\"))",
+        ), format!("{:?}", expression));
+
+        assert!(Parser::new(Lexer::new("f(,)")).parse().is_err());
+        assert!(Parser::new(Lexer::new("(1 + 2")).parse().is_err());
+    }
+
+    #[test]
+    fn test_match_expressions() {
+        let module = Parser::new(Lexer::new("enum Shape { Empty, Rect(i32, i32,), }")).parse_module().unwrap();
+
+        let Item::Enum(definition) = &module.items[0] else { panic!() };
+        assert_eq!(2, definition.variants.len());
+        assert_eq!(2, definition.variants[1].fields.len());
+
+        // The brace after the scrutinee starts the arms, not a struct literal
+        let expression = Parser::new(Lexer::new("match s { Shape::Rect(w, -1) => w, _ => Shape::Empty }")).parse().unwrap();
+
+        assert_eq!(concat!(
+            "Match(Identifier(\"s\"), [",
+            "(Variant([\"Shape\", \"Rect\"], [Binding(\"w\"), Integer(-1, None)]), Identifier(\"w\")), ",
+            "(Wildcard, Path([\"Shape\", \"Empty\"], []))])",
+        ), format!("{:?}", expression));
+
+        assert!(Parser::new(Lexer::new("match (P { x: 1 }) { _ => 0 }")).parse().is_ok());
+        assert!(Parser::new(Lexer::new("match x { 1 => 2 3 => 4 }")).parse().is_err());
+    }
+
+    #[test]
+    fn test_generics() {
+        let module = Parser::new(Lexer::new("fn first<T, U,>(p: Pair<Pair<T>>, u: U) -> T { return p.a.a; }")).parse_module().unwrap();
+
+        let Item::Function(function) = &module.items[0] else { panic!() };
+        assert_eq!(vec!["T", "U"], function.generics);
+
+        // `>>` closes two lists of type arguments
+        assert_eq!(
+            "Generic(\"Pair\", [Generic(\"Pair\", [Named(\"T\")])])",
+            format!("{:?}", function.parameters[0].type_)
+        );
+
+        let expression = Parser::new(Lexer::new("max::<i32>(Option::<u8>::None, Pair::<i8> { a: 1 })")).parse().unwrap();
+
+        assert_eq!(concat!(
+            "Call(Path([\"max\"], [Named(\"i32\")]), [Path([\"Option\", \"None\"], [Named(\"u8\")]), ",
+            "Struct(\"Pair\", [Named(\"i8\")], [(\"a\", Integer(1, None))])])",
+        ), format!("{:?}", expression));
+
+        // Without `::`, `<` is a comparison
+        assert!(matches!(*Parser::new(Lexer::new("a < b")).parse().unwrap(), Expression::Binary(BinaryOperator::Less, _, _)));
+    }
+
+    #[test]
+    fn test_pointers() {
+        let module = Parser::new(Lexer::new("fn f(a: *T, b: &mut T, c: &[T], d: &[T; 2]) {}")).parse_module().unwrap();
+
+        let Item::Function(function) = &module.items[0] else { panic!() };
+        let types: Vec<String> = function.parameters.iter().map(|parameter| format!("{:?}", parameter.type_)).collect();
+
+        assert_eq!(vec![
+            "Pointer(true, Named(\"T\"))",
+            "Reference(true, Named(\"T\"))",
+            "Slice(Named(\"T\"))",
+            "Reference(false, Array(Named(\"T\"), 2))",
+        ], types);
+
+        let expression = Parser::new(Lexer::new("*p.next == null && &mut x != &y")).parse().unwrap();
+
+        assert_eq!(concat!(
+            "Binary(LogicalAnd, Binary(Equal, Unary(Deref, Field(Identifier(\"p\"), \"next\")), Null), ",
+            "Binary(NotEqual, Unary(ReferenceMut, Identifier(\"x\")), Unary(Reference, Identifier(\"y\"))))",
+        ), format!("{:?}", expression));
     }
 }
@@ -0,0 +1,75 @@
+// The runtime library, which is written in dylang and compiled with every
+// program as the module `rt`. The platform is the one of the object format
+
+use crate::ir::modules::PRELUDE;
+use crate::lexer::Lexer;
+use crate::object_writer::ObjectFormat;
+use crate::parser::{self, Parser};
+
+const COMMON: &str = include_str!("runtime/common.dl");
+const WINDOWS: &str = include_str!("runtime/windows.dl");
+const LINUX: &str = include_str!("runtime/linux.dl");
+
+pub fn module(format: ObjectFormat) -> parser::Item {
+    let platform = match format {
+        ObjectFormat::Coff => WINDOWS,
+        ObjectFormat::Elf => LINUX,
+    };
+
+    let mut items = Vec::new();
+
+    for source in [COMMON, platform] {
+        let ast = Parser::new(Lexer::new(source)).parse_module().expect("syntax error in the runtime library");
+        items.extend(ast.items);
+    }
+
+    parser::Item::Module(parser::ModuleDeclaration {
+        doc: Vec::new(),
+        public: false,
+        name: PRELUDE.to_string(),
+        items: Some(items),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::lowering::{lower_module, LoweringOptions};
+    use crate::ir::verifier::verify_module;
+    use crate::ir::Module;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use crate::runtime::*;
+
+    fn lower(source: &str, format: ObjectFormat) -> Module {
+        let mut ast = Parser::new(Lexer::new(source)).parse_module().unwrap();
+        ast.items.push(module(format));
+
+        let module = lower_module(&ast, &LoweringOptions::default()).unwrap();
+        assert_eq!(Ok(()), verify_module(&module));
+        module
+    }
+
+    #[test]
+    fn test_runtime() {
+        let source = concat!(
+            "struct Node { value: i32, next: *Node }",
+            "fn main() {",
+            "    let node = alloc::<Node>(1);",
+            "    node.value = 42;",
+            "    print_str(\"value: \");",
+            "    print_int(node.value);",
+            "    free(node);",
+            "    exit(0);",
+            "}",
+        );
+
+        let module = lower(source, ObjectFormat::Coff);
+        assert!(module.function("rt::alloc$4Node").is_some());
+        assert!(module.externals.iter().any(|external| external.name == "HeapAlloc"));
+
+        let module = lower(source, ObjectFormat::Elf);
+        assert!(module.function("rt::alloc$4Node").is_some());
+        assert!(module.externals.iter().any(|external| external.name == "mmap" && external.library.is_none()));
+        assert!(!module.externals.iter().any(|external| external.name == "HeapAlloc"));
+    }
+}
@@ -0,0 +1,409 @@
+use std::collections::HashMap;
+
+use object::elf;
+use object::write::elf::{FileHeader, ProgramHeader, Rel, SectionHeader, Sym, Writer};
+use object::Endianness;
+
+use crate::codegen::{Codegen, RelocationKind};
+
+const PAGE_SIZE: u64 = 0x1000;
+const ALIGN: usize = 4;
+
+// The library of `extern` functions which do not name theirs, the C library
+pub const DEFAULT_LIBRARY: &str = "libc.so.6";
+
+// Writes an i386 ELF shared object. Everything lives in one RWX segment
+// loaded at the file offsets, absolute addresses are fixed up by the dynamic
+// loader with R_386_RELATIVE (so the text has relocations, DT_TEXTREL)
+pub struct SoWriter {
+    rdata: Vec<(String, Vec<u8>)>,
+    exports: Vec<String>,
+    needed: Vec<String>,
+}
+
+impl SoWriter {
+    pub fn new() -> Self {
+        Self {
+            rdata: Vec::new(),
+            exports: Vec::new(),
+            needed: Vec::new(),
+        }
+    }
+
+    pub fn add_rdata(&mut self, symbol: &str, bytes: &[u8]) {
+        self.rdata.push((symbol.to_string(), bytes.to_vec()));
+    }
+
+    // Adds the function defined by `symbol` to the dynamic symbol table
+    pub fn add_export(&mut self, symbol: &str) {
+        if !self.exports.iter().any(|export| export == symbol) {
+            self.exports.push(symbol.to_string());
+        }
+    }
+
+    // Makes the dynamic loader load `library` (DT_NEEDED) to resolve the
+    // undefined symbols
+    pub fn add_needed(&mut self, library: &str) {
+        if !self.needed.iter().any(|needed| needed == library) {
+            self.needed.push(library.to_string());
+        }
+    }
+
+    pub fn build(&self, codegen: &Codegen, soname: &str) -> Vec<u8> {
+        let mut rodata = Vec::new();
+        let mut rodata_symbols = Vec::new();
+
+        for (name, bytes) in &self.rdata {
+            rodata.resize(rodata.len().next_multiple_of(4), 0);
+            rodata_symbols.push((name.clone(), rodata.len()));
+            rodata.extend_from_slice(bytes);
+        }
+
+        // Relocations left by codegen refer to data or to functions defined
+        // elsewhere, the latter become undefined dynamic symbols
+        let mut externals: Vec<String> = Vec::new();
+
+        for relocation in codegen.relocations() {
+            let defined = codegen.symbols().contains_key(&relocation.symbol) ||
+                rodata_symbols.iter().any(|(name, _)| *name == relocation.symbol);
+
+            if !defined && !externals.contains(&relocation.symbol) {
+                externals.push(relocation.symbol.clone());
+            }
+        }
+
+        let mut out_data = Vec::new();
+        let mut writer = Writer::new(Endianness::Little, false, &mut out_data);
+
+        writer.reserve_file_header();
+        writer.reserve_program_headers(2);
+
+        let soname_id = writer.add_dynamic_string(soname.as_bytes());
+        let needed_ids: Vec<_> = self.needed.iter().map(|library| writer.add_dynamic_string(library.as_bytes())).collect();
+
+        let mut dynamic_symbols = Vec::new();
+
+        for name in self.exports.iter().chain(&externals) {
+            let id = writer.add_dynamic_string(name.as_bytes());
+            let index = writer.reserve_dynamic_symbol_index();
+            dynamic_symbols.push((name, id, index));
+        }
+
+        let symbol_count = writer.dynamic_symbol_count();
+        let bucket_count = symbol_count.max(1);
+
+        let hash_offset = next_offset(&writer);
+        writer.reserve_hash(bucket_count, symbol_count);
+
+        let dynsym_offset = next_offset(&writer);
+        writer.reserve_dynsym();
+
+        let dynstr_offset = next_offset(&writer);
+        writer.reserve_dynstr();
+        let dynstr_size = writer.reserved_len() - dynstr_offset;
+
+        let relocation_count = codegen.relocations().len();
+        let rel_offset = writer.reserve_relocations(relocation_count, false);
+
+        let text = codegen.get_bytes();
+        let text_offset = writer.reserve(text.len(), 16);
+        let rodata_offset = writer.reserve(rodata.len(), ALIGN);
+
+        // NEEDED for each library, SONAME, HASH, STRTAB, SYMTAB, STRSZ,
+        // SYMENT and NULL, plus REL, RELSZ, RELENT, TEXTREL and FLAGS when
+        // the text has relocations
+        let dynamic_count = needed_ids.len() + if relocation_count > 0 { 12 } else { 7 };
+        let dynamic_offset = next_offset(&writer);
+        writer.reserve_dynamic(dynamic_count);
+
+        let segment_size = writer.reserved_len();
+
+        writer.reserve_null_section_index();
+        writer.reserve_hash_section_index();
+        let dynsym_index = writer.reserve_dynsym_section_index();
+        writer.reserve_dynstr_section_index();
+        let rel_name = writer.add_section_name(b".rel.dyn");
+        writer.reserve_section_index();
+        let text_name = writer.add_section_name(b".text");
+        let text_index = writer.reserve_section_index();
+        let rodata_name = writer.add_section_name(b".rodata");
+        writer.reserve_section_index();
+        writer.reserve_dynamic_section_index();
+        writer.reserve_shstrtab_section_index();
+        writer.reserve_shstrtab();
+        writer.reserve_section_headers();
+
+        // Addresses are equal to file offsets
+        let mut addresses: HashMap<&str, u32> = HashMap::new();
+
+        for (name, offset) in codegen.symbols() {
+            addresses.insert(name, (text_offset + offset) as u32);
+        }
+
+        for (name, offset) in &rodata_symbols {
+            addresses.insert(name, (rodata_offset + offset) as u32);
+        }
+
+        let mut text = text.to_vec();
+        let mut relocations = Vec::new();
+
+        for relocation in codegen.relocations() {
+            let r_offset = (text_offset + relocation.offset) as u64;
+            let field = relocation.offset..relocation.offset + 4;
+
+            let (r_sym, r_type, addend) = match (addresses.get(relocation.symbol.as_str()), relocation.kind) {
+                (Some(address), RelocationKind::Absolute32) => (0, elf::R_386_RELATIVE, *address),
+                (Some(_), RelocationKind::Relative32) => unreachable!("resolved by codegen"),
+
+                (None, kind) => {
+                    let (_, _, index) = dynamic_symbols
+                        .iter()
+                        .find(|(name, _, _)| **name == relocation.symbol)
+                        .unwrap();
+
+                    match kind {
+                        RelocationKind::Absolute32 => (index.0, elf::R_386_32, 0),
+                        RelocationKind::Relative32 => (index.0, elf::R_386_PC32, -4i32 as u32),
+                    }
+                },
+            };
+
+            // REL relocations keep the addend in the relocated field
+            text[field].copy_from_slice(&addend.to_le_bytes());
+            relocations.push(Rel { r_offset, r_sym, r_type, r_addend: 0 });
+        }
+
+        writer.write_file_header(&FileHeader {
+            os_abi: elf::ELFOSABI_NONE,
+            abi_version: 0,
+            e_type: elf::ET_DYN,
+            e_machine: elf::EM_386,
+            e_entry: 0,
+            e_flags: 0,
+        }).unwrap();
+
+        writer.write_align_program_headers();
+
+        writer.write_program_header(&ProgramHeader {
+            p_type: elf::PT_LOAD,
+            p_flags: elf::PF_R | elf::PF_W | elf::PF_X,
+            p_offset: 0,
+            p_vaddr: 0,
+            p_paddr: 0,
+            p_filesz: segment_size as u64,
+            p_memsz: segment_size as u64,
+            p_align: PAGE_SIZE,
+        });
+
+        writer.write_program_header(&ProgramHeader {
+            p_type: elf::PT_DYNAMIC,
+            p_flags: elf::PF_R | elf::PF_W,
+            p_offset: dynamic_offset as u64,
+            p_vaddr: dynamic_offset as u64,
+            p_paddr: dynamic_offset as u64,
+            p_filesz: (dynamic_count * 8) as u64,
+            p_memsz: (dynamic_count * 8) as u64,
+            p_align: ALIGN as u64,
+        });
+
+        writer.write_hash(bucket_count, symbol_count, |index| {
+            let (name, _, _) = dynamic_symbols.get(index.checked_sub(1)? as usize)?;
+            Some(elf_hash(name.as_bytes()))
+        });
+
+        writer.write_null_dynamic_symbol();
+
+        for (name, id, _) in &dynamic_symbols {
+            let (section, value) = match addresses.get(name.as_str()) {
+                Some(address) => (Some(text_index), *address as u64),
+                None => (None, 0),
+            };
+
+            writer.write_dynamic_symbol(&Sym {
+                name: Some(*id),
+                section,
+                st_info: (elf::STB_GLOBAL << 4) | elf::STT_FUNC,
+                st_other: elf::STV_DEFAULT,
+                st_shndx: 0,
+                st_value: value,
+                st_size: 0,
+            });
+        }
+
+        writer.write_dynstr();
+
+        writer.write_align_relocation();
+
+        for relocation in &relocations {
+            writer.write_relocation(false, relocation);
+        }
+
+        writer.pad_until(text_offset);
+        writer.write(&text);
+        writer.pad_until(rodata_offset);
+        writer.write(&rodata);
+
+        writer.write_align_dynamic();
+
+        for id in &needed_ids {
+            writer.write_dynamic_string(elf::DT_NEEDED, *id);
+        }
+
+        writer.write_dynamic_string(elf::DT_SONAME, soname_id);
+        writer.write_dynamic(elf::DT_HASH, hash_offset as u64);
+        writer.write_dynamic(elf::DT_STRTAB, dynstr_offset as u64);
+        writer.write_dynamic(elf::DT_SYMTAB, dynsym_offset as u64);
+        writer.write_dynamic(elf::DT_STRSZ, dynstr_size as u64);
+        writer.write_dynamic(elf::DT_SYMENT, 16);
+
+        if relocation_count > 0 {
+            writer.write_dynamic(elf::DT_REL, rel_offset as u64);
+            writer.write_dynamic(elf::DT_RELSZ, (relocation_count * 8) as u64);
+            writer.write_dynamic(elf::DT_RELENT, 8);
+            writer.write_dynamic(elf::DT_TEXTREL, 0);
+            writer.write_dynamic(elf::DT_FLAGS, elf::DF_TEXTREL as u64);
+        }
+
+        writer.write_dynamic(elf::DT_NULL, 0);
+
+        writer.write_shstrtab();
+
+        writer.write_null_section_header();
+        writer.write_hash_section_header(hash_offset as u64);
+        writer.write_dynsym_section_header(dynsym_offset as u64, 1);
+        writer.write_dynstr_section_header(dynstr_offset as u64);
+
+        writer.write_section_header(&SectionHeader {
+            name: Some(rel_name),
+            sh_type: elf::SHT_REL,
+            sh_flags: elf::SHF_ALLOC as u64,
+            sh_addr: rel_offset as u64,
+            sh_offset: rel_offset as u64,
+            sh_size: (relocation_count * 8) as u64,
+            sh_link: dynsym_index.0,
+            sh_info: 0,
+            sh_addralign: ALIGN as u64,
+            sh_entsize: 8,
+        });
+
+        writer.write_section_header(&SectionHeader {
+            name: Some(text_name),
+            sh_type: elf::SHT_PROGBITS,
+            sh_flags: (elf::SHF_ALLOC | elf::SHF_EXECINSTR) as u64,
+            sh_addr: text_offset as u64,
+            sh_offset: text_offset as u64,
+            sh_size: text.len() as u64,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: 16,
+            sh_entsize: 0,
+        });
+
+        writer.write_section_header(&SectionHeader {
+            name: Some(rodata_name),
+            sh_type: elf::SHT_PROGBITS,
+            sh_flags: elf::SHF_ALLOC as u64,
+            sh_addr: rodata_offset as u64,
+            sh_offset: rodata_offset as u64,
+            sh_size: rodata.len() as u64,
+            sh_link: 0,
+            sh_info: 0,
+            sh_addralign: ALIGN as u64,
+            sh_entsize: 0,
+        });
+
+        writer.write_dynamic_section_header(dynamic_offset as u64);
+        writer.write_shstrtab_section_header();
+
+        out_data
+    }
+
+    pub fn write(&self, codegen: &Codegen, path: &str) {
+        let soname = std::path::Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(path);
+
+        std::fs::write(path, self.build(codegen, soname)).unwrap();
+    }
+}
+
+// Offset the next reservation will start at
+fn next_offset(writer: &Writer) -> usize {
+    writer.reserved_len().next_multiple_of(ALIGN)
+}
+
+// The SysV ELF hash function used by DT_HASH
+fn elf_hash(name: &[u8]) -> u32 {
+    let mut hash: u32 = 0;
+
+    for &byte in name {
+        hash = (hash << 4).wrapping_add(byte as u32);
+        let high = hash & 0xF0000000;
+
+        if high != 0 {
+            hash ^= high >> 24;
+        }
+
+        hash &= !high;
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use object::{Object, ObjectSection, ObjectSymbol};
+
+    use crate::so_writer::*;
+    use crate::x86::instruction_table::Kind;
+
+    #[test]
+    fn test_dynamic_symbols() {
+        let mut codegen = Codegen::new();
+
+        codegen.define_symbol("exported");
+        codegen.call_symbol("puts");
+        codegen.emit(Kind::Ret, None, None);
+        codegen.define_symbol("internal");
+        codegen.emit(Kind::Ret, None, None);
+        codegen.finish();
+
+        let mut writer = SoWriter::new();
+        writer.add_export("exported");
+        writer.add_needed(DEFAULT_LIBRARY);
+        writer.add_needed(DEFAULT_LIBRARY);
+
+        let bytes = writer.build(&codegen, "libtest.so");
+        let file = object::File::parse(&*bytes).unwrap();
+
+        // The first entry is the null symbol
+        let names: Vec<&str> = file.dynamic_symbols().skip(1).map(|symbol| symbol.name().unwrap()).collect();
+        assert_eq!(vec!["exported", "puts"], names);
+
+        let exported = file.dynamic_symbols().find(|symbol| symbol.name() == Ok("exported")).unwrap();
+        assert!(exported.is_global() && exported.is_definition());
+        assert_eq!(file.section_by_name(".text").unwrap().address(), exported.address());
+
+        let puts = file.dynamic_symbols().find(|symbol| symbol.name() == Ok("puts")).unwrap();
+        assert!(puts.is_undefined());
+
+        assert_eq!(vec![DEFAULT_LIBRARY.as_bytes()], needed_libraries(&bytes));
+    }
+
+    fn needed_libraries(bytes: &[u8]) -> Vec<&[u8]> {
+        let file = object::File::parse(bytes).unwrap();
+        let dynamic = file.section_by_name(".dynamic").unwrap().data().unwrap();
+        let dynstr = file.section_by_name(".dynstr").unwrap().data().unwrap();
+
+        dynamic
+            .chunks(8)
+            .map(|entry| (u32::from_le_bytes(entry[..4].try_into().unwrap()), u32::from_le_bytes(entry[4..].try_into().unwrap())))
+            .filter(|(tag, _)| *tag == elf::DT_NEEDED)
+            .map(|(_, offset)| {
+                let name = &dynstr[offset as usize..];
+                &name[..name.iter().position(|&byte| byte == 0).unwrap()]
+            })
+            .collect()
+    }
+}
@@ -1,18 +1,76 @@
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Token {
-    Identifier(String), // Keyword or Identifier
-    NumberLiteral(i64),
-    StringLiteral(String),
+use std::borrow::Cow;
+
+// Tokens borrow from the source, only literals with escapes own their text
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token<'a> {
+    Identifier(&'a str), // `r#fn` gives `fn`
+    IntegerLiteral(u64, Option<&'a str>), // value and type suffix
+    FloatLiteral(f64, Option<&'a str>),
+    StringLiteral(Cow<'a, str>),
+    ByteStringLiteral(Cow<'a, [u8]>),
+    CharLiteral(char),
     Plus, // +
     Minus, // -
     Star, // *
+    StarStar, // **
     Slash, // /
+    Percent, // %
+    Ampersand, // &
+    Pipe, // |
+    Caret, // ^
+    ShiftLeft, // <<
+    ShiftRight, // >>
+    Bang, // !
+    EqualEqual, // ==
+    NotEqual, // !=
+    Less, // <
+    LessEqual, // <=
+    Greater, // >
+    GreaterEqual, // >=
+    AndAnd, // &&
+    OrOr, // ||
     Equal, // =
+    PlusEqual, // +=
+    MinusEqual, // -=
+    StarEqual, // *=
+    SlashEqual, // /=
+    PercentEqual, // %=
+    AmpersandEqual, // &=
+    PipeEqual, // |=
+    CaretEqual, // ^=
+    ShiftLeftEqual, // <<=
+    ShiftRightEqual, // >>=
     LParen, // (
     RParen, // )
     LBrace, // {
     RBrace, // }
+    LBracket, // [
+    RBracket, // ]
     Comma, // ,
     Colon, // :
+    ColonColon, // ::
     Semicolon, // ;
+    Dot, // .
+    Arrow, // ->
+    FatArrow, // =>
+    Fn, // fn
+    Let, // let
+    Mut, // mut
+    If, // if
+    Else, // else
+    While, // while
+    Loop, // loop
+    Return, // return
+    True, // true
+    False, // false
+    Null, // null
+    Struct, // struct
+    Enum, // enum
+    Match, // match
+    Extern, // extern
+    Pub, // pub
+    Use, // use
+    Mod, // mod
+    Const, // const
+    DocComment(&'a str), // /// text, trivia attached to the following item
 }
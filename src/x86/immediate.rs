@@ -1,6 +1,6 @@
 
 #[allow(unused)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Immediate {
     U8(u8),
     U16(u16),
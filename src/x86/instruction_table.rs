@@ -1,8 +1,38 @@
+use super::immediate::Immediate;
+use super::operand::Operand;
+use super::register::{GPReg8, Register};
 
 #[allow(unused)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Kind {
     Mov,
+    MovByte, // stores the low byte
+    MovWord, // stores the low word
+    MovsxByte,
+    MovsxWord,
+    Lea,
+    Add,
+    Or,
+    And,
+    Sub,
+    Xor,
+    Cmp,
+    Imul,
+    Div,
+    Idiv,
+    Neg,
+    Inc,
+    Shl,
+    Sar,
+    Shr,
+    Cdq,
+    Push,
+    Pop,
+    Call,
+    Jmp,
+    Jcc,
+    Ret,
+    Ud2,
 }
 
 #[allow(unused)]
@@ -13,15 +43,53 @@ pub enum OpType {
     ModRm16_32,
     Reg8,
     Reg16_32,
+    RegInOpcode16_32, // +rd
+    Imm8, // sign-extended to the operand size
+    Imm16,
+    Imm16_32,
+    Rel32,
+    Cl, // implicit shift count
+}
+
+impl OpType {
+    pub fn matches(self, operand: Option<Operand>) -> bool {
+        match (self, operand) {
+            (OpType::NoOperand, None) => true,
+
+            (OpType::ModRm16_32, Some(Operand::Register(Register::GPR32(_)))) => true,
+            (OpType::ModRm16_32, Some(Operand::Memory(_))) => true,
+            (OpType::ModRm8, Some(Operand::Memory(_))) => true,
+            (OpType::Reg8, Some(Operand::Register(Register::GPR8(_)))) => true,
+            (OpType::Reg16_32, Some(Operand::Register(Register::GPR32(_)))) => true,
+            (OpType::RegInOpcode16_32, Some(Operand::Register(Register::GPR32(_)))) => true,
+
+            (OpType::Imm8, Some(Operand::Immediate(imm))) => fits_imm8(imm),
+            (OpType::Imm16, Some(Operand::Immediate(Immediate::U16(_)))) => true,
+            (OpType::Imm16_32, Some(Operand::Immediate(Immediate::U32(_)))) => true,
+            (OpType::Cl, Some(Operand::Register(Register::GPR8(GPReg8::CL)))) => true,
+
+            //
+            _ => false,
+        }
+    }
+}
+
+fn fits_imm8(immediate: Immediate) -> bool {
+    match immediate {
+        Immediate::U8(imm) => (imm as i8) >= 0,
+        Immediate::U16(imm) => (imm as i16) >= -128 && (imm as i16) <= 127,
+        Immediate::U32(imm) => (imm as i32) >= -128 && (imm as i32) <= 127,
+    }
 }
 
 #[allow(unused)]
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub struct InstrInfo {
     pub op: &'static [u8],
-    kind: Kind,
-    op_type1: OpType,
-    op_type2: OpType,
+    pub ext: Option<u8>, // /digit in the ModRM reg field
+    pub kind: Kind,
+    pub op_type1: OpType,
+    pub op_type2: OpType,
 }
 
 impl InstrInfo {
@@ -33,6 +101,23 @@ impl InstrInfo {
     ) -> Self {
         Self {
             op,
+            ext: None,
+            kind,
+            op_type1,
+            op_type2
+        }
+    }
+
+    const fn with_ext(
+        op: &'static [u8],
+        ext: u8,
+        kind: Kind,
+        op_type1: OpType,
+        op_type2: OpType,
+    ) -> Self {
+        Self {
+            op,
+            ext: Some(ext),
             kind,
             op_type1,
             op_type2
@@ -40,35 +125,86 @@ impl InstrInfo {
     }
 }
 
+// Shorter encodings come first, `find_instruction` picks the first match
 static INSTRUCTION_TABLE: &[InstrInfo] = &[
     InstrInfo::new(&[0x89], Kind::Mov, OpType::ModRm16_32, OpType::Reg16_32),
     InstrInfo::new(&[0x8B], Kind::Mov, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::new(&[0xB8], Kind::Mov, OpType::RegInOpcode16_32, OpType::Imm16_32),
+    InstrInfo::with_ext(&[0xC7], 0, Kind::Mov, OpType::ModRm16_32, OpType::Imm16_32),
+    InstrInfo::new(&[0x88], Kind::MovByte, OpType::ModRm8, OpType::Reg8),
+    InstrInfo::new(&[0x66, 0x89], Kind::MovWord, OpType::ModRm16_32, OpType::Reg16_32), // operand-size prefix
+    InstrInfo::new(&[0x0F, 0xBE], Kind::MovsxByte, OpType::Reg16_32, OpType::ModRm8),
+    InstrInfo::new(&[0x0F, 0xBF], Kind::MovsxWord, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::new(&[0x8D], Kind::Lea, OpType::Reg16_32, OpType::ModRm16_32),
+
+    InstrInfo::new(&[0x01], Kind::Add, OpType::ModRm16_32, OpType::Reg16_32),
+    InstrInfo::new(&[0x03], Kind::Add, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::with_ext(&[0x83], 0, Kind::Add, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0x81], 0, Kind::Add, OpType::ModRm16_32, OpType::Imm16_32),
+
+    InstrInfo::new(&[0x09], Kind::Or, OpType::ModRm16_32, OpType::Reg16_32),
+    InstrInfo::new(&[0x0B], Kind::Or, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::with_ext(&[0x83], 1, Kind::Or, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0x81], 1, Kind::Or, OpType::ModRm16_32, OpType::Imm16_32),
+
+    InstrInfo::new(&[0x21], Kind::And, OpType::ModRm16_32, OpType::Reg16_32),
+    InstrInfo::new(&[0x23], Kind::And, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::with_ext(&[0x83], 4, Kind::And, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0x81], 4, Kind::And, OpType::ModRm16_32, OpType::Imm16_32),
+
+    InstrInfo::new(&[0x29], Kind::Sub, OpType::ModRm16_32, OpType::Reg16_32),
+    InstrInfo::new(&[0x2B], Kind::Sub, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::with_ext(&[0x83], 5, Kind::Sub, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0x81], 5, Kind::Sub, OpType::ModRm16_32, OpType::Imm16_32),
+
+    InstrInfo::new(&[0x31], Kind::Xor, OpType::ModRm16_32, OpType::Reg16_32),
+    InstrInfo::new(&[0x33], Kind::Xor, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::with_ext(&[0x83], 6, Kind::Xor, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0x81], 6, Kind::Xor, OpType::ModRm16_32, OpType::Imm16_32),
+
+    InstrInfo::new(&[0x39], Kind::Cmp, OpType::ModRm16_32, OpType::Reg16_32),
+    InstrInfo::new(&[0x3B], Kind::Cmp, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::with_ext(&[0x83], 7, Kind::Cmp, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0x81], 7, Kind::Cmp, OpType::ModRm16_32, OpType::Imm16_32),
+
+    InstrInfo::new(&[0x0F, 0xAF], Kind::Imul, OpType::Reg16_32, OpType::ModRm16_32),
+    InstrInfo::with_ext(&[0xF7], 6, Kind::Div, OpType::ModRm16_32, OpType::NoOperand),
+    InstrInfo::with_ext(&[0xF7], 7, Kind::Idiv, OpType::ModRm16_32, OpType::NoOperand),
+    InstrInfo::with_ext(&[0xF7], 3, Kind::Neg, OpType::ModRm16_32, OpType::NoOperand),
+    InstrInfo::new(&[0x40], Kind::Inc, OpType::RegInOpcode16_32, OpType::NoOperand),
+    InstrInfo::with_ext(&[0xFF], 0, Kind::Inc, OpType::ModRm16_32, OpType::NoOperand),
+    InstrInfo::with_ext(&[0xC1], 4, Kind::Shl, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0xD3], 4, Kind::Shl, OpType::ModRm16_32, OpType::Cl),
+    InstrInfo::with_ext(&[0xC1], 7, Kind::Sar, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0xD3], 7, Kind::Sar, OpType::ModRm16_32, OpType::Cl),
+    InstrInfo::with_ext(&[0xC1], 5, Kind::Shr, OpType::ModRm16_32, OpType::Imm8),
+    InstrInfo::with_ext(&[0xD3], 5, Kind::Shr, OpType::ModRm16_32, OpType::Cl),
+    InstrInfo::new(&[0x99], Kind::Cdq, OpType::NoOperand, OpType::NoOperand),
+
+    InstrInfo::new(&[0x50], Kind::Push, OpType::RegInOpcode16_32, OpType::NoOperand),
+    InstrInfo::new(&[0x6A], Kind::Push, OpType::Imm8, OpType::NoOperand),
+    InstrInfo::new(&[0x68], Kind::Push, OpType::Imm16_32, OpType::NoOperand),
+    InstrInfo::with_ext(&[0xFF], 6, Kind::Push, OpType::ModRm16_32, OpType::NoOperand),
+    InstrInfo::new(&[0x58], Kind::Pop, OpType::RegInOpcode16_32, OpType::NoOperand),
+    InstrInfo::with_ext(&[0x8F], 0, Kind::Pop, OpType::ModRm16_32, OpType::NoOperand),
+
+    InstrInfo::new(&[0xE8], Kind::Call, OpType::Rel32, OpType::NoOperand),
+    InstrInfo::with_ext(&[0xFF], 2, Kind::Call, OpType::ModRm16_32, OpType::NoOperand),
+    InstrInfo::new(&[0xE9], Kind::Jmp, OpType::Rel32, OpType::NoOperand),
+    InstrInfo::with_ext(&[0xFF], 4, Kind::Jmp, OpType::ModRm16_32, OpType::NoOperand),
+    InstrInfo::new(&[0x0F, 0x80], Kind::Jcc, OpType::Rel32, OpType::NoOperand), // + condition code
+    InstrInfo::new(&[0xC3], Kind::Ret, OpType::NoOperand, OpType::NoOperand),
+    InstrInfo::new(&[0xC2], Kind::Ret, OpType::Imm16, OpType::NoOperand), // pops imm16 bytes of arguments
+    InstrInfo::new(&[0x0F, 0x0B], Kind::Ud2, OpType::NoOperand, OpType::NoOperand),
 ];
 
-pub fn query_instruction(
+pub fn find_instruction(
     kind: Kind,
-    op_type1: Option<OpType>,
-    op_type2: Option<OpType>
+    operand1: Option<Operand>,
+    operand2: Option<Operand>
 ) -> Option<InstrInfo> {
-    let instructions = INSTRUCTION_TABLE
+    INSTRUCTION_TABLE
         .iter()
-        .filter(Box::new(move |ii: &&InstrInfo| ii.kind == kind) as Box<dyn Fn(&&InstrInfo) -> bool>);
-    
-    let instructions = if let Some(op_type) = op_type1 {
-        instructions.filter(Box::new(move |ii: &&InstrInfo| ii.op_type1 == op_type) as Box<dyn Fn(&&InstrInfo) -> bool>)
-    } else {
-        instructions.filter(Box::new(move |_: &&InstrInfo| true) as Box<dyn Fn(&&InstrInfo) -> bool>)
-    };
-
-    let mut instructions = if let Some(op_type) = op_type2 {
-        instructions.filter(Box::new(move |ii: &&InstrInfo| ii.op_type2 == op_type) as Box<dyn Fn(&&InstrInfo) -> bool>)
-    } else {
-        instructions.filter(Box::new(move |_: &&InstrInfo| true) as Box<dyn Fn(&&InstrInfo) -> bool>)
-    };
-    
-    if let Some(result) = instructions.next() {
-        return Some(result.clone());
-    }
-
-    None
+        .find(|ii| ii.kind == kind && ii.op_type1.matches(operand1) && ii.op_type2.matches(operand2))
+        .copied()
 }
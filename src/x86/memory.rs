@@ -2,7 +2,7 @@ use super::{immediate::Immediate, register::Register};
 
 #[allow(unused)]
 #[repr(u8)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Scale {
     X2,
     X4,
@@ -10,13 +10,13 @@ pub enum Scale {
 }
 
 #[allow(unused)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Memory {
     Register(Register), // [eax]
     Immediate(Immediate), // [0x00000000]
     RegisterDisplacement(Register, Immediate), // [eax + 0x00000000]
     BaseIndex(Register, Register), // [eax + ebx]
-    IndexScale(Register, Scale), // [eax * 2]
+    IndexScale(Register, Scale, Immediate), // [eax * 2 + 0x12345678], the displacement is always there
     BaseIndexScale(Register, Register, Scale), // [eax + ebx * 4]
     BaseIndexScaleDisplacement(Register, Register, Scale, Immediate) // [eax + ebx * 8 + 0x12345678]
 }
@@ -6,4 +6,7 @@ pub mod sib;
 pub mod operand;
 pub mod instruction_table;
 pub mod utils;
+pub mod peephole;
+pub mod register_allocator;
+pub mod selection;
 
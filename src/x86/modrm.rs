@@ -145,6 +145,20 @@ pub fn gen_modrm(mod_: Mod, rm: Rm, reg: Reg) -> u8 {
     mod_value | src_value | dst_value
 }
 
+pub fn digit_to_reg(digit: u8) -> Reg {
+    match digit {
+        0 => Reg::R0,
+        1 => Reg::R1,
+        2 => Reg::R2,
+        3 => Reg::R3,
+        4 => Reg::R4,
+        5 => Reg::R5,
+        6 => Reg::R6,
+        7 => Reg::R7,
+        _ => panic!("invalid ModRM digit: {}", digit),
+    }
+}
+
 #[allow(unused)]
 pub fn register_to_rm(register: Register) -> Rm {
     match register {
@@ -1,10 +1,12 @@
 use super::register::Register;
 use super::memory::Memory;
+use super::immediate::Immediate;
 
 
 #[allow(unused)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Operand {
     Register(Register),
     Memory(Memory),
+    Immediate(Immediate),
 }
@@ -0,0 +1,195 @@
+use crate::codegen::{Instruction, Label};
+
+use super::immediate::Immediate;
+use super::instruction_table::{self, Kind};
+use super::operand::Operand;
+use super::register::Register;
+
+// Rewrites short instruction sequences until nothing changes
+pub fn optimize(instructions: &mut Vec<Instruction>) {
+    while run(instructions) {}
+}
+
+fn run(instructions: &mut Vec<Instruction>) -> bool {
+    let input = std::mem::take(instructions);
+    let mut changed = false;
+    let mut index = 0;
+
+    while index < input.len() {
+        let next = input.get(index + 1);
+
+        match (&input[index], next) {
+            // mov eax, eax
+            (Instruction::Op(Kind::Mov, Some(dst), Some(src)), _) if dst == src => {
+                index += 1;
+                changed = true;
+                continue;
+            },
+
+            // push x; pop x => nothing, push x; pop y => mov y, x
+            (Instruction::Op(Kind::Push, Some(src), None), Some(Instruction::Op(Kind::Pop, Some(dst), None))) => {
+                if src != dst {
+                    if instruction_table::find_instruction(Kind::Mov, Some(*dst), Some(*src)).is_none() {
+                        instructions.push(input[index].clone());
+                        index += 1;
+                        continue;
+                    }
+
+                    instructions.push(Instruction::Op(Kind::Mov, Some(*dst), Some(*src)));
+                }
+
+                index += 2;
+                changed = true;
+                continue;
+            },
+
+            (Instruction::Jmp(label) | Instruction::Jcc(_, label), _) if jumps_to_next(&input[index + 1..], *label) => {
+                index += 1;
+                changed = true;
+                continue;
+            },
+
+            // mov r, 0 => xor r, r
+            (Instruction::Op(Kind::Mov, Some(dst @ Operand::Register(Register::GPR32(_))), Some(src)), _)
+                if is_immediate(*src, 0) && !flags_live(&input[index + 1..]) => {
+                instructions.push(Instruction::Op(Kind::Xor, Some(*dst), Some(*dst)));
+                index += 1;
+                changed = true;
+                continue;
+            },
+
+            // add r, 1 => inc r
+            (Instruction::Op(Kind::Add, Some(dst @ Operand::Register(Register::GPR32(_))), Some(src)), _)
+                if is_immediate(*src, 1) && !flags_live(&input[index + 1..]) => {
+                instructions.push(Instruction::Op(Kind::Inc, Some(*dst), None));
+                index += 1;
+                changed = true;
+                continue;
+            },
+
+            _ => {},
+        }
+
+        instructions.push(input[index].clone());
+        index += 1;
+    }
+
+    changed
+}
+
+fn is_immediate(operand: Operand, value: i32) -> bool {
+    match operand {
+        Operand::Immediate(Immediate::U8(imm)) => imm as i8 as i32 == value,
+        Operand::Immediate(Immediate::U16(imm)) => imm as i16 as i32 == value,
+        Operand::Immediate(Immediate::U32(imm)) => imm as i32 == value,
+        _ => false,
+    }
+}
+
+// The label is bound right after the jump, possibly among other labels
+fn jumps_to_next(rest: &[Instruction], target: Label) -> bool {
+    for instruction in rest {
+        match instruction {
+            Instruction::Label(label) if *label == target => return true,
+            Instruction::Label(_) => {},
+            _ => return false,
+        }
+    }
+
+    false
+}
+
+// Whether the flags may be read before they are overwritten. Only the code
+// falling through is followed, anything unknown counts as a read
+fn flags_live(rest: &[Instruction]) -> bool {
+    for instruction in rest {
+        match instruction {
+            Instruction::Op(kind, _, operand2) => match kind {
+                Kind::Add | Kind::Or | Kind::And | Kind::Sub | Kind::Xor | Kind::Cmp |
+                Kind::Imul | Kind::Div | Kind::Idiv | Kind::Neg => return false,
+
+                // a count of zero leaves the flags unchanged
+                Kind::Shl | Kind::Sar | Kind::Shr => {
+                    if let Some(count @ Operand::Immediate(_)) = operand2 {
+                        if !is_immediate(*count, 0) {
+                            return false;
+                        }
+                    }
+                },
+
+                Kind::Mov | Kind::MovByte | Kind::MovWord | Kind::MovsxByte | Kind::MovsxWord | Kind::Lea |
+                Kind::Push | Kind::Pop | Kind::Cdq | Kind::Inc => {},
+
+                // the callee and the caller don't expect flags to be preserved
+                Kind::Call | Kind::Ret | Kind::Ud2 => return false,
+
+                Kind::Jmp | Kind::Jcc => return true,
+            },
+
+            Instruction::MovSymbolAddress(..) | Instruction::Label(_) | Instruction::Symbol(_) => {},
+            Instruction::CallSymbol(_) | Instruction::CallImport(_) => return false,
+            Instruction::Jmp(_) | Instruction::Jcc(..) | Instruction::JumpTable(..) | Instruction::Bytes(_) => return true,
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::{Codegen, Condition};
+    use crate::x86::peephole::*;
+    use crate::x86::register::GPReg32;
+    use crate::x86::memory::Memory;
+
+    fn reg(register: GPReg32) -> Operand {
+        Operand::Register(Register::GPR32(register))
+    }
+
+    fn imm(value: u32) -> Operand {
+        Operand::Immediate(Immediate::U32(value))
+    }
+
+    #[test]
+    fn test_peephole() {
+        let mut codegen = Codegen::new();
+        let next = codegen.create_label();
+        let other = codegen.create_label();
+        let local = Operand::Memory(Memory::RegisterDisplacement(
+            Register::GPR32(GPReg32::EBP),
+            Immediate::U32(-4i32 as u32)
+        ));
+
+        codegen.mov(reg(GPReg32::EAX), reg(GPReg32::EAX));
+        codegen.emit(Kind::Push, Some(reg(GPReg32::ECX)), None);
+        codegen.emit(Kind::Pop, Some(reg(GPReg32::ECX)), None);
+        codegen.emit(Kind::Push, Some(local), None);
+        codegen.emit(Kind::Pop, Some(reg(GPReg32::EDX)), None);
+        codegen.jmp(next);
+        codegen.bind_label(other);
+        codegen.bind_label(next);
+        codegen.mov(reg(GPReg32::EBX), imm(0));
+        codegen.emit(Kind::Add, Some(reg(GPReg32::EBX)), Some(imm(1)));
+        codegen.emit(Kind::Cmp, Some(reg(GPReg32::EBX)), Some(imm(0)));
+        codegen.emit(Kind::Add, Some(reg(GPReg32::EBX)), Some(imm(1)));
+        codegen.jcc(Condition::NotEqual, other);
+        codegen.emit(Kind::Ret, None, None);
+
+        codegen.peephole();
+
+        assert_eq!(
+            &[
+                Instruction::Op(Kind::Mov, Some(reg(GPReg32::EDX)), Some(local)),
+                Instruction::Label(other),
+                Instruction::Label(next),
+                Instruction::Op(Kind::Xor, Some(reg(GPReg32::EBX)), Some(reg(GPReg32::EBX))),
+                Instruction::Op(Kind::Inc, Some(reg(GPReg32::EBX)), None),
+                Instruction::Op(Kind::Cmp, Some(reg(GPReg32::EBX)), Some(imm(0))),
+                Instruction::Op(Kind::Add, Some(reg(GPReg32::EBX)), Some(imm(1))),
+                Instruction::Jcc(Condition::NotEqual, other),
+                Instruction::Op(Kind::Ret, None, None),
+            ],
+            codegen.instructions()
+        );
+    }
+}
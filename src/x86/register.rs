@@ -1,6 +1,6 @@
 
 #[allow(unused)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Register {
     GPR8(GPReg8),
     GPR16(GPReg16),
@@ -10,7 +10,7 @@ pub enum Register {
 
 #[allow(unused)]
 #[repr(u8)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum GPReg8 {
     AL = 0,
     CL,
@@ -24,7 +24,7 @@ pub enum GPReg8 {
 
 #[allow(unused)]
 #[repr(u8)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum GPReg16 {
     AX = 0,
     CX,
@@ -38,7 +38,7 @@ pub enum GPReg16 {
 
 #[allow(unused)]
 #[repr(u8)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum GPReg32 {
     EAX = 0,
     ECX,
@@ -52,7 +52,7 @@ pub enum GPReg32 {
 
 #[allow(unused)]
 #[repr(u8)]
-#[derive(PartialEq, Eq, Clone, Copy)]
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Segment {
     ES = 0,
     CS,
@@ -112,4 +112,4 @@ impl Register {
     const ESI: Self = Self::new(0b110);
     const EDI: Self = Self::new(0b111);
 }
-*/
\ No newline at end of file
+*/
\ No newline at end of file
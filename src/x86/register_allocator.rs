@@ -0,0 +1,530 @@
+use std::collections::HashSet;
+
+use crate::ir::layout::align_to;
+use crate::ir::{BinaryOp, BlockId, Function, InstKind, SlotId, Type, Value};
+use crate::ir::cfg::ControlFlowGraph;
+
+use super::immediate::Immediate;
+use super::memory::Memory;
+use super::operand::Operand;
+use super::register::{GPReg32, Register};
+
+// ESP and EBP are reserved for the stack frame
+pub const ALLOCATABLE: &[GPReg32] = &[
+    GPReg32::EAX,
+    GPReg32::ECX,
+    GPReg32::EDX,
+    GPReg32::EBX,
+    GPReg32::ESI,
+    GPReg32::EDI,
+];
+
+pub const CALLER_SAVED: &[GPReg32] = &[GPReg32::EAX, GPReg32::ECX, GPReg32::EDX];
+pub const CALLEE_SAVED: &[GPReg32] = &[GPReg32::EBX, GPReg32::ESI, GPReg32::EDI];
+
+// cdq + idiv use EDX:EAX
+const DIVISION_CLOBBERS: &[GPReg32] = &[GPReg32::EAX, GPReg32::EDX];
+const SHIFT_CLOBBERS: &[GPReg32] = &[GPReg32::ECX];
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Location {
+    Register(GPReg32),
+    Stack(i32), // [ebp + offset]
+}
+
+impl Location {
+    pub fn operand(self) -> Operand {
+        match self {
+            Location::Register(register) => Operand::Register(Register::GPR32(register)),
+            Location::Stack(offset) => Operand::Memory(Memory::RegisterDisplacement(
+                Register::GPR32(GPReg32::EBP),
+                Immediate::U32(offset as u32)
+            )),
+        }
+    }
+}
+
+pub struct Allocation {
+    locations: Vec<Option<Location>>,
+    stack_slots: Vec<i32>, // offsets from EBP
+    stack_slot_size: u32,
+    spill_slots: u32,
+    used_callee_saved: Vec<GPReg32>,
+}
+
+impl Allocation {
+    pub fn location(&self, value: Value) -> Location {
+        self.locations[value.0 as usize].expect("value has no location")
+    }
+
+    pub fn stack_slot_offset(&self, slot: SlotId) -> i32 {
+        self.stack_slots[slot.0 as usize]
+    }
+
+    // Bytes below EBP used by stack slots and spill slots
+    pub fn frame_size(&self) -> u32 {
+        self.stack_slot_size + self.spill_slots * 4
+    }
+
+    pub fn used_callee_saved(&self) -> &[GPReg32] {
+        &self.used_callee_saved
+    }
+}
+
+// [start, end] in instruction positions, see `Linearization`
+#[derive(Clone, Copy)]
+struct Interval {
+    value: Value,
+    start: u32,
+    end: u32,
+}
+
+impl Interval {
+    fn contains(&self, position: u32) -> bool {
+        self.start <= position && position <= self.end
+    }
+}
+
+// Instruction k uses its operands at 2k and defines its result at 2k + 1,
+// so an operand dying at an instruction may share a register with the result.
+// Parameters are defined at position 1, before the first instruction.
+struct Linearization {
+    order: Vec<BlockId>,
+    block_ranges: Vec<(u32, u32)>,
+}
+
+impl Linearization {
+    fn new(function: &Function, cfg: &ControlFlowGraph) -> Self {
+        let mut order = Vec::new();
+        let mut block_ranges = vec![(0, 0); function.blocks.len()];
+        let mut index = 1;
+
+        // reverse postorder puts every definition before its uses
+        for &block_id in cfg.reverse_postorder() {
+            let block = function.block(block_id);
+            let start = index * 2;
+
+            // instructions plus the terminator
+            index += block.insts.len() as u32 + 1;
+
+            block_ranges[block_id.0 as usize] = (start, index * 2 - 1);
+            order.push(block_id);
+        }
+
+        Self { order, block_ranges }
+    }
+
+    fn block_start(&self, block: BlockId) -> u32 {
+        self.block_ranges[block.0 as usize].0
+    }
+
+    fn block_end(&self, block: BlockId) -> u32 {
+        self.block_ranges[block.0 as usize].1
+    }
+
+    fn use_position(&self, block: BlockId, index: usize) -> u32 {
+        self.block_start(block) + index as u32 * 2
+    }
+}
+
+pub fn allocate(function: &Function) -> Allocation {
+    for &ty in &function.value_types {
+        if ty == Type::I64 {
+            panic!("64-bit values are not supported by the i386 backend");
+        }
+    }
+
+    let cfg = ControlFlowGraph::new(function);
+    let linearization = Linearization::new(function, &cfg);
+
+    let intervals = build_intervals(function, &linearization);
+    let clobbers = collect_clobbers(function, &linearization);
+
+    // Stack slots go right below EBP and the spill slots below them. EBP is
+    // only known to be 4-byte aligned, so no slot is aligned any further
+    let mut stack_slots = Vec::new();
+    let mut stack_slot_size = 0;
+
+    for slot in &function.stack_slots {
+        stack_slot_size = align_to(stack_slot_size + slot.size, slot.align.min(4));
+        stack_slots.push(-(stack_slot_size as i32));
+    }
+
+    let stack_slot_size = align_to(stack_slot_size, 4);
+
+    let mut allocator = LinearScan {
+        params: function.params.clone(),
+        locations: vec![None; function.value_types.len()],
+        active: Vec::new(),
+        spill_base: stack_slot_size,
+        spill_slots: 0,
+        used_callee_saved: Vec::new(),
+    };
+
+    for interval in intervals {
+        let forbidden: Vec<GPReg32> = clobbers
+            .iter()
+            .filter(|(position, _)| interval.contains(*position))
+            .flat_map(|(_, registers)| registers.iter().copied())
+            .collect();
+
+        allocator.allocate(interval, &forbidden);
+    }
+
+    Allocation {
+        locations: allocator.locations,
+        stack_slots,
+        stack_slot_size,
+        spill_slots: allocator.spill_slots,
+        used_callee_saved: allocator.used_callee_saved,
+    }
+}
+
+fn build_intervals(function: &Function, linearization: &Linearization) -> Vec<Interval> {
+    let live_out = compute_live_out(function, linearization);
+    let mut ranges: Vec<Option<(u32, u32)>> = vec![None; function.value_types.len()];
+
+    let mut extend = |value: Value, position: u32| {
+        let range = &mut ranges[value.0 as usize];
+
+        *range = Some(match *range {
+            Some((start, end)) => (start.min(position), end.max(position)),
+            None => (position, position),
+        });
+    };
+
+    for &param in &function.params {
+        extend(param, 1);
+    }
+
+    for &block_id in &linearization.order {
+        let block = function.block(block_id);
+
+        for (index, inst) in block.insts.iter().enumerate() {
+            let position = linearization.use_position(block_id, index);
+
+            if let Some(result) = inst.result {
+                extend(result, position + 1);
+            }
+
+            match &inst.kind {
+                // phi moves happen at the end of the incoming block
+                InstKind::Phi(incoming) => {
+                    for &(predecessor, value) in incoming {
+                        extend(value, linearization.block_end(predecessor));
+                        extend(inst.result.unwrap(), linearization.block_end(predecessor));
+                    }
+                },
+
+                _ => {
+                    for operand in inst.operands() {
+                        extend(operand, position);
+                    }
+                },
+            }
+        }
+
+        let terminator_position = linearization.use_position(block_id, block.insts.len());
+
+        for operand in block.terminator.as_ref().unwrap().operands() {
+            extend(operand, terminator_position);
+        }
+
+        for &value in &live_out[block_id.0 as usize] {
+            extend(value, linearization.block_end(block_id));
+        }
+
+        // live-out values which are not defined here flow through the block
+        for &value in &live_out[block_id.0 as usize] {
+            let defined_here = block.insts.iter().any(|inst| inst.result == Some(value));
+
+            if !defined_here {
+                extend(value, linearization.block_start(block_id));
+            }
+        }
+    }
+
+    let mut intervals: Vec<Interval> = ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(index, range)| range.map(|(start, end)| Interval {
+            value: Value(index as u32),
+            start,
+            end,
+        }))
+        .collect();
+
+    intervals.sort_by_key(|interval| (interval.start, interval.value));
+    intervals
+}
+
+fn compute_live_out(function: &Function, linearization: &Linearization) -> Vec<HashSet<Value>> {
+    let block_count = function.blocks.len();
+    let mut live_in: Vec<HashSet<Value>> = vec![HashSet::new(); block_count];
+    let mut live_out: Vec<HashSet<Value>> = vec![HashSet::new(); block_count];
+
+    let mut changed = true;
+
+    while changed {
+        changed = false;
+
+        for &block_id in linearization.order.iter().rev() {
+            let block = function.block(block_id);
+            let mut out = HashSet::new();
+
+            for successor in block.terminator.as_ref().unwrap().successors() {
+                let successor_block = function.block(successor);
+
+                for &value in &live_in[successor.0 as usize] {
+                    let is_phi_result = successor_block.insts
+                        .iter()
+                        .any(|inst| matches!(inst.kind, InstKind::Phi(_)) && inst.result == Some(value));
+
+                    if !is_phi_result {
+                        out.insert(value);
+                    }
+                }
+
+                for inst in &successor_block.insts {
+                    if let InstKind::Phi(incoming) = &inst.kind {
+                        for &(predecessor, value) in incoming {
+                            if predecessor == block_id {
+                                out.insert(value);
+                            }
+                        }
+                    }
+                }
+            }
+
+            let mut live = out.clone();
+
+            for operand in block.terminator.as_ref().unwrap().operands() {
+                live.insert(operand);
+            }
+
+            for inst in block.insts.iter().rev() {
+                if let Some(result) = inst.result {
+                    live.remove(&result);
+                }
+
+                if !matches!(inst.kind, InstKind::Phi(_)) {
+                    live.extend(inst.operands());
+                }
+            }
+
+            // phi results are defined at the top of the block, so they stay live-in
+            for inst in &block.insts {
+                if let (InstKind::Phi(_), Some(result)) = (&inst.kind, inst.result) {
+                    live.insert(result);
+                }
+            }
+
+            let index = block_id.0 as usize;
+
+            if live != live_in[index] || out != live_out[index] {
+                live_in[index] = live;
+                live_out[index] = out;
+                changed = true;
+            }
+        }
+    }
+
+    live_out
+}
+
+// Values defined by `const`, instruction selection uses them as immediates
+// where the encoding allows it
+fn constant_values(function: &Function) -> HashSet<Value> {
+    function.blocks
+        .iter()
+        .flat_map(|block| &block.insts)
+        .filter(|inst| matches!(inst.kind, InstKind::Const(_)))
+        .filter_map(|inst| inst.result)
+        .collect()
+}
+
+// Positions at which instructions overwrite fixed registers
+fn collect_clobbers(function: &Function, linearization: &Linearization) -> Vec<(u32, &'static [GPReg32])> {
+    let mut clobbers = Vec::new();
+    let constants = constant_values(function);
+
+    for &block_id in &linearization.order {
+        for (index, inst) in function.block(block_id).insts.iter().enumerate() {
+            let position = linearization.use_position(block_id, index);
+
+            let registers = match inst.kind {
+                InstKind::Binary(BinaryOp::Div | BinaryOp::Rem | BinaryOp::Udiv | BinaryOp::Urem, _, _) => DIVISION_CLOBBERS,
+                InstKind::Call(..) => CALLER_SAVED,
+
+                // The count goes through CL, and the result is computed in
+                // place so it must not live in ECX either
+                InstKind::Binary(BinaryOp::Shl | BinaryOp::Sar | BinaryOp::Shr, _, count)
+                    if !constants.contains(&count) => {
+                    clobbers.push((position + 1, SHIFT_CLOBBERS));
+                    SHIFT_CLOBBERS
+                },
+
+                _ => continue,
+            };
+
+            clobbers.push((position, registers));
+        }
+    }
+
+    clobbers
+}
+
+struct LinearScan {
+    params: Vec<Value>,
+    locations: Vec<Option<Location>>,
+    active: Vec<(Interval, GPReg32)>,
+    spill_base: u32, // bytes below EBP taken by stack slots
+    spill_slots: u32,
+    used_callee_saved: Vec<GPReg32>,
+}
+
+impl LinearScan {
+    fn allocate(&mut self, interval: Interval, forbidden: &[GPReg32]) {
+        self.active.retain(|(active, _)| active.end >= interval.start);
+
+        let free = ALLOCATABLE
+            .iter()
+            .copied()
+            .find(|register| {
+                !forbidden.contains(register) &&
+                    !self.active.iter().any(|(_, used)| used == register)
+            });
+
+        if let Some(register) = free {
+            self.assign(interval, register);
+            return;
+        }
+
+        // Spill whichever interval ends last, if it ends after this one
+        let candidate = self.active
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, register))| !forbidden.contains(register))
+            .max_by_key(|(_, (active, _))| active.end)
+            .map(|(index, (active, register))| (index, *active, *register));
+
+        match candidate {
+            Some((index, active, register)) if active.end > interval.end => {
+                self.active.remove(index);
+                self.spill(active);
+                self.assign(interval, register);
+            },
+
+            _ => self.spill(interval),
+        }
+    }
+
+    fn assign(&mut self, interval: Interval, register: GPReg32) {
+        if CALLEE_SAVED.contains(&register) && !self.used_callee_saved.contains(&register) {
+            self.used_callee_saved.push(register);
+        }
+
+        self.locations[interval.value.0 as usize] = Some(Location::Register(register));
+        self.active.push((interval, register));
+    }
+
+    fn spill(&mut self, interval: Interval) {
+        let param_index = self.params.iter().position(|param| *param == interval.value);
+
+        let location = match param_index {
+            // cdecl arguments already live above the return address
+            Some(index) => Location::Stack(8 + index as i32 * 4),
+            None => {
+                self.spill_slots += 1;
+                Location::Stack(-((self.spill_base + self.spill_slots * 4) as i32))
+            },
+        };
+
+        self.locations[interval.value.0 as usize] = Some(location);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::builder::FunctionBuilder;
+    use crate::x86::register_allocator::*;
+
+    #[test]
+    fn test_values_live_across_calls() {
+        let mut function = Function::new("main", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        builder.switch_to_block(entry);
+
+        let value = builder.iconst(Type::I32, 1);
+        let result = builder.call("main", Vec::new(), Type::I32).unwrap();
+        let sum = builder.binary(BinaryOp::Add, value, result);
+        builder.ret(Some(sum));
+
+        let allocation = allocate(&function);
+
+        match allocation.location(value) {
+            Location::Register(register) => assert!(!CALLER_SAVED.contains(&register)),
+            Location::Stack(_) => {},
+        }
+    }
+
+    #[test]
+    fn test_spills_to_stack() {
+        let mut function = Function::new("main", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let entry = builder.create_block();
+        builder.switch_to_block(entry);
+
+        let values: Vec<Value> = (0..8).map(|n| builder.iconst(Type::I32, n)).collect();
+        let mut sum = values[0];
+
+        for &value in &values[1..] {
+            sum = builder.binary(BinaryOp::Add, sum, value);
+        }
+
+        builder.ret(Some(sum));
+
+        let allocation = allocate(&function);
+
+        let spilled = values
+            .iter()
+            .filter(|value| matches!(allocation.location(**value), Location::Stack(_)))
+            .count();
+
+        assert_eq!(2, spilled);
+        assert_eq!(8, allocation.frame_size());
+    }
+
+    #[test]
+    fn test_spilled_params_use_incoming_slots() {
+        let mut function = Function::new("main", Type::I32);
+        let mut builder = FunctionBuilder::new(&mut function);
+
+        let params: Vec<Value> = (0..8).map(|_| builder.add_param(Type::I32)).collect();
+
+        let entry = builder.create_block();
+        builder.switch_to_block(entry);
+
+        let mut sum = params[0];
+
+        for &param in &params[1..] {
+            sum = builder.binary(BinaryOp::Add, sum, param);
+        }
+
+        builder.ret(Some(sum));
+
+        let allocation = allocate(&function);
+
+        for (index, &param) in params.iter().enumerate() {
+            match allocation.location(param) {
+                Location::Stack(offset) => assert_eq!(8 + index as i32 * 4, offset),
+                Location::Register(_) => {},
+            }
+        }
+
+        assert_eq!(0, allocation.frame_size());
+    }
+}
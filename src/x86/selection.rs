@@ -0,0 +1,1129 @@
+use std::collections::HashMap;
+
+use crate::codegen::{Codegen, Condition, Label};
+use crate::import_builder::import_symbol;
+use crate::ir::cfg::ControlFlowGraph;
+use crate::ir::{BinaryOp, BlockId, CallingConvention, DataId, Function, InstKind, Module, Terminator, Type, UnaryOp, Value};
+
+use super::immediate::Immediate;
+use super::instruction_table::Kind;
+use super::memory::{Memory, Scale};
+use super::operand::Operand;
+use super::register::{GPReg32, GPReg8, Register};
+use super::register_allocator::{self, Allocation, Location, ALLOCATABLE};
+
+pub const ENTRY_SYMBOL: &str = "_start";
+pub const DLL_ENTRY_SYMBOL: &str = "_DllMain";
+pub const TRAP_SYMBOL: &str = "__trap";
+
+// The exit code of a program aborted by a trap
+pub const TRAP_EXIT_CODE: i64 = 101;
+
+// Smaller switches compare the value with every case
+const JUMP_TABLE_MIN_CASES: usize = 4;
+
+// Imported by every program, the entry point exits through ExitProcess
+pub const IMPORTS: &[(&str, &str)] = &[
+    ("kernel32.dll", "ExitProcess"),
+    ("kernel32.dll", "GetStdHandle"),
+    ("kernel32.dll", "WriteFile"),
+];
+
+// Imports `extern` functions which do not name their library from the C
+// runtime
+pub const DEFAULT_LIBRARY: &str = "msvcrt.dll";
+
+pub fn data_symbol(data: DataId) -> String {
+    format!(".Ldata{}", data.0)
+}
+
+fn reg(register: GPReg32) -> Operand {
+    Operand::Register(Register::GPR32(register))
+}
+
+fn imm(value: i64) -> Operand {
+    Operand::Immediate(Immediate::U32(value as u32))
+}
+
+// [base + offset]
+fn displacement(base: GPReg32, offset: i32) -> Operand {
+    Operand::Memory(Memory::RegisterDisplacement(Register::GPR32(base), Immediate::U32(offset as u32)))
+}
+
+// Only EAX, ECX, EDX and EBX have an addressable low byte
+fn low_byte(register: GPReg32) -> Option<GPReg8> {
+    match register {
+        GPReg32::EAX => Some(GPReg8::AL),
+        GPReg32::ECX => Some(GPReg8::CL),
+        GPReg32::EDX => Some(GPReg8::DL),
+        GPReg32::EBX => Some(GPReg8::BL),
+        _ => None,
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum EntryPoint {
+    Executable, // calls `main`
+    Dll, // DllMain stub
+    None, // the code is linked with something which brings its own
+}
+
+pub fn compile_module(module: &Module, entry_point: EntryPoint) -> Codegen {
+    let mut codegen = select_module(module, entry_point);
+    codegen.finish();
+    codegen
+}
+
+// The instructions before they are encoded
+fn select_module(module: &Module, entry_point: EntryPoint) -> Codegen {
+    let mut codegen = Codegen::new();
+
+    // Linked images call external functions through their import slots,
+    // objects leave the calls to the linker
+    let imports = entry_point != EntryPoint::None;
+
+    for function in &module.functions {
+        let allocation = register_allocator::allocate(function);
+        FunctionSelector::new(&mut codegen, module, function, &allocation, imports).emit();
+    }
+
+    match entry_point {
+        EntryPoint::Executable => {
+            if let Some(main) = module.function("main") {
+                emit_entry_point(&mut codegen, main);
+            }
+        },
+
+        EntryPoint::Dll => emit_dll_entry_point(&mut codegen),
+        EntryPoint::None => {},
+    }
+
+    let traps = module.functions
+        .iter()
+        .flat_map(|function| &function.blocks)
+        .any(|block| matches!(block.terminator, Some(Terminator::Trap(_))));
+
+    if traps && imports {
+        emit_trap_handler(&mut codegen);
+    }
+
+    codegen.peephole();
+    codegen
+}
+
+// Calls `main` and passes its result to ExitProcess
+fn emit_entry_point(codegen: &mut Codegen, main: &Function) {
+    codegen.define_symbol(ENTRY_SYMBOL);
+    codegen.call_symbol(&main.name);
+
+    if main.return_type == Type::Void {
+        codegen.mov(reg(GPReg32::EAX), imm(0));
+    }
+
+    codegen.emit(Kind::Push, Some(reg(GPReg32::EAX)), None);
+    codegen.call_import(&import_symbol("ExitProcess"));
+    codegen.emit(Kind::Ud2, None, None);
+}
+
+// BOOL WINAPI DllMain(HINSTANCE, DWORD reason, LPVOID), returns TRUE for
+// every notification. stdcall, so the callee pops the three arguments
+fn emit_dll_entry_point(codegen: &mut Codegen) {
+    codegen.define_symbol(DLL_ENTRY_SYMBOL);
+    codegen.mov(reg(GPReg32::EAX), imm(1));
+    codegen.emit(Kind::Ret, Some(Operand::Immediate(Immediate::U16(12))), None);
+}
+
+// Writes the message to stderr and exits with TRAP_EXIT_CODE, called with
+// the message and its length on the stack:
+//
+//     push ebp
+//     mov ebp, esp
+//     sub esp, 4                  ; the number of bytes written
+//     push -12                    ; STD_ERROR_HANDLE
+//     call [GetStdHandle]
+//     push 0
+//     lea ecx, [ebp - 4]
+//     push ecx
+//     push [ebp + 12]
+//     push [ebp + 8]
+//     push eax
+//     call [WriteFile]
+//     push 101
+//     call [ExitProcess]
+fn emit_trap_handler(codegen: &mut Codegen) {
+    codegen.define_symbol(TRAP_SYMBOL);
+    codegen.emit(Kind::Push, Some(reg(GPReg32::EBP)), None);
+    codegen.mov(reg(GPReg32::EBP), reg(GPReg32::ESP));
+    codegen.emit(Kind::Sub, Some(reg(GPReg32::ESP)), Some(imm(4)));
+
+    codegen.emit(Kind::Push, Some(imm(-12)), None);
+    codegen.call_import(&import_symbol("GetStdHandle"));
+
+    codegen.emit(Kind::Push, Some(imm(0)), None);
+    codegen.emit(Kind::Lea, Some(reg(GPReg32::ECX)), Some(displacement(GPReg32::EBP, -4)));
+    codegen.emit(Kind::Push, Some(reg(GPReg32::ECX)), None);
+    codegen.emit(Kind::Push, Some(displacement(GPReg32::EBP, 12)), None);
+    codegen.emit(Kind::Push, Some(displacement(GPReg32::EBP, 8)), None);
+    codegen.emit(Kind::Push, Some(reg(GPReg32::EAX)), None);
+    codegen.call_import(&import_symbol("WriteFile"));
+
+    codegen.emit(Kind::Push, Some(imm(TRAP_EXIT_CODE)), None);
+    codegen.call_import(&import_symbol("ExitProcess"));
+    codegen.emit(Kind::Ud2, None, None);
+}
+
+struct FunctionSelector<'a> {
+    codegen: &'a mut Codegen,
+    module: &'a Module,
+    function: &'a Function,
+    allocation: &'a Allocation,
+    block_labels: Vec<Label>,
+    constants: HashMap<Value, i64>,
+    imports: bool,
+}
+
+impl<'a> FunctionSelector<'a> {
+    fn new(
+        codegen: &'a mut Codegen,
+        module: &'a Module,
+        function: &'a Function,
+        allocation: &'a Allocation,
+        imports: bool
+    ) -> Self {
+        let block_labels = function.blocks
+            .iter()
+            .map(|_| codegen.create_label())
+            .collect();
+
+        let mut constants = HashMap::new();
+
+        for block in &function.blocks {
+            for inst in &block.insts {
+                if let (InstKind::Const(value), Some(result)) = (&inst.kind, inst.result) {
+                    constants.insert(result, *value);
+                }
+            }
+        }
+
+        Self {
+            codegen,
+            module,
+            function,
+            allocation,
+            block_labels,
+            constants,
+            imports,
+        }
+    }
+
+    fn emit(&mut self) {
+        let cfg = ControlFlowGraph::new(self.function);
+
+        self.emit_prologue();
+
+        for &block_id in cfg.reverse_postorder() {
+            self.codegen.bind_label(self.block_labels[block_id.0 as usize]);
+            self.emit_block(block_id);
+        }
+    }
+
+    fn emit_prologue(&mut self) {
+        self.codegen.define_symbol(&self.function.name);
+
+        self.codegen.emit(Kind::Push, Some(reg(GPReg32::EBP)), None);
+        self.codegen.mov(reg(GPReg32::EBP), reg(GPReg32::ESP));
+
+        if self.allocation.frame_size() > 0 {
+            self.codegen.emit(Kind::Sub, Some(reg(GPReg32::ESP)), Some(imm(self.allocation.frame_size() as i64)));
+        }
+
+        for &register in self.allocation.used_callee_saved() {
+            self.codegen.emit(Kind::Push, Some(reg(register)), None);
+        }
+
+        for (index, &param) in self.function.params.iter().enumerate() {
+            if let Location::Register(_) = self.allocation.location(param) {
+                let incoming = Location::Stack(8 + index as i32 * 4).operand();
+                self.codegen.mov(self.operand(param), incoming);
+            }
+        }
+    }
+
+    fn emit_epilogue(&mut self) {
+        for &register in self.allocation.used_callee_saved().iter().rev() {
+            self.codegen.emit(Kind::Pop, Some(reg(register)), None);
+        }
+
+        self.codegen.mov(reg(GPReg32::ESP), reg(GPReg32::EBP));
+        self.codegen.emit(Kind::Pop, Some(reg(GPReg32::EBP)), None);
+        self.codegen.emit(Kind::Ret, None, None);
+    }
+
+    fn operand(&self, value: Value) -> Operand {
+        self.allocation.location(value).operand()
+    }
+
+    fn emit_block(&mut self, block_id: BlockId) {
+        let block = self.function.block(block_id);
+
+        for inst in &block.insts {
+            let result = inst.result.map(|result| self.operand(result));
+
+            match &inst.kind {
+                InstKind::Const(value) => self.codegen.mov(result.unwrap(), imm(*value)),
+                InstKind::Copy(value) | InstKind::Bitcast(value) => self.move_value(result.unwrap(), self.operand(*value)),
+
+                InstKind::DataAddress(data) => {
+                    self.codegen.mov_symbol_address(result.unwrap(), &data_symbol(*data));
+                },
+
+                InstKind::StackAddress(slot) => {
+                    let address = displacement(GPReg32::EBP, self.allocation.stack_slot_offset(*slot));
+                    self.emit_lea(result.unwrap(), address);
+                },
+
+                InstKind::Load(address, offset) => {
+                    self.emit_load(inst.ty, result.unwrap(), self.operand(*address), *offset);
+                },
+
+                InstKind::Store(address, offset, value) => {
+                    self.emit_store(inst.ty, self.operand(*address), *offset, self.operand(*value));
+                },
+
+                InstKind::ElementAddress(base, index, scale) => {
+                    self.emit_element_address(result.unwrap(), self.operand(*base), self.operand(*index), *scale);
+                },
+
+                InstKind::Unary(UnaryOp::Neg, value) => {
+                    self.move_value(result.unwrap(), self.operand(*value));
+                    self.codegen.emit(Kind::Neg, result, None);
+                    self.wrap(inst.ty, result.unwrap());
+                },
+
+                // The quotient is left in EAX, the remainder in EDX
+                InstKind::Binary(op @ (BinaryOp::Div | BinaryOp::Rem), lhs, rhs) => {
+                    self.move_value(reg(GPReg32::EAX), self.operand(*lhs));
+                    self.codegen.emit(Kind::Cdq, None, None);
+                    self.codegen.emit(Kind::Idiv, Some(self.operand(*rhs)), None);
+
+                    let quotient_or_remainder = if *op == BinaryOp::Div { GPReg32::EAX } else { GPReg32::EDX };
+                    self.move_value(result.unwrap(), reg(quotient_or_remainder));
+                },
+
+                // EDX is zeroed instead of sign-extended. Narrow values are
+                // zero-extended for the division, in a scratch register for
+                // the divisor, and the result is sign-extended back
+                InstKind::Binary(op @ (BinaryOp::Udiv | BinaryOp::Urem), lhs, rhs) => {
+                    let quotient_or_remainder = if *op == BinaryOp::Udiv { GPReg32::EAX } else { GPReg32::EDX };
+
+                    self.move_value(reg(GPReg32::EAX), self.operand(*lhs));
+                    self.codegen.emit(Kind::Xor, Some(reg(GPReg32::EDX)), Some(reg(GPReg32::EDX)));
+
+                    match narrow_mask(inst.ty) {
+                        Some(mask) => {
+                            let scratch = scratch_register(&[reg(GPReg32::EAX), reg(GPReg32::EDX)]);
+
+                            self.codegen.emit(Kind::And, Some(reg(GPReg32::EAX)), Some(imm(mask)));
+                            self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+                            self.codegen.mov(reg(scratch), self.operand(*rhs));
+                            self.codegen.emit(Kind::And, Some(reg(scratch)), Some(imm(mask)));
+                            self.codegen.emit(Kind::Div, Some(reg(scratch)), None);
+                            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+                            self.sign_extend(inst.ty, reg(quotient_or_remainder));
+                        },
+
+                        None => self.codegen.emit(Kind::Div, Some(self.operand(*rhs)), None),
+                    }
+
+                    self.move_value(result.unwrap(), reg(quotient_or_remainder));
+                },
+
+                InstKind::Binary(
+                    op @ (BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge
+                        | BinaryOp::Ult | BinaryOp::Ule | BinaryOp::Ugt | BinaryOp::Uge),
+                    lhs,
+                    rhs
+                ) => {
+                    self.emit_compare(*op, result.unwrap(), self.operand(*lhs), self.operand(*rhs));
+                },
+
+                InstKind::Binary(op @ (BinaryOp::Shl | BinaryOp::Sar | BinaryOp::Shr), lhs, rhs) => {
+                    self.emit_shift(*op, inst.ty, result.unwrap(), self.operand(*lhs), *rhs);
+                },
+
+                InstKind::Binary(op, lhs, rhs) => {
+                    self.emit_binary(*op, result.unwrap(), self.operand(*lhs), self.operand(*rhs));
+
+                    // Bitwise operations keep the sign extension
+                    if matches!(op, BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul) {
+                        self.wrap(inst.ty, result.unwrap());
+                    }
+                },
+
+                // cdecl, which is what C compilers expect from exported and
+                // external functions: arguments are pushed right to left and
+                // the caller pops them, the result is returned in EAX.
+                // stdcall externals pop their own arguments
+                InstKind::Call(name, args) => {
+                    for &arg in args.iter().rev() {
+                        self.codegen.emit(Kind::Push, Some(self.operand(arg)), None);
+                    }
+
+                    let external = self.module.external(name);
+
+                    if external.is_some() && self.imports {
+                        self.codegen.call_import(&import_symbol(name));
+                    } else {
+                        self.codegen.call_symbol(name);
+                    }
+
+                    let callee_pops = external
+                        .is_some_and(|external| external.convention == CallingConvention::Stdcall);
+
+                    if !args.is_empty() && !callee_pops {
+                        self.codegen.emit(Kind::Add, Some(reg(GPReg32::ESP)), Some(imm(args.len() as i64 * 4)));
+                    }
+
+                    if let Some(result) = result {
+                        self.move_value(result, reg(GPReg32::EAX));
+                    }
+                },
+
+                // resolved by the predecessors, see `emit_phi_moves`
+                InstKind::Phi(_) => {},
+            }
+        }
+
+        let terminator = block.terminator.as_ref().unwrap();
+
+        self.emit_phi_moves(block_id, terminator);
+
+        match terminator {
+            Terminator::Return(value) => {
+                if let Some(value) = value {
+                    self.move_value(reg(GPReg32::EAX), self.operand(*value));
+                }
+
+                self.emit_epilogue();
+            },
+
+            Terminator::Jump(target) => {
+                self.codegen.jmp(self.block_labels[target.0 as usize]);
+            },
+
+            Terminator::Branch(condition, then_block, else_block) => {
+                self.codegen.emit(Kind::Cmp, Some(self.operand(*condition)), Some(imm(0)));
+                self.codegen.jcc(Condition::NotEqual, self.block_labels[then_block.0 as usize]);
+                self.codegen.jmp(self.block_labels[else_block.0 as usize]);
+            },
+
+            // The jump table is indexed by a register, a spilled value is
+            // compared instead. The bounds check is unsigned so negative
+            // values go to the default too
+            Terminator::Switch(value, cases, default) => {
+                let value = self.operand(*value);
+                let default = self.block_labels[default.0 as usize];
+                let cases: Vec<Label> = cases.iter().map(|case| self.block_labels[case.0 as usize]).collect();
+
+                match value {
+                    Operand::Register(Register::GPR32(index)) if cases.len() >= JUMP_TABLE_MIN_CASES => {
+                        self.codegen.emit(Kind::Cmp, Some(value), Some(imm(cases.len() as i64)));
+                        self.codegen.jcc(Condition::AboveOrEqual, default);
+                        self.codegen.jump_table(index, cases);
+                    },
+
+                    _ => {
+                        for (index, case) in cases.into_iter().enumerate() {
+                            self.codegen.emit(Kind::Cmp, Some(value), Some(imm(index as i64)));
+                            self.codegen.jcc(Condition::Equal, case);
+                        }
+
+                        self.codegen.jmp(default);
+                    },
+                }
+            },
+
+            Terminator::Unreachable => self.codegen.emit(Kind::Ud2, None, None),
+
+            // Objects have no imports to report the message with, so they
+            // stop at the ud2
+            Terminator::Trap(message) => {
+                if self.imports {
+                    let length = self.module.data[message.0 as usize].bytes.len();
+
+                    self.codegen.emit(Kind::Push, Some(imm(length as i64)), None);
+                    self.codegen.mov_symbol_address(reg(GPReg32::EAX), &data_symbol(*message));
+                    self.codegen.emit(Kind::Push, Some(reg(GPReg32::EAX)), None);
+                    self.codegen.call_symbol(TRAP_SYMBOL);
+                }
+
+                self.codegen.emit(Kind::Ud2, None, None);
+            },
+        }
+    }
+
+    // dst = lhs op rhs, where only the destination is written
+    fn emit_binary(&mut self, op: BinaryOp, dst: Operand, lhs: Operand, rhs: Operand) {
+        if let Operand::Memory(_) = dst {
+            let scratch = scratch_register(&[lhs, rhs]);
+
+            self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+            self.emit_binary(op, reg(scratch), lhs, rhs);
+            self.codegen.mov(dst, reg(scratch));
+            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+
+            return;
+        }
+
+        let (lhs, rhs) = if rhs == dst && lhs != dst {
+            match op {
+                BinaryOp::Add | BinaryOp::Mul | BinaryOp::And | BinaryOp::Or | BinaryOp::Xor => (rhs, lhs),
+
+                // dst = -rhs + lhs
+                BinaryOp::Sub => {
+                    self.codegen.emit(Kind::Neg, Some(dst), None);
+                    self.codegen.emit(Kind::Add, Some(dst), Some(lhs));
+                    return;
+                },
+
+                _ => unreachable!(),
+            }
+        } else {
+            (lhs, rhs)
+        };
+
+        self.move_value(dst, lhs);
+
+        let kind = match op {
+            BinaryOp::Add => Kind::Add,
+            BinaryOp::Sub => Kind::Sub,
+            BinaryOp::Mul => Kind::Imul,
+            BinaryOp::And => Kind::And,
+            BinaryOp::Or => Kind::Or,
+            BinaryOp::Xor => Kind::Xor,
+            _ => unreachable!(),
+        };
+
+        self.codegen.emit(kind, Some(dst), Some(rhs));
+    }
+
+    // dst = lhs op rhs ? 1 : 0, without setcc so that any register can hold
+    // the result:
+    //
+    //     cmp lhs, rhs
+    //     mov dst, 1
+    //     jcc done
+    //     mov dst, 0
+    // done:
+    //
+    // Sign extension keeps the unsigned order of narrow values, so they are
+    // compared as 32-bit values
+    fn emit_compare(&mut self, op: BinaryOp, dst: Operand, lhs: Operand, rhs: Operand) {
+        let condition = match op {
+            BinaryOp::Eq => Condition::Equal,
+            BinaryOp::Ne => Condition::NotEqual,
+            BinaryOp::Lt => Condition::Less,
+            BinaryOp::Le => Condition::LessOrEqual,
+            BinaryOp::Gt => Condition::Greater,
+            BinaryOp::Ge => Condition::GreaterOrEqual,
+            BinaryOp::Ult => Condition::Below,
+            BinaryOp::Ule => Condition::BelowOrEqual,
+            BinaryOp::Ugt => Condition::Above,
+            BinaryOp::Uge => Condition::AboveOrEqual,
+            _ => unreachable!(),
+        };
+
+        if let (Operand::Memory(_), Operand::Memory(_)) = (lhs, rhs) {
+            // pop leaves the flags alone
+            let scratch = scratch_register(&[lhs, rhs]);
+
+            self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+            self.codegen.mov(reg(scratch), lhs);
+            self.codegen.emit(Kind::Cmp, Some(reg(scratch)), Some(rhs));
+            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+        } else {
+            self.codegen.emit(Kind::Cmp, Some(lhs), Some(rhs));
+        }
+
+        let done = self.codegen.create_label();
+
+        self.codegen.mov(dst, imm(1));
+        self.codegen.jcc(condition, done);
+        self.codegen.mov(dst, imm(0));
+        self.codegen.bind_label(done);
+    }
+
+    // Constant counts are encoded as imm8, others are loaded into CL. The
+    // allocator keeps the operands and the result out of ECX in that case
+    fn emit_shift(&mut self, op: BinaryOp, ty: Type, dst: Operand, lhs: Operand, count: Value) {
+        let kind = match op {
+            BinaryOp::Shl => Kind::Shl,
+            BinaryOp::Sar => Kind::Sar,
+            BinaryOp::Shr => Kind::Shr,
+            _ => unreachable!(),
+        };
+
+        let count = match self.constants.get(&count) {
+            Some(value) => imm(value & 31),
+
+            None => {
+                self.move_value(reg(GPReg32::ECX), self.operand(count));
+                Operand::Register(Register::GPR8(GPReg8::CL))
+            },
+        };
+
+        if let Operand::Memory(_) = dst {
+            let scratch = scratch_register(&[lhs, reg(GPReg32::ECX)]);
+
+            self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+            self.move_value(reg(scratch), lhs);
+            self.shift_in_place(kind, ty, reg(scratch), count);
+            self.codegen.mov(dst, reg(scratch));
+            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+
+            return;
+        }
+
+        self.move_value(dst, lhs);
+        self.shift_in_place(kind, ty, dst, count);
+    }
+
+    // A logical shift of a narrow value would shift in the copies of its
+    // sign bit, so the value is zero-extended first and the result is
+    // sign-extended back
+    fn shift_in_place(&mut self, kind: Kind, ty: Type, target: Operand, count: Operand) {
+        match narrow_mask(ty) {
+            Some(mask) if kind == Kind::Shr => {
+                self.codegen.emit(Kind::And, Some(target), Some(imm(mask)));
+                self.codegen.emit(kind, Some(target), Some(count));
+                self.sign_extend(ty, target);
+            },
+
+            _ => {
+                self.codegen.emit(kind, Some(target), Some(count));
+
+                if kind == Kind::Shl {
+                    self.wrap(ty, target);
+                }
+            },
+        }
+    }
+
+    // Arithmetic on narrow values carries into the upper bits, which are
+    // dropped like in `Type::truncate`
+    fn wrap(&mut self, ty: Type, target: Operand) {
+        if narrow_mask(ty).is_some() {
+            self.sign_extend(ty, target);
+        }
+    }
+
+    // Sign-extends the low bits of a narrow value to the whole register
+    fn sign_extend(&mut self, ty: Type, target: Operand) {
+        let shift = imm(32 - ty.bits() as i64);
+
+        self.codegen.emit(Kind::Shl, Some(target), Some(shift));
+        self.codegen.emit(Kind::Sar, Some(target), Some(shift));
+    }
+
+    fn emit_lea(&mut self, dst: Operand, address: Operand) {
+        if let Operand::Memory(_) = dst {
+            let scratch = scratch_register(&[]);
+
+            self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+            self.codegen.emit(Kind::Lea, Some(reg(scratch)), Some(address));
+            self.codegen.mov(dst, reg(scratch));
+            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+
+            return;
+        }
+
+        self.codegen.emit(Kind::Lea, Some(dst), Some(address));
+    }
+
+    // dst = base + index * scale, with scratch registers for a spilled base
+    // or index
+    fn emit_element_address(&mut self, dst: Operand, base: Operand, index: Operand, scale: u32) {
+        let mut saved = Vec::new();
+
+        let base = match base {
+            Operand::Register(Register::GPR32(base)) => base,
+
+            _ => {
+                let scratch = scratch_register(&[dst, index]);
+
+                self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+                self.codegen.mov(reg(scratch), base);
+                saved.push(scratch);
+                scratch
+            },
+        };
+
+        let index = match index {
+            Operand::Register(Register::GPR32(index)) => index,
+
+            _ => {
+                let scratch = scratch_register(&[dst, reg(base), index]);
+
+                self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+                self.codegen.mov(reg(scratch), index);
+                saved.push(scratch);
+                scratch
+            },
+        };
+
+        let address = match scale {
+            1 => Memory::BaseIndex(Register::GPR32(base), Register::GPR32(index)),
+            2 => Memory::BaseIndexScale(Register::GPR32(base), Register::GPR32(index), Scale::X2),
+            4 => Memory::BaseIndexScale(Register::GPR32(base), Register::GPR32(index), Scale::X4),
+            8 => Memory::BaseIndexScale(Register::GPR32(base), Register::GPR32(index), Scale::X8),
+            _ => unreachable!(),
+        };
+
+        // The scratch registers are restored after the result is stored
+        if let Operand::Memory(_) = dst {
+            let scratch = scratch_register(&[reg(base), reg(index)]);
+
+            self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+            self.codegen.emit(Kind::Lea, Some(reg(scratch)), Some(Operand::Memory(address)));
+            self.codegen.mov(dst, reg(scratch));
+            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+        } else {
+            self.codegen.emit(Kind::Lea, Some(dst), Some(Operand::Memory(address)));
+        }
+
+        for &scratch in saved.iter().rev() {
+            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+        }
+    }
+
+    // dst = [address + offset], narrow values are sign-extended to 32 bits
+    // like every value in a register
+    fn emit_load(&mut self, ty: Type, dst: Operand, address: Operand, offset: i32) {
+        let Operand::Register(Register::GPR32(dst_register)) = dst else {
+            let scratch = scratch_register(&[address]);
+
+            self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+            self.emit_load(ty, reg(scratch), address, offset);
+            self.codegen.mov(dst, reg(scratch));
+            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+
+            return;
+        };
+
+        // A spilled address is loaded into the destination first
+        let base = match address {
+            Operand::Register(Register::GPR32(base)) => base,
+
+            _ => {
+                self.codegen.mov(dst, address);
+                dst_register
+            },
+        };
+
+        let kind = match ty {
+            Type::I8 => Kind::MovsxByte,
+            Type::I16 => Kind::MovsxWord,
+            _ => Kind::Mov,
+        };
+
+        self.codegen.emit(kind, Some(dst), Some(displacement(base, offset)));
+    }
+
+    // [address + offset] = value, with scratch registers for a spilled
+    // address and for a byte taken from a register without a low byte
+    fn emit_store(&mut self, ty: Type, address: Operand, offset: i32, value: Operand) {
+        let mut saved = Vec::new();
+
+        let base = match address {
+            Operand::Register(Register::GPR32(base)) => base,
+
+            _ => {
+                let scratch = scratch_register(&[value]);
+
+                self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+                self.codegen.mov(reg(scratch), address);
+                saved.push(scratch);
+                scratch
+            },
+        };
+
+        let source = match value {
+            Operand::Register(Register::GPR32(source)) if ty != Type::I8 || low_byte(source).is_some() => source,
+
+            // At most two registers are taken, so the scratch register is
+            // one of EAX, ECX and EDX
+            _ => {
+                let scratch = scratch_register(&[reg(base), value]);
+
+                self.codegen.emit(Kind::Push, Some(reg(scratch)), None);
+                self.codegen.mov(reg(scratch), value);
+                saved.push(scratch);
+                scratch
+            },
+        };
+
+        let (kind, source) = match ty {
+            Type::I8 => (Kind::MovByte, Operand::Register(Register::GPR8(low_byte(source).unwrap()))),
+            Type::I16 => (Kind::MovWord, reg(source)),
+            _ => (Kind::Mov, reg(source)),
+        };
+
+        self.codegen.emit(kind, Some(displacement(base, offset)), Some(source));
+
+        for &scratch in saved.iter().rev() {
+            self.codegen.emit(Kind::Pop, Some(reg(scratch)), None);
+        }
+    }
+
+    fn move_value(&mut self, dst: Operand, src: Operand) {
+        if dst == src {
+            return;
+        }
+
+        if let (Operand::Memory(_), Operand::Memory(_)) = (dst, src) {
+            self.codegen.emit(Kind::Push, Some(src), None);
+            self.codegen.emit(Kind::Pop, Some(dst), None);
+        } else {
+            self.codegen.mov(dst, src);
+        }
+    }
+
+    // Phi results are written at the end of each predecessor as a parallel move
+    fn emit_phi_moves(&mut self, block_id: BlockId, terminator: &Terminator) {
+        let successors = terminator.successors();
+        let mut moves = Vec::new();
+
+        for &successor in &successors {
+            for inst in &self.function.block(successor).insts {
+                if let InstKind::Phi(incoming) = &inst.kind {
+                    for &(predecessor, value) in incoming {
+                        if predecessor == block_id {
+                            moves.push((self.operand(inst.result.unwrap()), self.operand(value)));
+                        }
+                    }
+                }
+            }
+        }
+
+        moves.retain(|(dst, src)| dst != src);
+
+        if moves.is_empty() {
+            return;
+        }
+
+        assert!(successors.len() == 1, "critical edges must be split before instruction selection");
+
+        if moves.len() == 1 {
+            self.move_value(moves[0].0, moves[0].1);
+            return;
+        }
+
+        for &(_, src) in &moves {
+            self.codegen.emit(Kind::Push, Some(src), None);
+        }
+
+        for &(dst, _) in moves.iter().rev() {
+            self.codegen.emit(Kind::Pop, Some(dst), None);
+        }
+    }
+}
+
+// The bits of I8 and I16 values, which are sign-extended in registers
+fn narrow_mask(ty: Type) -> Option<i64> {
+    match ty {
+        Type::I8 => Some(0xFF),
+        Type::I16 => Some(0xFFFF),
+        _ => None,
+    }
+}
+
+fn scratch_register(used: &[Operand]) -> GPReg32 {
+    ALLOCATABLE
+        .iter()
+        .copied()
+        .find(|register| !used.contains(&reg(*register)))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::Instruction;
+    use crate::ir::lowering::{lower_module, LoweringOptions};
+    use crate::lexer::Lexer;
+    use crate::opt::{OptLevel, PassManager};
+    use crate::parser::Parser;
+    use crate::x86::selection::*;
+
+    // Runs the selected instructions of a function without calls to other
+    // modules, and returns EAX
+    struct Machine<'a> {
+        instructions: &'a [Instruction],
+        registers: [u32; 8],
+        stack: Vec<u8>,
+        flags: (bool, bool, bool, bool), // zero, sign, carry, overflow
+    }
+
+    const STACK_SIZE: u32 = 0x1000;
+
+    impl Machine<'_> {
+        fn run(&mut self, symbol: &str) -> i32 {
+            let find = |target: &Instruction| self.instructions.iter().position(|instruction| instruction == target).unwrap();
+
+            let mut index = find(&Instruction::Symbol(symbol.to_string()));
+            self.registers[GPReg32::ESP as usize] = STACK_SIZE;
+            self.push(u32::MAX);
+
+            while index != u32::MAX as usize {
+                let mut next = index + 1;
+
+                match &self.instructions[index] {
+                    Instruction::Op(kind, operand1, operand2) => {
+                        if let Some(target) = self.execute(*kind, *operand1, *operand2) {
+                            next = target;
+                        }
+                    },
+
+                    Instruction::CallSymbol(symbol) => {
+                        self.push(next as u32);
+                        next = find(&Instruction::Symbol(symbol.clone()));
+                    },
+
+                    Instruction::Jmp(label) => next = find(&Instruction::Label(*label)),
+
+                    Instruction::Jcc(condition, label) => {
+                        if self.condition(*condition) {
+                            next = find(&Instruction::Label(*label));
+                        }
+                    },
+
+                    Instruction::JumpTable(index, labels) => {
+                        next = find(&Instruction::Label(labels[self.registers[*index as usize] as usize]));
+                    },
+
+                    Instruction::Label(_) | Instruction::Symbol(_) => {},
+                    instruction => panic!("cannot run {:?}", instruction),
+                }
+
+                index = next;
+            }
+
+            self.registers[GPReg32::EAX as usize] as i32
+        }
+
+        // Returns the next instruction for a return
+        fn execute(&mut self, kind: Kind, operand1: Option<Operand>, operand2: Option<Operand>) -> Option<usize> {
+            let dst = operand1.unwrap_or(Operand::Immediate(Immediate::U32(0)));
+            let src = operand2.map(|operand| self.read(operand)).unwrap_or(0);
+
+            let result = match kind {
+                Kind::Mov => src,
+                Kind::MovsxByte | Kind::MovsxWord => {
+                    let size = if kind == Kind::MovsxByte { 1 } else { 2 };
+
+                    let value = match operand2.unwrap() {
+                        Operand::Memory(memory) => self.read_memory(self.address(memory), size),
+                        operand => self.read(operand),
+                    };
+
+                    if size == 1 { value as i8 as u32 } else { value as i16 as u32 }
+                },
+
+                Kind::MovByte | Kind::MovWord => {
+                    let size = if kind == Kind::MovByte { 1 } else { 2 };
+                    let Operand::Memory(memory) = dst else { unreachable!() };
+                    self.write_memory(self.address(memory), size, src);
+                    return None;
+                },
+
+                Kind::Lea => {
+                    let Some(Operand::Memory(memory)) = operand2 else { unreachable!() };
+                    self.address(memory)
+                },
+
+                Kind::Add => self.arithmetic(self.read(dst).wrapping_add(src)),
+                Kind::Or => self.arithmetic(self.read(dst) | src),
+                Kind::And => self.arithmetic(self.read(dst) & src),
+                Kind::Sub => self.arithmetic(self.read(dst).wrapping_sub(src)),
+                Kind::Xor => self.arithmetic(self.read(dst) ^ src),
+                Kind::Imul => self.arithmetic(self.read(dst).wrapping_mul(src)),
+                Kind::Neg => self.arithmetic(self.read(dst).wrapping_neg()),
+                Kind::Inc => self.arithmetic(self.read(dst).wrapping_add(1)),
+                Kind::Shl => self.arithmetic(self.read(dst) << (src & 31)),
+                Kind::Sar => self.arithmetic(((self.read(dst) as i32) >> (src & 31)) as u32),
+                Kind::Shr => self.arithmetic(self.read(dst) >> (src & 31)),
+
+                Kind::Cmp => {
+                    let (lhs, rhs) = (self.read(dst), src);
+                    let (result, overflow) = (lhs as i32).overflowing_sub(rhs as i32);
+                    self.flags = (lhs == rhs, result < 0, lhs < rhs, overflow);
+                    return None;
+                },
+
+                Kind::Cdq => {
+                    self.registers[GPReg32::EDX as usize] = ((self.registers[GPReg32::EAX as usize] as i32) >> 31) as u32;
+                    return None;
+                },
+
+                Kind::Div | Kind::Idiv => {
+                    let dividend = (self.registers[GPReg32::EDX as usize] as u64) << 32 | self.registers[GPReg32::EAX as usize] as u64;
+                    let divisor = self.read(dst);
+
+                    let (quotient, remainder) = if kind == Kind::Div {
+                        ((dividend / divisor as u64) as u32, (dividend % divisor as u64) as u32)
+                    } else {
+                        let (dividend, divisor) = (dividend as i64, divisor as i32 as i64);
+                        ((dividend / divisor) as u32, (dividend % divisor) as u32)
+                    };
+
+                    self.registers[GPReg32::EAX as usize] = quotient;
+                    self.registers[GPReg32::EDX as usize] = remainder;
+                    return None;
+                },
+
+                Kind::Push => {
+                    let value = self.read(dst);
+                    self.push(value);
+                    return None;
+                },
+
+                Kind::Pop => self.pop(),
+
+                Kind::Ret => {
+                    let target = self.pop();
+                    self.registers[GPReg32::ESP as usize] += operand1.map_or(0, |operand| self.read(operand));
+                    return Some(target as usize);
+                },
+
+                kind => panic!("cannot run {:?}", kind),
+            };
+
+            self.write(dst, result);
+            None
+        }
+
+        fn arithmetic(&mut self, result: u32) -> u32 {
+            self.flags = (result == 0, (result as i32) < 0, false, false);
+            result
+        }
+
+        fn condition(&self, condition: Condition) -> bool {
+            let (zero, sign, carry, overflow) = self.flags;
+
+            match condition {
+                Condition::Overflow => overflow,
+                Condition::NoOverflow => !overflow,
+                Condition::Below => carry,
+                Condition::AboveOrEqual => !carry,
+                Condition::Equal => zero,
+                Condition::NotEqual => !zero,
+                Condition::BelowOrEqual => carry || zero,
+                Condition::Above => !carry && !zero,
+                Condition::Sign => sign,
+                Condition::NoSign => !sign,
+                Condition::Less => sign != overflow,
+                Condition::GreaterOrEqual => sign == overflow,
+                Condition::LessOrEqual => zero || sign != overflow,
+                Condition::Greater => !zero && sign == overflow,
+            }
+        }
+
+        fn read(&self, operand: Operand) -> u32 {
+            match operand {
+                Operand::Register(Register::GPR32(register)) => self.registers[register as usize],
+                Operand::Register(Register::GPR8(register)) => self.registers[register as usize] & 0xFF,
+                Operand::Immediate(Immediate::U8(value)) => value as i8 as u32,
+                Operand::Immediate(Immediate::U16(value)) => value as u32,
+                Operand::Immediate(Immediate::U32(value)) => value,
+                Operand::Memory(memory) => self.read_memory(self.address(memory), 4),
+                operand => panic!("cannot read {:?}", operand),
+            }
+        }
+
+        fn write(&mut self, operand: Operand, value: u32) {
+            match operand {
+                Operand::Register(Register::GPR32(register)) => self.registers[register as usize] = value,
+                Operand::Memory(memory) => self.write_memory(self.address(memory), 4, value),
+                operand => panic!("cannot write {:?}", operand),
+            }
+        }
+
+        fn address(&self, memory: Memory) -> u32 {
+            let register = |register: Register| self.read(Operand::Register(register));
+            let displacement = |immediate: Immediate| self.read(Operand::Immediate(immediate));
+            let scale = |scale: Scale| match scale { Scale::X2 => 2, Scale::X4 => 4, Scale::X8 => 8 };
+
+            match memory {
+                Memory::Register(base) => register(base),
+                Memory::Immediate(address) => displacement(address),
+                Memory::RegisterDisplacement(base, offset) => register(base).wrapping_add(displacement(offset)),
+                Memory::BaseIndex(base, index) => register(base).wrapping_add(register(index)),
+                Memory::IndexScale(index, factor, offset) => (register(index) * scale(factor)).wrapping_add(displacement(offset)),
+                Memory::BaseIndexScale(base, index, factor) => register(base).wrapping_add(register(index) * scale(factor)),
+
+                Memory::BaseIndexScaleDisplacement(base, index, factor, offset) => {
+                    register(base).wrapping_add(register(index) * scale(factor)).wrapping_add(displacement(offset))
+                },
+            }
+        }
+
+        fn read_memory(&self, address: u32, size: usize) -> u32 {
+            let bytes = &self.stack[address as usize..address as usize + size];
+            bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32)
+        }
+
+        fn write_memory(&mut self, address: u32, size: usize, value: u32) {
+            self.stack[address as usize..address as usize + size].copy_from_slice(&value.to_le_bytes()[..size]);
+        }
+
+        fn push(&mut self, value: u32) {
+            self.registers[GPReg32::ESP as usize] -= 4;
+            self.write_memory(self.registers[GPReg32::ESP as usize], 4, value);
+        }
+
+        fn pop(&mut self) -> u32 {
+            let value = self.read_memory(self.registers[GPReg32::ESP as usize], 4);
+            self.registers[GPReg32::ESP as usize] += 4;
+            value
+        }
+    }
+
+    fn run(source: &str, level: OptLevel, function: &str) -> i32 {
+        let ast = Parser::new(Lexer::new(source)).parse_module().unwrap();
+        let mut module = lower_module(&ast, &LoweringOptions::default()).unwrap();
+        PassManager::for_level(level).run(&mut module);
+
+        let codegen = select_module(&module, EntryPoint::None);
+
+        let mut machine = Machine {
+            instructions: codegen.instructions(),
+            registers: [0; 8],
+            stack: vec![0; STACK_SIZE as usize],
+            flags: (false, false, false, false),
+        };
+
+        machine.run(function)
+    }
+
+    #[test]
+    fn test_narrow_arithmetic_wraps() {
+        let source = concat!(
+            "fn add() -> i8 { return 127i8 + 1i8; }",
+            "fn sub() -> i8 { let a = -128i8; return a - 1i8; }",
+            "fn mul() -> i16 { return 300i16 * 300i16; }",
+            "fn shl() -> i8 { return 1i8 << 7i8; }",
+            "fn neg() -> i16 { let a = -32768i16; return -a; }",
+            "fn compare() -> i32 { return match 127i8 + 1i8 < 0i8 { 0 => 0, _ => 1 }; }",
+            "fn matches() -> i32 { return match 200u8 { 200u8 => 1, _ => 0 }; }",
+        );
+
+        let expected = [
+            ("add", -128), ("sub", 127), ("mul", 24464), ("shl", -128), ("neg", -32768), ("compare", 1), ("matches", 1),
+        ];
+
+        // -O2 folds them with `Type::truncate`
+        for (function, result) in expected {
+            assert_eq!(result, run(source, OptLevel::O0, function), "{} at -O0", function);
+            assert_eq!(result, run(source, OptLevel::O2, function), "{} at -O2", function);
+        }
+    }
+}
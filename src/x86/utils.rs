@@ -8,16 +8,17 @@ fn validate_operands(operand1: Operand, operand2: Operand) {
     match operand1 {
         Operand::Register(_) => match operand2 {
             Operand::Register(_) => {},
-            Operand::Memory(_) => panic!("invalid operands"),
+            _ => panic!("invalid operands"),
         },
         Operand::Memory(_) => match operand2 {
             Operand::Register(_) => {},
-            Operand::Memory(_) => panic!("invalid operands"),
+            _ => panic!("invalid operands"),
         },
+        Operand::Immediate(_) => panic!("invalid operands"),
     }
 }
 
-fn emit_immediate(immediate: Immediate, buffer: &mut Vec<u8>) {
+pub fn emit_immediate(immediate: Immediate, buffer: &mut Vec<u8>) {
     match immediate {
         Immediate::U8(imm) => buffer.extend_from_slice(
             &imm.to_le_bytes()
@@ -34,23 +35,31 @@ fn emit_immediate(immediate: Immediate, buffer: &mut Vec<u8>) {
 }
 
 fn emit_modrm_reg_reg(rm: Register, register: Register, buffer: &mut Vec<u8>) {
+    emit_modrm_reg(rm, modrm::register_to_reg(register), buffer);
+}
+
+fn emit_modrm_reg(rm: Register, reg: modrm::Reg, buffer: &mut Vec<u8>) {
     buffer.push(
         modrm::gen_modrm(
             modrm::Mod::Reg,
             modrm::register_to_rm(rm),
-            modrm::register_to_reg(register)
+            reg
         )
     );
 }
 
 fn emit_modrm_mem_reg(rm: Memory, register: Register, buffer: &mut Vec<u8>) {
+    emit_modrm_mem(rm, modrm::register_to_reg(register), buffer);
+}
+
+fn emit_modrm_mem(rm: Memory, register: modrm::Reg, buffer: &mut Vec<u8>) {
     match rm {
         Memory::Register(reg) => {
             buffer.push(
                 modrm::gen_modrm(
                     modrm::Mod::NoDisp,
                     modrm::register_to_rm(reg),
-                    modrm::register_to_reg(register)
+                    register
                 )
             );
         },
@@ -60,7 +69,7 @@ fn emit_modrm_mem_reg(rm: Memory, register: Register, buffer: &mut Vec<u8>) {
                 modrm::gen_modrm(
                     modrm::Mod::NoDisp,
                     modrm::Rm::SPEC_DISP32,
-                    modrm::register_to_reg(register)
+                    register
                 )
             );
             
@@ -72,7 +81,7 @@ fn emit_modrm_mem_reg(rm: Memory, register: Register, buffer: &mut Vec<u8>) {
                 modrm::gen_modrm(
                     modrm::Mod::Disp32,
                     modrm::register_to_rm(reg),
-                    modrm::register_to_reg(register)
+                    register
                 )
             );
 
@@ -84,7 +93,7 @@ fn emit_modrm_mem_reg(rm: Memory, register: Register, buffer: &mut Vec<u8>) {
                 modrm::gen_modrm(
                     modrm::Mod::NoDisp,
                     modrm::Rm::SPEC_SIB,
-                    modrm::register_to_reg(register)
+                    register
                 )
             );
 
@@ -97,12 +106,13 @@ fn emit_modrm_mem_reg(rm: Memory, register: Register, buffer: &mut Vec<u8>) {
             );
         },
 
-        Memory::IndexScale(index, scale) => {
+        // SIB without a base register takes a disp32 instead
+        Memory::IndexScale(index, scale, disp) => {
             buffer.push(
                 modrm::gen_modrm(
                     modrm::Mod::NoDisp,
                     modrm::Rm::SPEC_SIB,
-                    modrm::register_to_reg(register)
+                    register
                 )
             );
 
@@ -117,6 +127,8 @@ fn emit_modrm_mem_reg(rm: Memory, register: Register, buffer: &mut Vec<u8>) {
                     sib::Base::ModSpecific
                 )
             );
+
+            emit_immediate(disp, buffer);
         },
 
         Memory::BaseIndexScale(base, index, scale) => {
@@ -124,7 +136,7 @@ fn emit_modrm_mem_reg(rm: Memory, register: Register, buffer: &mut Vec<u8>) {
                 modrm::gen_modrm(
                     modrm::Mod::NoDisp,
                     modrm::Rm::SPEC_SIB,
-                    modrm::register_to_reg(register)
+                    register
                 )
             );
 
@@ -146,7 +158,7 @@ fn emit_modrm_mem_reg(rm: Memory, register: Register, buffer: &mut Vec<u8>) {
                 modrm::gen_modrm(
                     modrm::Mod::Disp32,
                     modrm::Rm::SPEC_SIB,
-                    modrm::register_to_reg(register)
+                    register
                 )
             );
 
@@ -188,6 +200,17 @@ pub fn emit_modrm_byte(operand1: Operand, operand2: Operand, buffer: &mut Vec<u8
             },
             buffer
         ),
+
+        Operand::Immediate(_) => unreachable!(),
+    }
+}
+
+// ModRM byte with an opcode extension (/digit) in the reg field
+pub fn emit_modrm_ext(operand: Operand, digit: u8, buffer: &mut Vec<u8>) {
+    match operand {
+        Operand::Register(register) => emit_modrm_reg(register, modrm::digit_to_reg(digit), buffer),
+        Operand::Memory(memory) => emit_modrm_mem(memory, modrm::digit_to_reg(digit), buffer),
+        Operand::Immediate(_) => panic!("invalid operands"),
     }
 }
 